@@ -1,24 +1,59 @@
 use crate::error::{AnvilError, AnvilResult};
-use crate::objects::ShellObject;
-use std::collections::HashMap;
+use crate::objects::{PathObject, ShellObject};
+use crate::utils::{PathUtils, SystemUtils};
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{Arc, PoisonError, RwLock};
 
+/// The handler behind a registered command. It receives the command's
+/// arguments, already split and unquoted and without the command name, and
+/// returns the command's result object; errors are reported like any other
+/// failed command.
 pub type CommandFn = Box<dyn Fn(&[String]) -> AnvilResult<ShellObject> + Send + Sync>;
 
+/// The future an async command handler returns
+pub type CommandFuture = Pin<Box<dyn Future<Output = AnvilResult<ShellObject>> + Send>>;
+
+/// The handler behind an async command, for IO- or network-bound work that
+/// shouldn't block the runtime. It takes its arguments by value so the
+/// returned future can outlive the call.
+pub type AsyncCommandFn = Box<dyn Fn(Vec<String>) -> CommandFuture + Send + Sync>;
+
+pub enum CommandHandler {
+    Sync(CommandFn),
+    Async(AsyncCommandFn),
+}
+
+/// Base directories file commands are confined to; empty means anywhere
+type AllowedDirs = Arc<RwLock<Vec<PathBuf>>>;
+
+/// Time limit for `http` requests
+#[cfg(feature = "http")]
+type HttpTimeout = Arc<RwLock<std::time::Duration>>;
+
 pub struct CommandRegistry {
     commands: HashMap<String, CommandInfo>,
+    allowed_dirs: AllowedDirs,
+    #[cfg(feature = "http")]
+    http_timeout: HttpTimeout,
 }
 
 pub struct CommandInfo {
     pub name: String,
     pub description: String,
     pub usage: String,
-    pub handler: CommandFn,
+    pub handler: CommandHandler,
 }
 
 impl CommandRegistry {
     pub fn new() -> Self {
         let mut registry = Self {
             commands: HashMap::new(),
+            allowed_dirs: AllowedDirs::default(),
+            #[cfg(feature = "http")]
+            http_timeout: Arc::new(RwLock::new(std::time::Duration::from_secs(30))),
         };
         
         registry.register_builtin_commands();
@@ -29,15 +64,45 @@ impl CommandRegistry {
         self.commands.insert(info.name.clone(), info);
     }
 
+    /// Confine the file commands to paths inside `dirs`. An empty list
+    /// lifts the restriction.
+    pub fn set_allowed_dirs(&self, dirs: Vec<PathBuf>) {
+        *self.allowed_dirs.write().unwrap_or_else(PoisonError::into_inner) = dirs;
+    }
+
+    /// Give up on `http` requests that take longer than `timeout`
+    #[cfg(feature = "http")]
+    pub fn set_http_timeout(&self, timeout: std::time::Duration) {
+        *self.http_timeout.write().unwrap_or_else(PoisonError::into_inner) = timeout;
+    }
+
     pub fn has_command(&self, name: &str) -> bool {
         self.commands.contains_key(name)
     }
 
+    pub fn get_command_info(&self, name: &str) -> Option<&CommandInfo> {
+        self.commands.get(name)
+    }
+
     pub fn execute_command(&self, name: &str, args: &[String]) -> AnvilResult<ShellObject> {
-        if let Some(cmd) = self.commands.get(name) {
-            (cmd.handler)(args)
-        } else {
-            Err(AnvilError::command(format!("Unknown command: {}", name)))
+        if name == "help" {
+            return self.help(args);
+        }
+
+        match self.commands.get(name).map(|cmd| &cmd.handler) {
+            Some(CommandHandler::Sync(handler)) => handler(args),
+            Some(CommandHandler::Async(_)) => {
+                Err(AnvilError::command(format!("{}: async command must be awaited", name)))
+            }
+            None => Err(AnvilError::command(format!("Unknown command: {}", name))),
+        }
+    }
+
+    /// Run a command of either kind, awaiting async handlers
+    pub async fn execute_command_async(&self, name: &str, args: &[String]) -> AnvilResult<ShellObject> {
+        match self.commands.get(name).map(|cmd| &cmd.handler) {
+            Some(CommandHandler::Async(handler)) => handler(args.to_vec()).await,
+            _ => self.execute_command(name, args),
         }
     }
 
@@ -45,205 +110,425 @@ impl CommandRegistry {
         self.commands.values().collect()
     }
 
+    fn help(&self, args: &[String]) -> AnvilResult<ShellObject> {
+        if let Some(name) = args.first() {
+            let info = self.get_command_info(name)
+                .ok_or_else(|| AnvilError::command(format!("help: no help for '{}'", name)))?;
+            return Ok(ShellObject::String(format!(
+                "{} - {}\n\nUsage: {}\n", info.name, info.description, info.usage
+            )));
+        }
+
+        let mut commands = self.list_commands();
+        commands.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let mut help_text = String::from("Available built-in commands:\n\n");
+        for info in commands {
+            help_text.push_str(&format!("  {:<10} {}\n", info.name, info.description));
+        }
+        help_text.push_str("\nUse 'help <command>' for specific usage information.\n");
+        Ok(ShellObject::String(help_text))
+    }
+
     fn register_builtin_commands(&mut self) {
         // File system operations
+        let allowed = Arc::clone(&self.allowed_dirs);
         self.register_command(CommandInfo {
             name: "cat".to_string(),
             description: "Display file contents".to_string(),
-            usage: "cat <file>".to_string(),
-            handler: Box::new(|args| {
-                if args.is_empty() {
-                    return Err(AnvilError::command("cat: missing file argument"));
+            usage: "cat <file>... (- or no file reads stdin)".to_string(),
+            handler: CommandHandler::Sync(Box::new(move |args| {
+                check_flags("cat", &split_flags(args).0, &[])?;
+                let stdin_only = ["-".to_string()];
+                let files = if args.is_empty() { &stdin_only[..] } else { args };
+
+                // Like coreutils, a file that can't be read is reported and
+                // skipped; it's only an error when nothing could be read
+                let mut content = String::new();
+                let mut first_error = None;
+                let mut any_read = false;
+                for file in files {
+                    let result = if file == "-" {
+                        read_input(std::io::stdin())
+                    } else {
+                        read_file(&allowed, file)
+                    };
+
+                    match result {
+                        Ok(text) => {
+                            content.push_str(&text);
+                            any_read = true;
+                        }
+                        Err(e) => {
+                            eprintln!("cat: {}", e);
+                            first_error.get_or_insert(e);
+                        }
+                    }
                 }
-                
-                let content = std::fs::read_to_string(&args[0])
-                    .map_err(|e| AnvilError::file_not_found(format!("cat: {}: {}", args[0], e)))?;
-                Ok(ShellObject::String(content))
-            }),
+
+                match first_error {
+                    Some(e) if !any_read => Err(e),
+                    _ => Ok(ShellObject::String(content)),
+                }
+            })),
         });
 
+        let allowed = Arc::clone(&self.allowed_dirs);
         self.register_command(CommandInfo {
             name: "head".to_string(),
             description: "Display first lines of a file".to_string(),
             usage: "head [-n lines] <file>".to_string(),
-            handler: Box::new(|args| {
-                if args.is_empty() {
-                    return Err(AnvilError::command("head: missing file argument"));
-                }
-
-                let mut lines = 10;
-                let mut file_idx = 0;
-
-                // Parse -n option
-                if args.len() >= 3 && args[0] == "-n" {
-                    lines = args[1].parse::<usize>()
-                        .map_err(|_| AnvilError::command("head: invalid line count"))?;
-                    file_idx = 2;
-                } else if args.len() >= 1 {
-                    file_idx = 0;
-                }
-
-                let content = std::fs::read_to_string(&args[file_idx])
-                    .map_err(|e| AnvilError::file_not_found(format!("head: {}: {}", args[file_idx], e)))?;
+            handler: CommandHandler::Sync(Box::new(move |args| {
+                let (lines, file) = parse_line_count("head", args)?;
+                let content = read_file(&allowed, file)?;
                 
                 let output: Vec<&str> = content.lines().take(lines).collect();
                 Ok(ShellObject::String(output.join("\n")))
-            }),
+            })),
         });
 
+        let allowed = Arc::clone(&self.allowed_dirs);
         self.register_command(CommandInfo {
             name: "tail".to_string(),
             description: "Display last lines of a file".to_string(),
             usage: "tail [-n lines] <file>".to_string(),
-            handler: Box::new(|args| {
-                if args.is_empty() {
-                    return Err(AnvilError::command("tail: missing file argument"));
-                }
-
-                let mut lines = 10;
-                let mut file_idx = 0;
-
-                // Parse -n option
-                if args.len() >= 3 && args[0] == "-n" {
-                    lines = args[1].parse::<usize>()
-                        .map_err(|_| AnvilError::command("tail: invalid line count"))?;
-                    file_idx = 2;
-                } else if args.len() >= 1 {
-                    file_idx = 0;
-                }
-
-                let content = std::fs::read_to_string(&args[file_idx])
-                    .map_err(|e| AnvilError::file_not_found(format!("tail: {}: {}", args[file_idx], e)))?;
+            handler: CommandHandler::Sync(Box::new(move |args| {
+                let (lines, file) = parse_line_count("tail", args)?;
+                let content = read_file(&allowed, file)?;
                 
                 let all_lines: Vec<&str> = content.lines().collect();
                 let start_idx = if all_lines.len() > lines { all_lines.len() - lines } else { 0 };
                 let output: Vec<&str> = all_lines[start_idx..].to_vec();
                 Ok(ShellObject::String(output.join("\n")))
-            }),
+            })),
         });
 
+        let allowed = Arc::clone(&self.allowed_dirs);
         self.register_command(CommandInfo {
             name: "wc".to_string(),
             description: "Count lines, words, and characters".to_string(),
-            usage: "wc <file>".to_string(),
-            handler: Box::new(|args| {
-                if args.is_empty() {
+            usage: "wc [-l] [-w] [-c] [-m] <file>...".to_string(),
+            handler: CommandHandler::Sync(Box::new(move |args| {
+                let (flags, files) = split_flags(args);
+                if files.is_empty() {
                     return Err(AnvilError::command("wc: missing file argument"));
                 }
-                
-                let content = std::fs::read_to_string(&args[0])
-                    .map_err(|e| AnvilError::file_not_found(format!("wc: {}: {}", args[0], e)))?;
-                
-                let lines = content.lines().count();
-                let words = content.split_whitespace().count();
-                let chars = content.chars().count();
-                
-                let mut result = HashMap::new();
-                result.insert("lines".to_string(), ShellObject::Integer(lines as i64));
-                result.insert("words".to_string(), ShellObject::Integer(words as i64));
-                result.insert("chars".to_string(), ShellObject::Integer(chars as i64));
-                result.insert("file".to_string(), ShellObject::String(args[0].clone()));
-                
-                Ok(ShellObject::Map(result))
-            }),
+
+                let mut selected = Vec::new();
+                for flag in flags {
+                    for ch in flag[1..].chars() {
+                        let count = match ch {
+                            'l' => "lines",
+                            'w' => "words",
+                            'c' => "bytes",
+                            'm' => "chars",
+                            _ => return Err(unsupported_flag("wc", flag)),
+                        };
+                        if !selected.contains(&count) {
+                            selected.push(count);
+                        }
+                    }
+                }
+                if selected.is_empty() {
+                    selected = vec!["lines", "words", "chars"];
+                }
+
+                let mut rows = Vec::new();
+                let mut totals = BTreeMap::new();
+                for file in &files {
+                    let content = read_file(&allowed, file)?;
+
+                    let mut result = BTreeMap::new();
+                    for &count in &selected {
+                        let value = match count {
+                            "lines" => content.lines().count(),
+                            "words" => content.split_whitespace().count(),
+                            "bytes" => content.len(),
+                            _ => content.chars().count(),
+                        } as i64;
+                        *totals.entry(count.to_string()).or_insert(0) += value;
+                        result.insert(count.to_string(), ShellObject::Integer(value));
+                    }
+                    result.insert("file".to_string(), ShellObject::String(file.to_string()));
+                    rows.push(ShellObject::Map(result));
+                }
+
+                if rows.len() == 1 {
+                    return Ok(rows.remove(0));
+                }
+
+                let mut total: BTreeMap<String, ShellObject> = totals.into_iter()
+                    .map(|(count, value)| (count, ShellObject::Integer(value)))
+                    .collect();
+                total.insert("file".to_string(), ShellObject::String("total".to_string()));
+                rows.push(ShellObject::Map(total));
+                Ok(ShellObject::Array(rows))
+            })),
         });
 
+        let allowed = Arc::clone(&self.allowed_dirs);
         self.register_command(CommandInfo {
             name: "find".to_string(),
             description: "Find files matching criteria".to_string(),
-            usage: "find <path> [-name pattern]".to_string(),
-            handler: Box::new(|args| {
-                if args.is_empty() {
-                    return Err(AnvilError::command("find: missing path argument"));
+            usage: "find [path] [-name pattern] [-type f|d] [-maxdepth n]".to_string(),
+            handler: CommandHandler::Sync(Box::new(move |args| {
+                let (root, options) = match args.split_first() {
+                    Some((path, rest)) if !path.starts_with('-') => (path.as_str(), rest),
+                    _ => (".", args),
+                };
+
+                let mut criteria = FindCriteria::default();
+                let mut iter = options.iter();
+                while let Some(option) = iter.next() {
+                    let mut value = || iter.next()
+                        .ok_or_else(|| AnvilError::command(format!("find: missing argument to '{}'", option)));
+                    match option.as_str() {
+                        "-name" => criteria.name = Some(value()?.clone()),
+                        "-type" => criteria.kind = match value()?.as_str() {
+                            "f" => Some(false),
+                            "d" => Some(true),
+                            other => return Err(unsupported_flag("find", &format!("-type {}", other))),
+                        },
+                        "-maxdepth" => criteria.max_depth = Some(value()?.parse()
+                            .map_err(|_| AnvilError::command("find: -maxdepth expects a non-negative number"))?),
+                        other => return Err(unsupported_flag("find", other)),
+                    }
                 }
 
-                let path = &args[0];
-                let mut pattern = None;
+                // Symlinks aren't followed, so everything found stays inside the root
+                let root = confined(&allowed, root)?;
+                let mut results = Vec::new();
+                find_paths(&root, 0, &criteria, &mut results)?;
+                Ok(ShellObject::Array(results))
+            })),
+        });
 
-                // Parse -name option
-                if args.len() >= 3 && args[1] == "-name" {
-                    pattern = Some(&args[2]);
+        let allowed = Arc::clone(&self.allowed_dirs);
+        self.register_command(CommandInfo {
+            name: "cp".to_string(),
+            description: "Copy files and directories".to_string(),
+            usage: "cp [-r] <source> <dest>".to_string(),
+            handler: CommandHandler::Sync(Box::new(move |args| {
+                let (flags, paths) = split_flags(args);
+                check_flags("cp", &flags, &["-r", "-R"])?;
+                if paths.len() != 2 {
+                    return Err(AnvilError::command("cp: expected source and destination"));
                 }
+                let recursive = !flags.is_empty();
+                let src = checked_path(&allowed, "cp", paths[0])?;
+                let dst = checked_path(&allowed, "cp", paths[1])?;
 
-                let mut results = Vec::new();
-                if let Ok(entries) = std::fs::read_dir(path) {
-                    for entry in entries.flatten() {
-                        let file_name = entry.file_name().to_string_lossy().to_string();
-                        
-                        if let Some(pat) = pattern {
-                            if file_name.contains(pat) {
-                                results.push(ShellObject::String(
-                                    entry.path().to_string_lossy().to_string()
-                                ));
-                            }
-                        } else {
-                            results.push(ShellObject::String(
-                                entry.path().to_string_lossy().to_string()
-                            ));
+                if !src.exists() {
+                    return Err(AnvilError::file_not_found(src.to_string_lossy().to_string()));
+                }
+                let dst = destination_for(&src, &dst);
+
+                if src.is_dir() {
+                    if !recursive {
+                        return Err(AnvilError::command(format!("cp: -r not specified; omitting directory '{}'", src.display())));
+                    }
+                    copy_dir_recursive(&src, &dst)?;
+                } else {
+                    std::fs::copy(&src, &dst)?;
+                }
+
+                Ok(ShellObject::String(format!("Copied {} to {}", src.display(), dst.display())))
+            })),
+        });
+
+        let allowed = Arc::clone(&self.allowed_dirs);
+        self.register_command(CommandInfo {
+            name: "mv".to_string(),
+            description: "Move or rename files and directories".to_string(),
+            usage: "mv <source> <dest>".to_string(),
+            handler: CommandHandler::Sync(Box::new(move |args| {
+                let (flags, paths) = split_flags(args);
+                check_flags("mv", &flags, &[])?;
+                if paths.len() != 2 {
+                    return Err(AnvilError::command("mv: expected source and destination"));
+                }
+                let src = checked_path(&allowed, "mv", paths[0])?;
+                let dst = checked_path(&allowed, "mv", paths[1])?;
+
+                if !src.exists() {
+                    return Err(AnvilError::file_not_found(src.to_string_lossy().to_string()));
+                }
+                let dst = destination_for(&src, &dst);
+                std::fs::rename(&src, &dst)?;
+
+                Ok(ShellObject::String(format!("Moved {} to {}", src.display(), dst.display())))
+            })),
+        });
+
+        let allowed = Arc::clone(&self.allowed_dirs);
+        self.register_command(CommandInfo {
+            name: "rm".to_string(),
+            description: "Remove files and directories".to_string(),
+            usage: "rm [-rf] <path>...".to_string(),
+            handler: CommandHandler::Sync(Box::new(move |args| {
+                let (flags, paths) = split_flags(args);
+                if let Some(flag) = flags.iter().find(|f| !f[1..].chars().all(|c| matches!(c, 'r' | 'R' | 'f'))) {
+                    return Err(unsupported_flag("rm", flag));
+                }
+                let recursive = flags.iter().any(|f| f.contains(['r', 'R']));
+                let force = flags.iter().any(|f| f.contains('f'));
+                if paths.is_empty() && !force {
+                    return Err(AnvilError::command("rm: missing path argument"));
+                }
+
+                let mut removed = Vec::new();
+                for path in paths {
+                    let path = checked_path(&allowed, "rm", path)?;
+                    if !path.exists() {
+                        // -f skips missing files without complaint
+                        if force {
+                            continue;
                         }
+                        return Err(AnvilError::file_not_found(path.to_string_lossy().to_string()));
                     }
+
+                    if path.is_dir() {
+                        if !recursive {
+                            return Err(AnvilError::command(format!("rm: cannot remove '{}': Is a directory", path.display())));
+                        }
+                        std::fs::remove_dir_all(&path)?;
+                    } else {
+                        std::fs::remove_file(&path)?;
+                    }
+                    removed.push(path.to_string_lossy().to_string());
                 }
 
-                Ok(ShellObject::Array(results))
-            }),
+                Ok(ShellObject::String(format!("Removed {}", removed.join(", "))))
+            })),
+        });
+
+        let allowed = Arc::clone(&self.allowed_dirs);
+        self.register_command(CommandInfo {
+            name: "mkdir".to_string(),
+            description: "Create a directory".to_string(),
+            usage: "mkdir [-p] <dir>".to_string(),
+            handler: CommandHandler::Sync(Box::new(move |args| {
+                let (flags, paths) = split_flags(args);
+                check_flags("mkdir", &flags, &["-p"])?;
+                if paths.len() != 1 {
+                    return Err(AnvilError::command("mkdir: expected a single directory argument"));
+                }
+                let parents = !flags.is_empty();
+                let path = confined(&allowed, paths[0])?;
+
+                if parents {
+                    std::fs::create_dir_all(&path)?;
+                } else if path.exists() {
+                    return Err(AnvilError::command(format!("mkdir: cannot create directory '{}': File exists", path.display())));
+                } else {
+                    std::fs::create_dir(&path)?;
+                }
+
+                Ok(ShellObject::Path(PathObject { path }))
+            })),
+        });
+
+        let allowed = Arc::clone(&self.allowed_dirs);
+        self.register_command(CommandInfo {
+            name: "touch".to_string(),
+            description: "Create a file or update its modification time".to_string(),
+            usage: "touch <file>".to_string(),
+            handler: CommandHandler::Sync(Box::new(move |args| {
+                check_flags("touch", &split_flags(args).0, &[])?;
+                if args.len() != 1 {
+                    return Err(AnvilError::command("touch: expected a single file argument"));
+                }
+                let path = confined(&allowed, &args[0])?;
+
+                let file = std::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&path)?;
+                file.set_modified(std::time::SystemTime::now())?;
+
+                Ok(ShellObject::Path(PathObject { path }))
+            })),
         });
 
         // Text processing
+        let allowed = Arc::clone(&self.allowed_dirs);
         self.register_command(CommandInfo {
             name: "grep".to_string(),
             description: "Search for patterns in text".to_string(),
-            usage: "grep <pattern> <file>".to_string(),
-            handler: Box::new(|args| {
-                if args.len() < 2 {
-                    return Err(AnvilError::command("grep: missing pattern or file argument"));
+            usage: "grep [-EFinv] <regex> [file]...".to_string(),
+            handler: CommandHandler::Sync(Box::new(move |args| {
+                let (flags, rest) = split_flags(args);
+                let mut options = GrepOptions::default();
+                for flag in &flags {
+                    for ch in flag[1..].chars() {
+                        match ch {
+                            'E' => options.fixed = false,
+                            'F' => options.fixed = true,
+                            'i' => options.ignore_case = true,
+                            'n' => options.line_numbers = true,
+                            'v' => options.invert = true,
+                            _ => return Err(unsupported_flag("grep", flag)),
+                        }
+                    }
                 }
 
-                let pattern = &args[0];
-                let file = &args[1];
-                
-                let content = std::fs::read_to_string(file)
-                    .map_err(|e| AnvilError::file_not_found(format!("grep: {}: {}", file, e)))?;
-                
-                let matching_lines: Vec<ShellObject> = content
-                    .lines()
-                    .filter(|line| line.contains(pattern))
-                    .map(|line| ShellObject::String(line.to_string()))
-                    .collect();
+                let Some((pattern, files)) = rest.split_first() else {
+                    return Err(AnvilError::command("grep: missing pattern"));
+                };
+                let pattern = if options.fixed { regex::escape(pattern) } else { pattern.to_string() };
+                let regex = regex::RegexBuilder::new(&pattern)
+                    .case_insensitive(options.ignore_case)
+                    .build()
+                    .map_err(|e| AnvilError::parse(format!("grep: invalid pattern: {}", e)))?;
+
+                if files.is_empty() {
+                    let content = read_input(std::io::stdin())?;
+                    return Ok(ShellObject::Array(grep_lines(&regex, &options, &content, None)));
+                }
 
-                Ok(ShellObject::Array(matching_lines))
-            }),
+                // Like grep, lines are prefixed with their file when searching several
+                let mut matches = Vec::new();
+                for file in files {
+                    let content = read_file(&allowed, file)?;
+                    let prefix = (files.len() > 1).then_some(*file);
+                    matches.extend(grep_lines(&regex, &options, &content, prefix));
+                }
+                Ok(ShellObject::Array(matches))
+            })),
         });
 
+        let allowed = Arc::clone(&self.allowed_dirs);
         self.register_command(CommandInfo {
             name: "sort".to_string(),
             description: "Sort lines of text".to_string(),
             usage: "sort <file>".to_string(),
-            handler: Box::new(|args| {
+            handler: CommandHandler::Sync(Box::new(move |args| {
+                check_flags("sort", &split_flags(args).0, &[])?;
                 if args.is_empty() {
                     return Err(AnvilError::command("sort: missing file argument"));
                 }
                 
-                let content = std::fs::read_to_string(&args[0])
-                    .map_err(|e| AnvilError::file_not_found(format!("sort: {}: {}", args[0], e)))?;
+                let content = read_file(&allowed, &args[0])?;
                 
                 let mut lines: Vec<&str> = content.lines().collect();
                 lines.sort();
                 
                 Ok(ShellObject::String(lines.join("\n")))
-            }),
+            })),
         });
 
+        let allowed = Arc::clone(&self.allowed_dirs);
         self.register_command(CommandInfo {
             name: "uniq".to_string(),
             description: "Remove duplicate lines".to_string(),
             usage: "uniq <file>".to_string(),
-            handler: Box::new(|args| {
+            handler: CommandHandler::Sync(Box::new(move |args| {
+                check_flags("uniq", &split_flags(args).0, &[])?;
                 if args.is_empty() {
                     return Err(AnvilError::command("uniq: missing file argument"));
                 }
                 
-                let content = std::fs::read_to_string(&args[0])
-                    .map_err(|e| AnvilError::file_not_found(format!("uniq: {}: {}", args[0], e)))?;
+                let content = read_file(&allowed, &args[0])?;
                 
                 let mut unique_lines = Vec::new();
                 let mut last_line = "";
@@ -256,48 +541,171 @@ impl CommandRegistry {
                 }
                 
                 Ok(ShellObject::String(unique_lines.join("\n")))
-            }),
+            })),
         });
 
-        // System information
+        let allowed = Arc::clone(&self.allowed_dirs);
         self.register_command(CommandInfo {
-            name: "ps".to_string(),
-            description: "List running processes".to_string(),
-            usage: "ps".to_string(),
-            handler: Box::new(|_args| {
-                // This is a simplified implementation
-                // In a real implementation, you'd use system APIs to get process info
-                let mut processes = Vec::new();
-                
-                // Add a dummy process for demonstration
-                let mut proc = HashMap::new();
-                proc.insert("pid".to_string(), ShellObject::Integer(std::process::id() as i64));
-                proc.insert("name".to_string(), ShellObject::String("anvil".to_string()));
-                proc.insert("status".to_string(), ShellObject::String("running".to_string()));
-                processes.push(ShellObject::Map(proc));
-                
-                Ok(ShellObject::Array(processes))
-            }),
+            name: "cut".to_string(),
+            description: "Select fields from each line".to_string(),
+            usage: "cut [-d delim] -f <fields> [file]".to_string(),
+            handler: CommandHandler::Sync(Box::new(move |args| {
+                let mut delimiter = "\t".to_string();
+                let mut fields = None;
+                let mut file = None;
+
+                let mut iter = args.iter();
+                while let Some(arg) = iter.next() {
+                    if let Some(rest) = arg.strip_prefix("-d") {
+                        delimiter = if rest.is_empty() { iter.next().cloned().unwrap_or_default() } else { rest.to_string() };
+                    } else if let Some(rest) = arg.strip_prefix("-f") {
+                        let list = if rest.is_empty() { iter.next().map(String::as_str).unwrap_or_default() } else { rest };
+                        fields = Some(parse_field_list(list)?);
+                    } else if arg.starts_with('-') && arg.len() > 1 {
+                        return Err(unsupported_flag("cut", arg));
+                    } else {
+                        file = Some(arg);
+                    }
+                }
+
+                if delimiter.chars().count() != 1 {
+                    return Err(AnvilError::command("cut: the delimiter must be a single character"));
+                }
+                let Some(fields) = fields else {
+                    return Err(AnvilError::command("cut: missing field list (-f)"));
+                };
+                let content = match file {
+                    Some(file) => read_file(&allowed, file)?,
+                    None => read_input(std::io::stdin())?,
+                };
+
+                // Like cut, lines without the delimiter are passed through whole
+                let lines = content.lines().map(|line| {
+                    if !line.contains(delimiter.as_str()) {
+                        return ShellObject::String(line.to_string());
+                    }
+                    let selected: Vec<&str> = line.split(delimiter.as_str())
+                        .enumerate()
+                        .filter(|(i, _)| fields.iter().any(|&(start, end)| *i + 1 >= start && *i < end))
+                        .map(|(_, field)| field)
+                        .collect();
+                    ShellObject::String(selected.join(&delimiter))
+                }).collect();
+
+                Ok(ShellObject::Array(lines))
+            })),
         });
 
+        let allowed = Arc::clone(&self.allowed_dirs);
         self.register_command(CommandInfo {
-            name: "df".to_string(),
-            description: "Display filesystem disk usage".to_string(),
-            usage: "df".to_string(),
-            handler: Box::new(|_args| {
-                // Simplified implementation
-                let mut filesystems = Vec::new();
-                
-                if let Ok(metadata) = std::fs::metadata(".") {
-                    let mut fs = HashMap::new();
-                    fs.insert("filesystem".to_string(), ShellObject::String("/".to_string()));
-                    fs.insert("type".to_string(), ShellObject::String("ext4".to_string()));
-                    fs.insert("available".to_string(), ShellObject::Integer(metadata.len() as i64));
-                    filesystems.push(ShellObject::Map(fs));
+            name: "sed".to_string(),
+            description: "Substitute text matching a regex".to_string(),
+            usage: "sed 's/pattern/replacement/[g]' [file]".to_string(),
+            handler: CommandHandler::Sync(Box::new(move |args| {
+                check_flags("sed", &split_flags(args).0, &[])?;
+                let Some(expr) = args.first() else {
+                    return Err(AnvilError::command("sed: missing substitution expression"));
+                };
+                let (regex, replacement, global) = parse_substitution(expr)?;
+                let content = match args.get(1) {
+                    Some(file) => read_file(&allowed, file)?,
+                    None => read_input(std::io::stdin())?,
+                };
+
+                let output: String = content.split_inclusive('\n').map(|line| {
+                    let (text, ending) = match line.strip_suffix('\n') {
+                        Some(text) => (text, "\n"),
+                        None => (line, ""),
+                    };
+                    let replaced = if global {
+                        regex.replace_all(text, replacement.as_str())
+                    } else {
+                        regex.replace(text, replacement.as_str())
+                    };
+                    format!("{}{}", replaced, ending)
+                }).collect();
+
+                Ok(ShellObject::String(output))
+            })),
+        });
+
+        let allowed = Arc::clone(&self.allowed_dirs);
+        self.register_command(CommandInfo {
+            name: "tee".to_string(),
+            description: "Copy input to a file and pass it through".to_string(),
+            usage: "tee [-a] <file>".to_string(),
+            handler: CommandHandler::Sync(Box::new(move |args| tee(&allowed, args, std::io::stdin()))),
+        });
+
+        // System information
+
+        self.register_command(CommandInfo {
+            name: "date".to_string(),
+            description: "Print the current date and time".to_string(),
+            usage: "date [-u] [+FORMAT]".to_string(),
+            handler: CommandHandler::Sync(Box::new(|args| {
+                let (flags, rest) = split_flags(args);
+                let utc = flags.contains(&"-u");
+                check_flags("date", &flags, &["-u"])?;
+
+                let now = if utc {
+                    chrono::Utc::now().fixed_offset()
+                } else {
+                    chrono::Local::now().fixed_offset()
+                };
+
+                match rest.iter().find_map(|a| a.strip_prefix('+')) {
+                    Some(format) => {
+                        let items: Vec<_> = chrono::format::StrftimeItems::new(format).collect();
+                        if items.contains(&chrono::format::Item::Error) {
+                            return Err(AnvilError::command(format!("date: invalid format '{}'", format)));
+                        }
+                        Ok(ShellObject::String(now.format_with_items(items.into_iter()).to_string()))
+                    }
+                    None => Ok(ShellObject::String(
+                        now.to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+                    )),
                 }
-                
-                Ok(ShellObject::Array(filesystems))
-            }),
+            })),
+        });
+
+        self.register_command(CommandInfo {
+            name: "whoami".to_string(),
+            description: "Print the current user name".to_string(),
+            usage: "whoami".to_string(),
+            handler: CommandHandler::Sync(Box::new(|args| {
+                check_flags("whoami", &split_flags(args).0, &[])?;
+                Ok(ShellObject::String(SystemUtils::username()))
+            })),
+        });
+
+        self.register_command(CommandInfo {
+            name: "hostname".to_string(),
+            description: "Print the system host name".to_string(),
+            usage: "hostname".to_string(),
+            handler: CommandHandler::Sync(Box::new(|args| {
+                check_flags("hostname", &split_flags(args).0, &[])?;
+                Ok(ShellObject::String(SystemUtils::hostname()))
+            })),
+        });
+
+        self.register_command(CommandInfo {
+            name: "uptime".to_string(),
+            description: "Show how long the system has been running".to_string(),
+            usage: "uptime".to_string(),
+            handler: CommandHandler::Sync(Box::new(|args| {
+                check_flags("uptime", &split_flags(args).0, &[])?;
+                let uptime = SystemUtils::uptime()
+                    .ok_or_else(|| AnvilError::unsupported("uptime: not available on this platform"))?;
+
+                let total = uptime.as_secs();
+                let mut info = BTreeMap::new();
+                info.insert("days".to_string(), ShellObject::Integer((total / 86400) as i64));
+                info.insert("hours".to_string(), ShellObject::Integer((total % 86400 / 3600) as i64));
+                info.insert("minutes".to_string(), ShellObject::Integer((total % 3600 / 60) as i64));
+                info.insert("seconds".to_string(), ShellObject::Integer(total as i64));
+                Ok(ShellObject::Map(info))
+            })),
         });
 
         // Network utilities (basic)
@@ -305,17 +713,19 @@ impl CommandRegistry {
             name: "ping".to_string(),
             description: "Ping a network host".to_string(),
             usage: "ping <host>".to_string(),
-            handler: Box::new(|args| {
+            handler: CommandHandler::Async(Box::new(|args| Box::pin(async move {
+                check_flags("ping", &split_flags(&args).0, &[])?;
                 if args.is_empty() {
                     return Err(AnvilError::command("ping: missing host argument"));
                 }
                 
                 // Use system ping command
-                let output = std::process::Command::new("ping")
+                let output = tokio::process::Command::new("ping")
                     .arg("-c")
                     .arg("4")
                     .arg(&args[0])
                     .output()
+                    .await
                     .map_err(|e| AnvilError::command(format!("ping: {}", e)))?;
                 
                 if output.status.success() {
@@ -323,41 +733,409 @@ impl CommandRegistry {
                 } else {
                     Ok(ShellObject::String(String::from_utf8_lossy(&output.stderr).to_string()))
                 }
-            }),
+            }))),
         });
 
-        // Help command
+        #[cfg(feature = "http")]
+        {
+            let timeout = Arc::clone(&self.http_timeout);
+            self.register_command(CommandInfo {
+                name: "http".to_string(),
+                description: "Make an HTTP request".to_string(),
+                usage: "http get <url> | http post [-d body] <url>".to_string(),
+                handler: CommandHandler::Async(Box::new(move |args| {
+                    let timeout = *timeout.read().unwrap_or_else(PoisonError::into_inner);
+                    Box::pin(http_request(args, timeout))
+                })),
+            });
+        }
+
+        // Help needs to read the registry itself, so execute_command answers
+        // it directly; this entry makes it show up alongside the others
         self.register_command(CommandInfo {
             name: "help".to_string(),
             description: "Show help for built-in commands".to_string(),
             usage: "help [command]".to_string(),
-            handler: Box::new(move |args| {
-                if args.is_empty() {
-                    // List all commands
-                    let mut help_text = String::from("Available built-in commands:\n\n");
-                    
-                    let command_names = [
-                        "cat", "head", "tail", "wc", "find", "grep", "sort", "uniq",
-                        "ps", "df", "ping", "help"
-                    ];
-                    
-                    for cmd in &command_names {
-                        help_text.push_str(&format!("  {}\n", cmd));
-                    }
-                    
-                    help_text.push_str("\nUse 'help <command>' for specific usage information.\n");
-                    Ok(ShellObject::String(help_text))
-                } else {
-                    // Show help for specific command
-                    let cmd_name = &args[0];
-                    // In a real implementation, we'd look up the command info
-                    Ok(ShellObject::String(format!("Help for command: {}", cmd_name)))
-                }
-            }),
+            handler: CommandHandler::Sync(Box::new(|_args| {
+                Err(AnvilError::command("help: must be run through the command registry"))
+            })),
         });
     }
 }
 
+/// Resolve a path argument, refusing paths outside the allowed directories
+fn confined<P: AsRef<Path>>(allowed: &AllowedDirs, path: P) -> AnvilResult<PathBuf> {
+    let path = path.as_ref();
+    let allowed = allowed.read().unwrap_or_else(PoisonError::into_inner);
+    PathUtils::check_allowed(path, &allowed)?;
+    Ok(path.to_path_buf())
+}
+
+/// Read a file argument, naming the file and reason on failure
+fn read_file<P: AsRef<Path>>(allowed: &AllowedDirs, path: P) -> AnvilResult<String> {
+    let path = confined(allowed, path)?;
+    std::fs::read_to_string(&path)
+        .map_err(|e| AnvilError::io_at(path.display().to_string(), e))
+}
+
+/// An `http` invocation: the method, the body to post if any, and the URL
+#[cfg(feature = "http")]
+#[derive(Debug, PartialEq)]
+struct HttpArgs {
+    post: bool,
+    body: Option<String>,
+    url: String,
+}
+
+/// Parse `http get <url>` or `http post [-d body] <url>`
+#[cfg(feature = "http")]
+fn parse_http_args(args: &[String]) -> AnvilResult<HttpArgs> {
+    let usage = || AnvilError::command("usage: http get <url> | http post [-d body] <url>");
+
+    let (method, rest) = args.split_first().ok_or_else(usage)?;
+    let post = match method.as_str() {
+        "get" => false,
+        "post" => true,
+        _ => return Err(usage()),
+    };
+    let mut body = None;
+    let mut url = None;
+    let mut iter = rest.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "-d" if post => body = Some(iter.next().ok_or_else(usage)?.clone()),
+            _ if url.is_none() => url = Some(arg.clone()),
+            _ => return Err(usage()),
+        }
+    }
+    let url = url.ok_or_else(usage)?;
+    Ok(HttpArgs { post, body, url })
+}
+
+/// Send a GET or POST request, returning the status, headers and body. A
+/// header sent once maps to its value and a repeated one to an array of its
+/// values. JSON bodies are parsed into objects; anything else is returned
+/// as text.
+#[cfg(feature = "http")]
+async fn http_request(args: Vec<String>, timeout: std::time::Duration) -> AnvilResult<ShellObject> {
+    let network = |e: reqwest::Error| {
+        if e.is_timeout() {
+            AnvilError::timeout("http request", timeout.as_millis() as u64)
+        } else {
+            AnvilError::command(format!("http: {}", e))
+        }
+    };
+
+    let HttpArgs { post, body, url } = parse_http_args(&args)?;
+    let client = reqwest::Client::builder()
+        .timeout(timeout)
+        .build()
+        .map_err(network)?;
+    let request = if post {
+        client.post(&url).body(body.unwrap_or_default())
+    } else {
+        client.get(&url)
+    };
+    let response = request.send().await.map_err(network)?;
+
+    let status = response.status().as_u16();
+    let mut headers = BTreeMap::new();
+    for name in response.headers().keys() {
+        let mut values: Vec<ShellObject> = response.headers().get_all(name).iter()
+            .map(|value| ShellObject::String(String::from_utf8_lossy(value.as_bytes()).into_owned()))
+            .collect();
+        let value = if values.len() == 1 { values.remove(0) } else { ShellObject::Array(values) };
+        headers.insert(name.as_str().to_string(), value);
+    }
+    let is_json = response.headers().get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.contains("json"));
+    let text = response.text().await.map_err(network)?;
+
+    let body = match serde_json::from_str(&text) {
+        Ok(value) if is_json => ShellObject::from_json(&value),
+        _ => ShellObject::String(text),
+    };
+
+    let mut result = BTreeMap::new();
+    result.insert("status".to_string(), ShellObject::Integer(status as i64));
+    result.insert("headers".to_string(), ShellObject::Map(headers));
+    result.insert("body".to_string(), body);
+    Ok(ShellObject::Map(result))
+}
+
+/// Parse a `cut` field list like `1,3-5,7-` into inclusive 1-based ranges,
+/// with `usize::MAX` for an open end
+fn parse_field_list(list: &str) -> AnvilResult<Vec<(usize, usize)>> {
+    let invalid = || AnvilError::command(format!("cut: invalid field list '{}'", list));
+    let number = |text: &str| text.parse::<usize>().ok().filter(|&n| n > 0).ok_or_else(invalid);
+
+    list.split(',')
+        .map(|part| match part.split_once('-') {
+            Some(("", "")) => Err(invalid()),
+            Some((start, end)) => {
+                let start = if start.is_empty() { 1 } else { number(start)? };
+                let end = if end.is_empty() { usize::MAX } else { number(end)? };
+                if start > end {
+                    return Err(invalid());
+                }
+                Ok((start, end))
+            }
+            None => number(part).map(|n| (n, n)),
+        })
+        .collect()
+}
+
+/// Parse a sed `s/pattern/replacement/flags` expression into the regex, a
+/// replacement in `regex` syntax, and whether to replace every match. Any
+/// character may stand in for `/`, and `&` and `\1` refer to the match and
+/// its groups as in sed.
+fn parse_substitution(expr: &str) -> AnvilResult<(regex::Regex, String, bool)> {
+    let invalid = |reason: &str| AnvilError::parse(format!("sed: invalid expression '{}': {}", expr, reason));
+
+    let mut chars = expr.chars();
+    if chars.next() != Some('s') {
+        return Err(invalid("only s/// substitutions are supported"));
+    }
+    let delimiter = chars.next().filter(|c| !c.is_alphanumeric() && *c != '\\')
+        .ok_or_else(|| invalid("missing delimiter"))?;
+
+    // Split into pattern, replacement and flags on unescaped delimiters
+    let mut parts = vec![String::new()];
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\\' => match chars.next() {
+                Some(next) if next == delimiter => parts.last_mut().unwrap().push(next),
+                Some(next) => {
+                    let part = parts.last_mut().unwrap();
+                    part.push('\\');
+                    part.push(next);
+                }
+                None => return Err(invalid("trailing backslash")),
+            },
+            c if c == delimiter && parts.len() < 3 => parts.push(String::new()),
+            c => parts.last_mut().unwrap().push(c),
+        }
+    }
+    let [pattern, replacement, flags] = <[String; 3]>::try_from(parts)
+        .map_err(|_| invalid("unterminated s command"))?;
+
+    let global = match flags.as_str() {
+        "" => false,
+        "g" => true,
+        _ => return Err(invalid(&format!("unknown flags '{}'", flags))),
+    };
+    let regex = regex::Regex::new(&pattern).map_err(|e| invalid(&e.to_string()))?;
+
+    // Translate sed's `&` and `\N` into regex's `${0}` and `${N}`
+    let mut translated = String::new();
+    let mut rep = replacement.chars().peekable();
+    while let Some(ch) = rep.next() {
+        match ch {
+            '&' => translated.push_str("${0}"),
+            '$' => translated.push_str("$$"),
+            '\\' => match rep.next() {
+                Some(d) if d.is_ascii_digit() => translated.push_str(&format!("${{{}}}", d)),
+                Some('n') => translated.push('\n'),
+                Some('t') => translated.push('\t'),
+                Some(other) => translated.push(other),
+                None => translated.push('\\'),
+            },
+            c => translated.push(c),
+        }
+    }
+
+    Ok((regex, translated, global))
+}
+
+/// Write all of `input` to the file named in `args`, appending with `-a`,
+/// and return it unchanged
+fn tee<R: std::io::Read>(allowed: &AllowedDirs, args: &[String], input: R) -> AnvilResult<ShellObject> {
+    let (flags, paths) = split_flags(args);
+    check_flags("tee", &flags, &["-a"])?;
+    if paths.len() != 1 {
+        return Err(AnvilError::command("tee: expected a single file argument"));
+    }
+    let append = !flags.is_empty();
+    let path = confined(allowed, paths[0])?;
+
+    let content = read_input(input)?;
+    std::fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(append)
+        .truncate(!append)
+        .open(&path)
+        .and_then(|mut file| std::io::Write::write_all(&mut file, content.as_bytes()))
+        .map_err(|e| AnvilError::io_at(path.display().to_string(), e))?;
+
+    Ok(ShellObject::String(content))
+}
+
+/// Read all of a command's piped input, failing the command rather than the
+/// shell if it can't be read
+fn read_input<R: std::io::Read>(input: R) -> AnvilResult<String> {
+    std::io::read_to_string(input).map_err(|e| AnvilError::io_at("<stdin>", e))
+}
+
+/// The error for a flag a command doesn't implement. The shell hands such
+/// commands to the program of the same name on PATH when there is one.
+fn unsupported_flag(cmd: &str, flag: &str) -> AnvilError {
+    AnvilError::unsupported(format!("{}: option '{}'", cmd, flag))
+}
+
+/// Refuse any flag outside `supported`
+fn check_flags(cmd: &str, flags: &[&str], supported: &[&str]) -> AnvilResult<()> {
+    match flags.iter().find(|flag| !supported.contains(flag)) {
+        Some(flag) => Err(unsupported_flag(cmd, flag)),
+        None => Ok(()),
+    }
+}
+
+/// Parse `head`/`tail` arguments: a line count given as `-n N`, `-nN` or
+/// `-N`, and a single file
+fn parse_line_count<'a>(cmd: &str, args: &'a [String]) -> AnvilResult<(usize, &'a str)> {
+    let invalid = || AnvilError::command(format!("{}: invalid line count", cmd));
+    let mut lines = 10;
+    let mut files = Vec::new();
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if arg == "-n" {
+            lines = iter.next().ok_or_else(invalid)?.parse().map_err(|_| invalid())?;
+        } else if let Some(count) = arg.strip_prefix("-n") {
+            lines = count.parse().map_err(|_| invalid())?;
+        } else if let Some(count) = arg.strip_prefix('-').filter(|c| !c.is_empty()) {
+            if !count.chars().all(|c| c.is_ascii_digit()) {
+                return Err(unsupported_flag(cmd, arg));
+            }
+            lines = count.parse().map_err(|_| invalid())?;
+        } else {
+            files.push(arg.as_str());
+        }
+    }
+
+    match files[..] {
+        [file] => Ok((lines, file)),
+        [] => Err(AnvilError::command(format!("{}: missing file argument", cmd))),
+        // Several files get headers, which isn't implemented here
+        _ => Err(AnvilError::unsupported(format!("{}: multiple files", cmd))),
+    }
+}
+
+/// What `find` looks for
+#[derive(Default)]
+struct FindCriteria {
+    /// Glob the file name must match
+    name: Option<String>,
+    /// Only directories (true) or only other files (false)
+    kind: Option<bool>,
+    max_depth: Option<usize>,
+}
+
+/// Walk `path` depth first, collecting the paths that match. Symlinks are
+/// listed but not followed. Subdirectories that can't be read are reported
+/// and skipped, like find does.
+fn find_paths(path: &Path, depth: usize, criteria: &FindCriteria, results: &mut Vec<ShellObject>) -> AnvilResult<()> {
+    let metadata = std::fs::symlink_metadata(path)
+        .map_err(|e| AnvilError::io_at(path.display().to_string(), e))?;
+    let is_dir = metadata.is_dir();
+
+    let name = path.file_name().map(|n| n.to_string_lossy()).unwrap_or_else(|| path.to_string_lossy());
+    let name_matches = criteria.name.as_ref().is_none_or(|pattern| crate::utils::glob_match(pattern, &name));
+    if name_matches && criteria.kind.is_none_or(|dir| dir == is_dir) {
+        results.push(ShellObject::String(path.to_string_lossy().to_string()));
+    }
+
+    if !is_dir || criteria.max_depth.is_some_and(|max| depth >= max) {
+        return Ok(());
+    }
+    let entries = match std::fs::read_dir(path) {
+        Ok(entries) => entries,
+        Err(e) if depth == 0 => return Err(AnvilError::io_at(path.display().to_string(), e)),
+        Err(e) => {
+            eprintln!("find: {}: {}", path.display(), e);
+            return Ok(());
+        }
+    };
+    let mut children: Vec<PathBuf> = entries.flatten().map(|entry| entry.path()).collect();
+    children.sort();
+    for child in children {
+        find_paths(&child, depth + 1, criteria, results)?;
+    }
+    Ok(())
+}
+
+/// Flags `grep` understands
+#[derive(Default)]
+struct GrepOptions {
+    fixed: bool,
+    ignore_case: bool,
+    line_numbers: bool,
+    invert: bool,
+}
+
+/// The lines of `content` grep reports, prefixed with the file name and
+/// line number when asked
+fn grep_lines(regex: &regex::Regex, options: &GrepOptions, content: &str, file: Option<&str>) -> Vec<ShellObject> {
+    content.lines()
+        .enumerate()
+        .filter(|(_, line)| regex.is_match(line) != options.invert)
+        .map(|(i, line)| {
+            let mut text = String::new();
+            if let Some(file) = file {
+                text.push_str(&format!("{}:", file));
+            }
+            if options.line_numbers {
+                text.push_str(&format!("{}:", i + 1));
+            }
+            text.push_str(line);
+            ShellObject::String(text)
+        })
+        .collect()
+}
+
+/// Separate `-flag` arguments from positional ones
+fn split_flags(args: &[String]) -> (Vec<&str>, Vec<&str>) {
+    args.iter()
+        .map(|a| a.as_str())
+        .partition(|a| a.starts_with('-') && a.len() > 1)
+}
+
+/// Reject paths that try to escape via `..` traversal or leave the allowed
+/// directories
+fn checked_path(allowed: &AllowedDirs, cmd: &str, path: &str) -> AnvilResult<PathBuf> {
+    let path = PathBuf::from(path);
+    if !PathUtils::is_safe_path(&path) {
+        return Err(AnvilError::command(format!(
+            "{}: refusing to operate on '{}': path traversal is not allowed", cmd, path.display()
+        )));
+    }
+    confined(allowed, path)
+}
+
+/// When the destination is an existing directory, place the source inside it
+fn destination_for(src: &Path, dst: &Path) -> PathBuf {
+    match src.file_name() {
+        Some(name) if dst.is_dir() => dst.join(name),
+        _ => dst.to_path_buf(),
+    }
+}
+
+fn copy_dir_recursive(src: &Path, dst: &Path) -> AnvilResult<()> {
+    std::fs::create_dir_all(dst)?;
+    for entry in std::fs::read_dir(src)? {
+        let entry = entry?;
+        let target = dst.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir_recursive(&entry.path(), &target)?;
+        } else {
+            std::fs::copy(entry.path(), &target)?;
+        }
+    }
+    Ok(())
+}
+
 impl Default for CommandRegistry {
     fn default() -> Self {
         Self::new()
@@ -368,6 +1146,15 @@ impl Default for CommandRegistry {
 mod tests {
     use super::*;
 
+    /// A registry, a scratch directory for its commands to work in, and a
+    /// function giving the path of a name inside that directory
+    fn fixture() -> (CommandRegistry, tempfile::TempDir, impl Fn(&str) -> String) {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().to_path_buf();
+        let path = move |name: &str| root.join(name).to_string_lossy().to_string();
+        (CommandRegistry::new(), dir, path)
+    }
+
     #[test]
     fn test_command_registry() {
         let registry = CommandRegistry::new();
@@ -376,6 +1163,29 @@ mod tests {
         assert!(!registry.has_command("nonexistent"));
     }
 
+    #[tokio::test]
+    async fn test_async_commands() {
+        let mut registry = CommandRegistry::new();
+        registry.register_command(CommandInfo {
+            name: "later".to_string(),
+            description: "Echo after yielding".to_string(),
+            usage: "later <word>...".to_string(),
+            handler: CommandHandler::Async(Box::new(|args| Box::pin(async move {
+                tokio::task::yield_now().await;
+                Ok(ShellObject::String(args.join(" ")))
+            }))),
+        });
+
+        let args = vec!["a".to_string(), "b".to_string()];
+        let result = registry.execute_command_async("later", &args).await.unwrap();
+        assert!(matches!(result, ShellObject::String(s) if s == "a b"));
+
+        // Sync commands run the same way, but async ones can't run synchronously
+        assert!(registry.execute_command_async("whoami", &[]).await.is_ok());
+        assert!(registry.execute_command("later", &args).is_err());
+        assert!(registry.execute_command_async("ping", &[]).await.is_err());
+    }
+
     #[test]
     fn test_help_command() {
         let registry = CommandRegistry::new();
@@ -388,4 +1198,419 @@ mod tests {
             _ => panic!("Expected string result for help"),
         }
     }
+
+    #[test]
+    fn test_help_for_command() {
+        let registry = CommandRegistry::new();
+
+        let help = registry.execute_command("help", &["cat".to_string()]).unwrap();
+        assert!(help.to_display_string().contains("cat <file>"));
+        assert!(help.to_display_string().contains("Display file contents"));
+
+        let listing = registry.execute_command("help", &[]).unwrap().to_display_string();
+        assert!(listing.contains("whoami"));
+
+        assert!(registry.execute_command("help", &["nope".to_string()]).is_err());
+        assert_eq!(registry.get_command_info("head").unwrap().usage, "head [-n lines] <file>");
+    }
+
+    #[test]
+    fn test_cp_mv_rm() {
+        let (registry, dir, path) = fixture();
+
+        std::fs::write(path("a.txt"), "hello").unwrap();
+        registry.execute_command("cp", &[path("a.txt"), path("b.txt")]).unwrap();
+        assert_eq!(std::fs::read_to_string(path("b.txt")).unwrap(), "hello");
+
+        std::fs::create_dir(path("sub")).unwrap();
+        registry.execute_command("mv", &[path("b.txt"), path("sub")]).unwrap();
+        assert!(dir.path().join("sub/b.txt").exists());
+
+        assert!(registry.execute_command("cp", &[path("sub"), path("copy")]).is_err());
+        registry.execute_command("cp", &["-r".to_string(), path("sub"), path("copy")]).unwrap();
+        assert!(dir.path().join("copy/b.txt").exists());
+
+        assert!(registry.execute_command("rm", &[path("copy")]).is_err());
+        registry.execute_command("rm", &["-r".to_string(), path("copy")]).unwrap();
+        assert!(!dir.path().join("copy").exists());
+
+        let err = registry.execute_command("rm", &[path("missing.txt")]).unwrap_err();
+        assert!(matches!(err, AnvilError::FileNotFound { .. }));
+        assert!(registry.execute_command("rm", &["../a.txt".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_mkdir_touch() {
+        let (registry, dir, path) = fixture();
+        let nested = path("a/b");
+
+        assert!(registry.execute_command("mkdir", std::slice::from_ref(&nested)).is_err());
+        let result = registry.execute_command("mkdir", &["-p".to_string(), nested.clone()]).unwrap();
+        assert!(matches!(result, ShellObject::Path(_)));
+        assert!(dir.path().join("a/b").is_dir());
+
+        let err = registry.execute_command("mkdir", &[nested]).unwrap_err();
+        assert!(err.is_recoverable());
+
+        let file = path("a/b/empty.txt");
+        registry.execute_command("touch", std::slice::from_ref(&file)).unwrap();
+        registry.execute_command("touch", std::slice::from_ref(&file)).unwrap();
+        assert_eq!(std::fs::metadata(&file).unwrap().len(), 0);
+    }
+
+    #[test]
+    fn test_system_info() {
+        let registry = CommandRegistry::new();
+
+        for cmd in ["whoami", "hostname"] {
+            match registry.execute_command(cmd, &[]).unwrap() {
+                ShellObject::String(s) => assert!(!s.is_empty()),
+                other => panic!("{} returned {:?}", cmd, other),
+            }
+        }
+
+        match registry.execute_command("uptime", &[]) {
+            Ok(ShellObject::Map(info)) => {
+                for key in ["days", "hours", "minutes", "seconds"] {
+                    assert!(info.contains_key(key));
+                }
+            }
+            Ok(other) => panic!("uptime returned {:?}", other),
+            Err(e) => assert!(matches!(e, AnvilError::Unsupported { .. })),
+        }
+    }
+
+    #[test]
+    fn test_date() {
+        let registry = CommandRegistry::new();
+
+        let utc = registry.execute_command("date", &["-u".to_string()]).unwrap();
+        assert!(utc.to_display_string().ends_with('Z'));
+
+        let year = registry.execute_command("date", &["+%Y".to_string()]).unwrap();
+        assert_eq!(year.to_display_string().len(), 4);
+
+        assert!(registry.execute_command("date", &["+%Q".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_cat_concatenates_files() {
+        let (registry, _dir, path) = fixture();
+        std::fs::write(path("a.txt"), "one\n").unwrap();
+        std::fs::write(path("b.txt"), "two\n").unwrap();
+
+        let result = registry.execute_command("cat", &[path("a.txt"), path("b.txt"), path("a.txt")]).unwrap();
+        assert!(matches!(result, ShellObject::String(s) if s == "one\ntwo\none\n"));
+
+        // A missing file is skipped when the others can be read
+        let result = registry.execute_command("cat", &[path("a.txt"), path("missing.txt"), path("b.txt")]).unwrap();
+        assert!(matches!(result, ShellObject::String(s) if s == "one\ntwo\n"));
+    }
+
+    #[test]
+    fn test_wc_counts() {
+        let (registry, _dir, path) = fixture();
+        std::fs::write(path("a.txt"), "héllo world\nbye\n").unwrap();
+        std::fs::write(path("b.txt"), "one\n").unwrap();
+
+        let count = |result: &ShellObject, key: &str| match result {
+            ShellObject::Map(map) => map.get(key).cloned(),
+            _ => None,
+        };
+
+        let all = registry.execute_command("wc", &[path("a.txt")]).unwrap();
+        assert_eq!(count(&all, "lines"), Some(ShellObject::Integer(2)));
+        assert_eq!(count(&all, "words"), Some(ShellObject::Integer(3)));
+        assert_eq!(count(&all, "chars"), Some(ShellObject::Integer(16)));
+
+        // Bytes and chars differ for non-ASCII text
+        let selected = registry.execute_command("wc", &["-cm".to_string(), path("a.txt")]).unwrap();
+        assert_eq!(count(&selected, "bytes"), Some(ShellObject::Integer(17)));
+        assert_eq!(count(&selected, "chars"), Some(ShellObject::Integer(16)));
+        assert_eq!(count(&selected, "lines"), None);
+
+        let result = registry.execute_command("wc", &["-l".to_string(), path("a.txt"), path("b.txt")]).unwrap();
+        let ShellObject::Array(rows) = result else { panic!("Expected a row per file") };
+        assert_eq!(rows.len(), 3);
+        assert_eq!(count(&rows[2], "lines"), Some(ShellObject::Integer(3)));
+        assert_eq!(count(&rows[2], "file"), Some(ShellObject::String("total".to_string())));
+
+        assert!(registry.execute_command("wc", &["-x".to_string(), path("a.txt")]).is_err());
+    }
+
+    #[test]
+    fn test_find() {
+        let (registry, dir, path) = fixture();
+        std::fs::create_dir_all(path("src/nested")).unwrap();
+        std::fs::write(path("src/main.rs"), "").unwrap();
+        std::fs::write(path("src/nested/mod.rs"), "").unwrap();
+        std::fs::write(path("src/notes.txt"), "").unwrap();
+
+        let root = dir.path().to_string_lossy().to_string();
+        let find = |options: &[&str]| {
+            let mut args = vec![root.clone()];
+            args.extend(options.iter().map(|o| o.to_string()));
+            match registry.execute_command("find", &args).unwrap() {
+                ShellObject::Array(paths) => paths.iter()
+                    .map(|p| p.to_display_string().strip_prefix(&root).unwrap().to_string())
+                    .collect::<Vec<_>>(),
+                other => panic!("find returned {:?}", other),
+            }
+        };
+
+        assert_eq!(find(&["-name", "*.rs"]), ["/src/main.rs", "/src/nested/mod.rs"]);
+        assert_eq!(find(&["-type", "d"]), ["", "/src", "/src/nested"]);
+        assert_eq!(find(&["-maxdepth", "1"]), ["", "/src"]);
+        // -name is a glob over the whole file name, not a substring
+        assert!(find(&["-name", "main"]).is_empty());
+
+        let err = registry.execute_command("find", &[root.clone(), "-newer".to_string(), root.clone()]).unwrap_err();
+        assert!(matches!(err, AnvilError::Unsupported { .. }));
+    }
+
+    #[test]
+    fn test_grep_flags() {
+        let (registry, _dir, path) = fixture();
+        std::fs::write(path("a.txt"), "Apple pie\nbanana\napple.js\n").unwrap();
+        std::fs::write(path("b.txt"), "pineapple\n").unwrap();
+
+        let grep = |args: &[&str]| {
+            let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+            registry.execute_command("grep", &args).map(|result| result.to_display_string())
+        };
+
+        assert_eq!(grep(&["apple", &path("a.txt")]).unwrap(), "[apple.js]");
+        assert_eq!(grep(&["-i", "^apple", &path("a.txt")]).unwrap(), "[Apple pie, apple.js]");
+        assert_eq!(grep(&["-vn", "apple", &path("a.txt")]).unwrap(), "[1:Apple pie, 2:banana]");
+        assert_eq!(grep(&["-F", "e.j", &path("a.txt")]).unwrap(), "[apple.js]");
+        assert_eq!(grep(&["-F", "e.", &path("a.txt")]).unwrap(), "[apple.js]");
+        assert_eq!(
+            grep(&["apple$", &path("a.txt"), &path("b.txt")]).unwrap(),
+            format!("[{}:pineapple]", path("b.txt"))
+        );
+
+        assert!(matches!(grep(&["-c", "apple", &path("a.txt")]), Err(AnvilError::Unsupported { .. })));
+        assert!(matches!(grep(&["(", &path("a.txt")]), Err(AnvilError::Parse { .. })));
+    }
+
+    #[test]
+    fn test_unsupported_flags() {
+        let (registry, _dir, path) = fixture();
+        let file = path("a.txt");
+        std::fs::write(&file, "1\n2\n3\n").unwrap();
+
+        let head = registry.execute_command("head", &["-2".to_string(), file.clone()]).unwrap();
+        assert_eq!(head.to_display_string(), "1\n2");
+        let tail = registry.execute_command("tail", &["-n1".to_string(), file.clone()]).unwrap();
+        assert_eq!(tail.to_display_string(), "3");
+
+        for (cmd, flag) in [("cat", "-n"), ("head", "-c"), ("sort", "-r"), ("uniq", "-c"), ("mv", "-v"), ("cp", "-a"), ("cut", "-c1")] {
+            let err = registry.execute_command(cmd, &[flag.to_string(), file.clone()]).unwrap_err();
+            assert!(matches!(err, AnvilError::Unsupported { .. }), "{} {}: {:?}", cmd, flag, err);
+        }
+
+        // rm -f skips files that don't exist
+        let missing = path("missing");
+        registry.execute_command("rm", &["-f".to_string(), missing]).unwrap();
+        registry.execute_command("rm", &["-rf".to_string(), file.clone()]).unwrap();
+        assert!(!Path::new(&file).exists());
+
+        assert!(!registry.has_command("ps"));
+        assert!(!registry.has_command("df"));
+    }
+
+    #[test]
+    fn test_cut() {
+        let (registry, _dir, path) = fixture();
+        let file = path("data.csv");
+        std::fs::write(&file, "a,b,c,d,e\n1,2,3,4,5\nplain\n").unwrap();
+
+        let cut = |spec: &[&str]| {
+            let mut args: Vec<String> = spec.iter().map(|a| a.to_string()).collect();
+            args.push(file.clone());
+            registry.execute_command("cut", &args).map(|result| result.to_display_string())
+        };
+
+        assert_eq!(cut(&["-d,", "-f1,3-4"]).unwrap(), "[a,c,d, 1,3,4, plain]");
+        assert_eq!(cut(&["-d", ",", "-f", "4-"]).unwrap(), "[d,e, 4,5, plain]");
+        assert_eq!(cut(&["-d,", "-f-2"]).unwrap(), "[a,b, 1,2, plain]");
+
+        assert!(cut(&["-d,", "-f0"]).is_err());
+        assert!(cut(&["-d,", "-f3-1"]).is_err());
+        assert!(cut(&["-d,"]).is_err());
+    }
+
+    #[test]
+    fn test_sed_substitution() {
+        let (registry, _dir, path) = fixture();
+        let file = path("text.txt");
+        std::fs::write(&file, "foo foo\nbar foo\n").unwrap();
+
+        let sed = |expr: &str| registry.execute_command("sed", &[expr.to_string(), file.clone()])
+            .map(|result| result.to_display_string());
+
+        assert_eq!(sed("s/foo/baz/").unwrap(), "baz foo\nbar baz\n");
+        assert_eq!(sed("s/foo/baz/g").unwrap(), "baz baz\nbar baz\n");
+        assert_eq!(sed("s|(b)ar|<\\1&>|").unwrap(), "foo foo\n<bbar> foo\n");
+        assert_eq!(sed("s/foo/\\/$/").unwrap(), "/$ foo\nbar /$\n");
+
+        for bad in ["s/foo/bar", "s/(/x/", "s/a/b/q", "y/a/b/"] {
+            assert!(matches!(sed(bad), Err(AnvilError::Parse { .. })), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_tee() {
+        let (registry, _dir, path) = fixture();
+        let path = path("out.txt");
+
+        let result = tee(&AllowedDirs::default(), std::slice::from_ref(&path), "first\n".as_bytes()).unwrap();
+        assert!(matches!(result, ShellObject::String(s) if s == "first\n"));
+        tee(&AllowedDirs::default(), &["-a".to_string(), path.clone()], "second\n".as_bytes()).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "first\nsecond\n");
+
+        // Without -a the file is overwritten
+        tee(&AllowedDirs::default(), std::slice::from_ref(&path), "third\n".as_bytes()).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "third\n");
+
+        assert!(registry.has_command("tee"));
+        assert!(tee(&AllowedDirs::default(), &[], "".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_allowed_dirs() {
+        let registry = CommandRegistry::new();
+        let allowed = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        let inside_file = allowed.path().join("in.txt").to_string_lossy().to_string();
+        let outside_file = outside.path().join("out.txt").to_string_lossy().to_string();
+        std::fs::write(&inside_file, "in").unwrap();
+        std::fs::write(&outside_file, "out").unwrap();
+
+        registry.set_allowed_dirs(vec![allowed.path().to_path_buf()]);
+        assert!(registry.execute_command("cat", std::slice::from_ref(&inside_file)).is_ok());
+        let denied = |cmd: &str, args: &[&String]| {
+            let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+            matches!(registry.execute_command(cmd, &args), Err(AnvilError::PermissionDenied { .. }))
+        };
+        assert!(denied("cat", &[&outside_file]));
+        assert!(denied("cp", &[&inside_file, &outside_file]));
+        assert!(denied("rm", &[&outside_file]));
+        assert!(denied("touch", &[&outside.path().join("new.txt").to_string_lossy().to_string()]));
+
+        // Symlinks are followed before checking
+        #[cfg(unix)]
+        {
+            let link = allowed.path().join("link").to_string_lossy().to_string();
+            std::os::unix::fs::symlink(&outside_file, &link).unwrap();
+            assert!(denied("cat", &[&link]));
+        }
+
+        registry.set_allowed_dirs(Vec::new());
+        assert!(registry.execute_command("cat", std::slice::from_ref(&outside_file)).is_ok());
+    }
+
+    #[test]
+    fn test_read_errors_name_the_file() {
+        let (registry, dir, path) = fixture();
+        let missing = path("missing.txt");
+
+        for cmd in ["cat", "head", "tail", "wc", "sort", "uniq"] {
+            let err = registry.execute_command(cmd, std::slice::from_ref(&missing)).unwrap_err();
+            assert!(matches!(&err, AnvilError::FileNotFound { path } if *path == missing), "{}", cmd);
+        }
+
+        let err = registry.execute_command("cat", &[dir.path().to_string_lossy().to_string()]).unwrap_err();
+        assert!(matches!(err, AnvilError::FileIo { .. }));
+        assert!(err.to_string().contains(&*dir.path().to_string_lossy()));
+    }
+
+    #[cfg(feature = "http")]
+    #[test]
+    fn test_http_args() {
+        let args = |words: &[&str]| parse_http_args(&words.iter().map(|w| w.to_string()).collect::<Vec<_>>());
+
+        assert_eq!(args(&["get", "http://x"]).unwrap(), HttpArgs { post: false, body: None, url: "http://x".to_string() });
+        assert_eq!(
+            args(&["post", "-d", "a=1", "http://x"]).unwrap(),
+            HttpArgs { post: true, body: Some("a=1".to_string()), url: "http://x".to_string() }
+        );
+        assert_eq!(args(&["post", "http://x"]).unwrap().body, None);
+
+        let bad: [&[&str]; 6] = [
+            &[],
+            &["get"],
+            &["put", "http://x"],
+            &["get", "-d", "a", "http://x"],
+            &["post", "http://x", "-d"],
+            &["get", "http://x", "http://y"],
+        ];
+        for words in bad {
+            let err = args(words).unwrap_err();
+            assert!(err.to_string().contains("usage: http"), "{:?}: {}", words, err);
+        }
+    }
+
+    /// Serve one connection on a local port with `response`, returning the
+    /// URL to request and the server thread, which yields the request it read
+    #[cfg(feature = "http")]
+    fn serve_once(response: &'static str) -> (String, std::thread::JoinHandle<String>) {
+        use std::io::{Read, Write};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0; 1024];
+            while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                let n = stream.read(&mut buf).unwrap();
+                if n == 0 {
+                    break;
+                }
+                request.extend_from_slice(&buf[..n]);
+            }
+            stream.write_all(response.as_bytes()).unwrap();
+            String::from_utf8_lossy(&request).into_owned()
+        });
+        (url, server)
+    }
+
+    #[cfg(feature = "http")]
+    #[tokio::test]
+    async fn test_http_request() {
+        let (url, server) = serve_once(concat!(
+            "HTTP/1.1 201 Created\r\n",
+            "Content-Type: application/json\r\n",
+            "Set-Cookie: a=1\r\n",
+            "Set-Cookie: b=2\r\n",
+            "Content-Length: 12\r\n",
+            "Connection: close\r\n\r\n",
+            "{\"ok\": true}",
+        ));
+        let result = http_request(vec!["get".to_string(), url], std::time::Duration::from_secs(10)).await.unwrap();
+        assert!(server.join().unwrap().starts_with("GET / HTTP/1.1"));
+
+        let ShellObject::Map(result) = result else { panic!("expected a map") };
+        assert_eq!(result["status"], ShellObject::Integer(201));
+        let ShellObject::Map(headers) = &result["headers"] else { panic!("expected headers") };
+        assert_eq!(headers["content-type"], ShellObject::String("application/json".to_string()));
+        assert_eq!(
+            headers["set-cookie"],
+            ShellObject::Array(vec![ShellObject::String("a=1".to_string()), ShellObject::String("b=2".to_string())])
+        );
+        let ShellObject::Map(body) = &result["body"] else { panic!("expected a parsed body") };
+        assert_eq!(body["ok"], ShellObject::Boolean(true));
+    }
+
+    #[cfg(feature = "http")]
+    #[tokio::test]
+    async fn test_http_timeout() {
+        // The connection is accepted into the backlog but never answered
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+
+        let err = http_request(vec!["get".to_string(), url], std::time::Duration::from_millis(100)).await.unwrap_err();
+        assert!(matches!(err, AnvilError::Timeout { .. }), "{}", err);
+    }
 }
\ No newline at end of file
@@ -5,6 +5,7 @@ use std::path::{Path, PathBuf};
 use tokio::fs;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Config {
     pub shell: ShellConfig,
     pub repl: ReplConfig,
@@ -13,22 +14,42 @@ pub struct Config {
     pub functions: HashMap<String, String>,
     pub keybindings: HashMap<String, String>,
     pub paths: PathsConfig,
+    /// The file this config was read from, if any
+    #[serde(skip)]
+    pub loaded_from: Option<PathBuf>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct ShellConfig {
     pub prompt: String,
     pub continuation_prompt: String,
     pub history_file: PathBuf,
     pub max_history_size: usize,
+    /// Change into a directory typed on its own as a command. Script lines
+    /// are left alone.
     pub auto_cd: bool,
     pub case_sensitive: bool,
     pub tab_completion: bool,
     pub syntax_highlighting: bool,
     pub auto_suggestions: bool,
+    /// Show the current git branch in the right prompt
+    pub show_git_branch: bool,
+    /// Show a failing exit status in the right prompt
+    pub show_exit_status: bool,
+    /// Print the banner when the interactive shell starts
+    pub show_banner: bool,
+    /// Replacement banner text; `{version}` is replaced with the version
+    pub banner: Option<String>,
+    /// Fail a command when a glob in its arguments matches nothing, instead
+    /// of passing the pattern through literally
+    pub failglob: bool,
+    /// Time limit for an `http` request, from connecting to reading the body
+    pub http_timeout_ms: u64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct ReplConfig {
     pub auto_print: bool,
     pub multiline_mode: bool,
@@ -37,9 +58,67 @@ pub struct ReplConfig {
     pub execution_timeout_ms: u64,
     pub enable_unsafe: bool,
     pub prelude: Vec<String>,
+    pub backend: ReplBackend,
+    pub display_mode: DisplayMode,
+    /// Run compiled snippets with CPU time and memory limits, and on Linux
+    /// without network access. Snippets fail to start where that isn't
+    /// permitted.
+    pub sandbox: bool,
+    /// Address space limit for sandboxed snippets
+    pub sandbox_memory_mb: u64,
+    /// Entries of each array or map shown when printing a result, 0 for all
+    pub max_display_items: usize,
+    /// Characters of each string shown when printing a result, 0 for all
+    pub max_display_width: usize,
+    /// Show a progress spinner on a terminal while snippets compile and run
+    pub spinner: bool,
+}
+
+/// How the REPL builds code the interpreter can't evaluate
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReplBackend {
+    /// Compile each snippet as a standalone file with rustc
+    #[default]
+    Rustc,
+    /// Build snippets inside a persistent cargo project in the cache directory
+    Cargo,
+}
+
+/// How the REPL prints results
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DisplayMode {
+    /// Everything on one line
+    #[default]
+    Compact,
+    /// Nested arrays and maps indented over several lines
+    Pretty,
+    /// JSON text
+    Json,
+    /// Arrays of maps as aligned tables, anything else compact
+    Table,
+}
+
+impl std::str::FromStr for DisplayMode {
+    type Err = AnvilError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "compact" => Ok(DisplayMode::Compact),
+            "pretty" => Ok(DisplayMode::Pretty),
+            "json" => Ok(DisplayMode::Json),
+            "table" => Ok(DisplayMode::Table),
+            other => Err(AnvilError::config(format!(
+                "Unknown display mode '{}' (expected compact, pretty, json or table)",
+                other
+            ))),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct EnvironmentConfig {
     pub inherit_system_env: bool,
     pub default_vars: HashMap<String, String>,
@@ -47,93 +126,193 @@ pub struct EnvironmentConfig {
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct PathsConfig {
     pub config_dir: PathBuf,
     pub data_dir: PathBuf,
     pub cache_dir: PathBuf,
     pub temp_dir: PathBuf,
+    /// Directories file commands may touch; empty allows any path
+    pub allowed_dirs: Vec<PathBuf>,
 }
 
 impl Default for Config {
     fn default() -> Self {
-        let config_dir = dirs::config_dir()
-            .unwrap_or_else(|| PathBuf::from("."))
-            .join("anvil");
-        
-        let data_dir = dirs::data_dir()
-            .unwrap_or_else(|| PathBuf::from("."))
-            .join("anvil");
-
         Self {
-            shell: ShellConfig {
-                prompt: "anvil> ".to_string(),
-                continuation_prompt: "    > ".to_string(),
-                history_file: data_dir.join("history.txt"),
-                max_history_size: 10000,
-                auto_cd: true,
-                case_sensitive: false,
-                tab_completion: true,
-                syntax_highlighting: true,
-                auto_suggestions: true,
-            },
-            repl: ReplConfig {
-                auto_print: true,
-                multiline_mode: true,
-                indent_size: 4,
-                compile_timeout_ms: 5000,
-                execution_timeout_ms: 30000,
-                enable_unsafe: false,
-                prelude: vec![
-                    "use std::collections::HashMap;".to_string(),
-                    "use std::path::PathBuf;".to_string(),
-                    "use std::fs;".to_string(),
-                    "use std::process::Command;".to_string(),
-                ],
-            },
-            environment: EnvironmentConfig {
-                inherit_system_env: true,
-                default_vars: HashMap::new(),
-                path_separator: if cfg!(windows) { ";" } else { ":" }.to_string(),
-            },
+            shell: ShellConfig::default(),
+            repl: ReplConfig::default(),
+            environment: EnvironmentConfig::default(),
             aliases: create_default_aliases(),
             functions: HashMap::new(),
             keybindings: create_default_keybindings(),
-            paths: PathsConfig {
-                config_dir: config_dir.clone(),
-                data_dir: data_dir.clone(),
-                cache_dir: dirs::cache_dir()
-                    .unwrap_or_else(|| PathBuf::from("."))
-                    .join("anvil"),
-                temp_dir: std::env::temp_dir().join("anvil"),
-            },
+            paths: PathsConfig::default(),
+            loaded_from: None,
+        }
+    }
+}
+
+impl Default for ShellConfig {
+    fn default() -> Self {
+        Self {
+            prompt: "anvil> ".to_string(),
+            continuation_prompt: "    > ".to_string(),
+            history_file: default_data_dir().join("history.txt"),
+            max_history_size: 10000,
+            auto_cd: true,
+            case_sensitive: false,
+            tab_completion: true,
+            syntax_highlighting: true,
+            auto_suggestions: true,
+            show_git_branch: true,
+            show_exit_status: true,
+            show_banner: true,
+            banner: None,
+            failglob: false,
+            http_timeout_ms: 30000,
+        }
+    }
+}
+
+impl Default for ReplConfig {
+    fn default() -> Self {
+        Self {
+            auto_print: true,
+            multiline_mode: true,
+            indent_size: 4,
+            compile_timeout_ms: 5000,
+            execution_timeout_ms: 30000,
+            enable_unsafe: false,
+            prelude: vec![
+                "use std::collections::HashMap;".to_string(),
+                "use std::path::PathBuf;".to_string(),
+                "use std::fs;".to_string(),
+                "use std::process::Command;".to_string(),
+            ],
+            backend: ReplBackend::default(),
+            display_mode: DisplayMode::default(),
+            sandbox: false,
+            sandbox_memory_mb: 1024,
+            max_display_items: 100,
+            max_display_width: 1000,
+            spinner: true,
+        }
+    }
+}
+
+impl Default for EnvironmentConfig {
+    fn default() -> Self {
+        Self {
+            inherit_system_env: true,
+            default_vars: HashMap::new(),
+            path_separator: if cfg!(windows) { ";" } else { ":" }.to_string(),
+        }
+    }
+}
+
+impl Default for PathsConfig {
+    fn default() -> Self {
+        Self {
+            config_dir: dirs::config_dir()
+                .unwrap_or_else(|| PathBuf::from("."))
+                .join("anvil"),
+            data_dir: default_data_dir(),
+            cache_dir: dirs::cache_dir()
+                .unwrap_or_else(|| PathBuf::from("."))
+                .join("anvil"),
+            temp_dir: std::env::temp_dir().join("anvil"),
+            allowed_dirs: Vec::new(),
         }
     }
 }
 
 impl Config {
-    /// Load configuration from file or create default
+    /// Load configuration from file or create default. See
+    /// [`Config::discover`] for where the file is looked for.
     pub async fn load(config_path: Option<&Path>) -> AnvilResult<Self> {
-        let config_file = if let Some(path) = config_path {
-            path.to_path_buf()
-        } else {
-            let config_dir = dirs::config_dir()
-                .unwrap_or_else(|| PathBuf::from("."))
-                .join("anvil");
-            config_dir.join("config.toml")
-        };
+        let current_dir = std::env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
+        let config_file = Self::discover(config_path, &current_dir)?;
 
         if config_file.exists() {
+            log::info!("Loading config from {}", config_file.display());
             let content = fs::read_to_string(&config_file).await?;
-            let config: Config = toml::from_str(&content)
-                .map_err(|e| AnvilError::config(format!("Failed to parse config: {}", e)))?;
+            let mut config: Config = toml::from_str(&content)
+                .map_err(|e| AnvilError::config(format!("Failed to parse {}: {}", config_file.display(), e)))?;
+            config.validate()
+                .map_err(|e| AnvilError::config(format!("{}: {}", config_file.display(), e)))?;
+            config.loaded_from = Some(config_file);
             Ok(config)
         } else {
+            log::info!("No config file at {}, using defaults", config_file.display());
             let config = Config::default();
             config.ensure_directories().await?;
             Ok(config)
         }
     }
 
+    /// The config file to use: an explicit path if given, else a
+    /// project-local `anvil.toml` in `dir`, else `config.toml` in the user
+    /// config directory (`$XDG_CONFIG_HOME/anvil` on Linux)
+    pub fn discover(config_path: Option<&Path>, dir: &Path) -> AnvilResult<PathBuf> {
+        if let Some(path) = config_path {
+            return crate::utils::expand_user_path(path);
+        }
+
+        let local = dir.join(crate::CONFIG_FILE);
+        if local.is_file() {
+            return Ok(local);
+        }
+
+        Ok(dirs::config_dir()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join("anvil")
+            .join("config.toml"))
+    }
+
+    /// Check settings that parse but can't work, naming the offending field
+    pub fn validate(&self) -> AnvilResult<()> {
+        let invalid = |field: &str, reason: String| {
+            Err(AnvilError::config(format!("Invalid {}: {}", field, reason)))
+        };
+
+        if self.shell.prompt.is_empty() {
+            return invalid("shell.prompt", "must not be empty".to_string());
+        }
+        if self.shell.http_timeout_ms == 0 {
+            return invalid("shell.http_timeout_ms", "must be greater than 0".to_string());
+        }
+        if self.repl.compile_timeout_ms == 0 {
+            return invalid("repl.compile_timeout_ms", "must be greater than 0".to_string());
+        }
+        if self.repl.execution_timeout_ms == 0 {
+            return invalid("repl.execution_timeout_ms", "must be greater than 0".to_string());
+        }
+        if self.repl.sandbox && self.repl.sandbox_memory_mb == 0 {
+            return invalid("repl.sandbox_memory_mb", "must be greater than 0".to_string());
+        }
+        if self.environment.path_separator.is_empty() {
+            return invalid("environment.path_separator", "must not be empty".to_string());
+        }
+
+        // Directories are created on demand, so they only need to not be
+        // something else already
+        let dirs = [
+            ("paths.config_dir", &self.paths.config_dir),
+            ("paths.data_dir", &self.paths.data_dir),
+            ("paths.cache_dir", &self.paths.cache_dir),
+            ("paths.temp_dir", &self.paths.temp_dir),
+        ];
+        for (field, dir) in dirs {
+            if dir.exists() && !dir.is_dir() {
+                return invalid(field, format!("'{}' is not a directory", dir.display()));
+            }
+        }
+        if self.shell.history_file.is_dir() {
+            return invalid("shell.history_file", format!("'{}' is a directory", self.shell.history_file.display()));
+        }
+
+        Ok(())
+    }
+
     /// Save configuration to file
     pub async fn save(&self, config_path: Option<&Path>) -> AnvilResult<()> {
         let config_file = if let Some(path) = config_path {
@@ -262,6 +441,77 @@ impl Config {
         Ok(())
     }
 
+    /// Read a setting by its dotted path, e.g. `shell.prompt`
+    pub fn get_value(&self, key: &str) -> AnvilResult<toml::Value> {
+        let root = self.to_toml_value()?;
+        lookup_key(&root, key)
+            .cloned()
+            .ok_or_else(|| AnvilError::config(format!("Unknown config key '{}'", key)))
+    }
+
+    /// Change a setting by its dotted path. The value is parsed as the
+    /// setting's type, and unknown keys and whole sections are rejected.
+    pub fn set_value(&mut self, key: &str, value: &str) -> AnvilResult<()> {
+        let mut root = self.to_toml_value()?;
+        let (parent_key, field) = key.rsplit_once('.').unwrap_or(("", key));
+        let invalid = |expected: &str| AnvilError::config(format!(
+            "Invalid value for {}: expected {}, got '{}'", key, expected, value
+        ));
+
+        let parent = if parent_key.is_empty() {
+            Some(&mut root)
+        } else {
+            parent_key.split('.').try_fold(&mut root, |node, part| node.get_mut(part))
+        };
+        let Some(toml::Value::Table(table)) = parent else {
+            return Err(AnvilError::config(format!("Unknown config key '{}'", key)));
+        };
+
+        let new_value = match table.get(field) {
+            Some(toml::Value::Boolean(_)) => toml::Value::Boolean(value.parse().map_err(|_| invalid("true or false"))?),
+            Some(toml::Value::Integer(_)) => toml::Value::Integer(value.parse().map_err(|_| invalid("an integer"))?),
+            Some(toml::Value::Float(_)) => toml::Value::Float(value.parse().map_err(|_| invalid("a number"))?),
+            Some(toml::Value::String(_)) | None => toml::Value::String(value.to_string()),
+            Some(_) => {
+                return Err(AnvilError::config(format!("Config key '{}' can't be set from a single value", key)));
+            }
+        };
+        table.insert(field.to_string(), new_value);
+
+        let mut config: Config = root.try_into()
+            .map_err(|e| AnvilError::config(format!("Invalid value for {}: {}", key, e)))?;
+        config.validate()?;
+        // Not serialized, so the round trip loses it
+        config.loaded_from = self.loaded_from.clone();
+
+        // Unset optional settings are accepted above, so make sure the key
+        // is real by checking it survived the round trip
+        lookup_key(&config.to_toml_value()?, key)
+            .ok_or_else(|| AnvilError::config(format!("Unknown config key '{}'", key)))?;
+
+        *self = config;
+        Ok(())
+    }
+
+    /// Run `get <key>` or `set <key> <value>` on the live config, saving
+    /// it back to the file it came from after a change. Returns the setting's value.
+    pub async fn run_command(&mut self, args: &[String]) -> AnvilResult<toml::Value> {
+        match args {
+            [action, key] if action == "get" => self.get_value(key),
+            [action, key, value] if action == "set" => {
+                self.set_value(key, value)?;
+                self.save(self.loaded_from.as_deref()).await?;
+                self.get_value(key)
+            }
+            _ => Err(AnvilError::config("usage: config get <key> | config set <key> <value>")),
+        }
+    }
+
+    fn to_toml_value(&self) -> AnvilResult<toml::Value> {
+        toml::Value::try_from(self)
+            .map_err(|e| AnvilError::config(format!("Failed to serialize config: {}", e)))
+    }
+
     /// Get the full path to a file in the config directory
     pub fn config_file(&self, filename: &str) -> PathBuf {
         self.paths.config_dir.join(filename)
@@ -283,6 +533,17 @@ impl Config {
     }
 }
 
+/// Follow a dotted path through nested tables
+fn lookup_key<'a>(root: &'a toml::Value, key: &str) -> Option<&'a toml::Value> {
+    key.split('.').try_fold(root, |node, part| node.get(part))
+}
+
+fn default_data_dir() -> PathBuf {
+    dirs::data_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("anvil")
+}
+
 fn create_default_aliases() -> HashMap<String, String> {
     let mut aliases = HashMap::new();
     
@@ -338,4 +599,139 @@ mod tests {
         let loaded_config = Config::load(Some(&config_file)).await.unwrap();
         assert_eq!(config.shell.prompt, loaded_config.shell.prompt);
     }
+
+    #[tokio::test]
+    async fn test_repl_backend_config() {
+        let mut config = Config::default();
+        assert_eq!(config.repl.backend, ReplBackend::Rustc);
+
+        config.repl.backend = ReplBackend::Cargo;
+        let content = toml::to_string_pretty(&config).unwrap();
+        assert!(content.contains("backend = \"cargo\""));
+
+        // Configs written before the backend option existed still load
+        let content = content.replace("backend = \"cargo\"", "");
+        let loaded: Config = toml::from_str(&content).unwrap();
+        assert_eq!(loaded.repl.backend, ReplBackend::Rustc);
+    }
+
+    #[tokio::test]
+    async fn test_config_get_set() {
+        let dir = tempdir().unwrap();
+        let mut config = Config::default();
+        config.paths.config_dir = dir.path().to_path_buf();
+
+        assert_eq!(config.get_value("shell.prompt").unwrap(), toml::Value::String("anvil> ".to_string()));
+
+        config.set_value("repl.auto_print", "false").unwrap();
+        assert!(!config.repl.auto_print);
+        config.set_value("repl.compile_timeout_ms", "250").unwrap();
+        assert_eq!(config.repl.compile_timeout_ms, 250);
+        config.set_value("shell.banner", "hi {version}").unwrap();
+        assert_eq!(config.shell.banner.as_deref(), Some("hi {version}"));
+
+        // Values are checked against the setting's type
+        assert!(config.set_value("repl.auto_print", "yes").is_err());
+        assert!(config.set_value("repl.compile_timeout_ms", "-1").is_err());
+        assert!(config.set_value("repl.display_mode", "fancy").is_err());
+        assert!(config.set_value("shell.no_such_setting", "1").is_err());
+        assert!(config.set_value("repl", "1").is_err());
+        assert!(config.get_value("nope.nothing").is_err());
+
+        let args = |words: &[&str]| words.iter().map(|w| w.to_string()).collect::<Vec<_>>();
+        config.run_command(&args(&["set", "shell.prompt", "$ "])).await.unwrap();
+        let saved = Config::load(Some(&dir.path().join("config.toml"))).await.unwrap();
+        assert_eq!(saved.shell.prompt, "$ ");
+        assert!(config.run_command(&args(&["frob"])).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_config_set_saves_to_loaded_file() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("custom.toml");
+        let mut config = Config::default();
+        config.paths.config_dir = dir.path().join("default");
+        config.save(Some(&path)).await.unwrap();
+
+        let mut config = Config::load(Some(&path)).await.unwrap();
+        let args = ["set", "shell.prompt", "% "].map(String::from);
+        config.run_command(&args).await.unwrap();
+        assert_eq!(config.loaded_from.as_deref(), Some(path.as_path()));
+        assert_eq!(Config::load(Some(&path)).await.unwrap().shell.prompt, "% ");
+        assert!(!dir.path().join("default/config.toml").exists());
+    }
+
+    #[tokio::test]
+    async fn test_config_validation() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+
+        let mut config = Config::default();
+        config.repl.compile_timeout_ms = 0;
+        std::fs::write(&path, toml::to_string(&config).unwrap()).unwrap();
+        let err = Config::load(Some(&path)).await.unwrap_err();
+        assert!(matches!(err, AnvilError::Config { .. }));
+        assert!(err.to_string().contains("repl.compile_timeout_ms"), "{}", err);
+
+        let mut config = Config::default();
+        config.shell.prompt.clear();
+        assert!(config.validate().unwrap_err().to_string().contains("shell.prompt"));
+
+        let mut config = Config::default();
+        config.paths.cache_dir = path.clone();
+        assert!(config.validate().unwrap_err().to_string().contains("paths.cache_dir"));
+
+        let mut config = Config::default();
+        assert!(config.set_value("repl.execution_timeout_ms", "0").is_err());
+        assert_eq!(config.repl.execution_timeout_ms, 30000);
+        assert!(Config::default().validate().is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_partial_config() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        std::fs::write(&path, "[shell]\nprompt = \"$ \"\n").unwrap();
+
+        let config = Config::load(Some(&path)).await.unwrap();
+        let defaults = Config::default();
+        assert_eq!(config.shell.prompt, "$ ");
+        assert_eq!(config.shell.continuation_prompt, defaults.shell.continuation_prompt);
+        assert_eq!(config.repl.compile_timeout_ms, defaults.repl.compile_timeout_ms);
+        assert_eq!(config.repl.prelude, defaults.repl.prelude);
+        assert_eq!(config.aliases, defaults.aliases);
+        assert_eq!(config.paths.cache_dir, defaults.paths.cache_dir);
+    }
+
+    #[test]
+    fn test_config_discovery() {
+        let project = tempdir().unwrap();
+        let explicit = project.path().join("custom.toml");
+        let user_config = dirs::config_dir()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join("anvil")
+            .join("config.toml");
+
+        assert_eq!(Config::discover(None, project.path()).unwrap(), user_config);
+
+        std::fs::write(project.path().join(crate::CONFIG_FILE), "").unwrap();
+        assert_eq!(Config::discover(None, project.path()).unwrap(), project.path().join("anvil.toml"));
+        assert_eq!(Config::discover(Some(&explicit), project.path()).unwrap(), explicit);
+    }
+
+    #[test]
+    fn test_display_mode_config() {
+        assert_eq!("Pretty".parse::<DisplayMode>().unwrap(), DisplayMode::Pretty);
+        assert_eq!("json".parse::<DisplayMode>().unwrap(), DisplayMode::Json);
+        assert!("fancy".parse::<DisplayMode>().is_err());
+
+        let mut config = Config::default();
+        config.repl.display_mode = DisplayMode::Table;
+        let content = toml::to_string_pretty(&config).unwrap();
+        assert!(content.contains("display_mode = \"table\""));
+
+        let content = content.replace("display_mode = \"table\"", "");
+        let loaded: Config = toml::from_str(&content).unwrap();
+        assert_eq!(loaded.repl.display_mode, DisplayMode::Compact);
+    }
 }
\ No newline at end of file
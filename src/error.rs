@@ -44,6 +44,13 @@ pub enum AnvilError {
     #[error("Permission denied: {path}")]
     PermissionDenied { path: String },
 
+    #[error("IO error: {path}: {source}")]
+    FileIo {
+        path: String,
+        #[source]
+        source: std::io::Error,
+    },
+
     #[error("Invalid syntax: {message}")]
     InvalidSyntax { message: String },
 
@@ -53,6 +60,9 @@ pub enum AnvilError {
     #[error("External command failed: {command} (exit code: {code})")]
     ExternalCommand { command: String, code: i32 },
 
+    #[error("Operation timed out: {phase} exceeded {elapsed_ms}ms")]
+    Timeout { phase: String, elapsed_ms: u64 },
+
     #[error("Interrupted")]
     Interrupted,
 
@@ -128,6 +138,17 @@ impl AnvilError {
         }
     }
 
+    /// Attach the offending path to an IO error, using the more specific
+    /// variants for missing files and permission problems
+    pub fn io_at<S: Into<String>>(path: S, source: std::io::Error) -> Self {
+        let path = path.into();
+        match source.kind() {
+            std::io::ErrorKind::NotFound => Self::FileNotFound { path },
+            std::io::ErrorKind::PermissionDenied => Self::PermissionDenied { path },
+            _ => Self::FileIo { path, source },
+        }
+    }
+
     pub fn invalid_syntax<S: Into<String>>(message: S) -> Self {
         Self::InvalidSyntax {
             message: message.into(),
@@ -147,6 +168,13 @@ impl AnvilError {
         }
     }
 
+    pub fn timeout<S: Into<String>>(phase: S, elapsed_ms: u64) -> Self {
+        Self::Timeout {
+            phase: phase.into(),
+            elapsed_ms,
+        }
+    }
+
     pub fn unsupported<S: Into<String>>(operation: S) -> Self {
         Self::Unsupported {
             operation: operation.into(),
@@ -155,23 +183,72 @@ impl AnvilError {
 
     /// Returns true if this error is recoverable in REPL mode
     pub fn is_recoverable(&self) -> bool {
-        matches!(
-            self,
+        match self {
+            // Anything caused by the line the user just entered
             AnvilError::Parse { .. }
-                | AnvilError::InvalidSyntax { .. }
-                | AnvilError::Type { .. }
-                | AnvilError::Command { .. }
-                | AnvilError::ExternalCommand { .. }
-        )
+            | AnvilError::InvalidSyntax { .. }
+            | AnvilError::Type { .. }
+            | AnvilError::Eval { .. }
+            | AnvilError::Object { .. }
+            | AnvilError::Runtime { .. }
+            | AnvilError::Compilation { .. }
+            | AnvilError::Timeout { .. }
+            | AnvilError::Command { .. }
+            | AnvilError::ExternalCommand { .. }
+            | AnvilError::Shell { .. }
+            | AnvilError::FileNotFound { .. }
+            | AnvilError::PermissionDenied { .. }
+            | AnvilError::FileIo { .. }
+            | AnvilError::Unsupported { .. }
+            | AnvilError::Serde(_) => true,
+
+            // Problems with the shell itself or its environment
+            AnvilError::Io(_)
+            | AnvilError::Config { .. }
+            | AnvilError::Repl { .. }
+            | AnvilError::Interrupted => false,
+        }
     }
 
     /// Returns true if this error should cause the shell to exit
     pub fn is_fatal(&self) -> bool {
-        matches!(
-            self,
-            AnvilError::Interrupted | AnvilError::Io(_)
-        )
+        match self {
+            AnvilError::Interrupted | AnvilError::Io(_) => true,
+
+            AnvilError::Serde(_)
+            | AnvilError::Config { .. }
+            | AnvilError::Repl { .. }
+            | AnvilError::Eval { .. }
+            | AnvilError::Command { .. }
+            | AnvilError::Parse { .. }
+            | AnvilError::Type { .. }
+            | AnvilError::Shell { .. }
+            | AnvilError::Object { .. }
+            | AnvilError::Runtime { .. }
+            | AnvilError::FileNotFound { .. }
+            | AnvilError::PermissionDenied { .. }
+            | AnvilError::FileIo { .. }
+            | AnvilError::InvalidSyntax { .. }
+            | AnvilError::Compilation { .. }
+            | AnvilError::ExternalCommand { .. }
+            | AnvilError::Timeout { .. }
+            | AnvilError::Unsupported { .. } => false,
+        }
+    }
+
+    /// Process exit status to report for this error. External commands
+    /// pass their own status through; failures inside anvil itself use
+    /// `INTERNAL_ERROR_EXIT_CODE` so callers can tell the two apart.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            AnvilError::ExternalCommand { code, .. } if *code > 0 => *code,
+            AnvilError::Interrupted => 130,
+            _ => INTERNAL_ERROR_EXIT_CODE,
+        }
     }
 }
 
+/// Exit status for errors that didn't come from an external command
+pub const INTERNAL_ERROR_EXIT_CODE: i32 = 125;
+
 // Custom display for better error messages in the shell
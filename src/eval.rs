@@ -1,9 +1,14 @@
 use crate::error::{AnvilError, AnvilResult};
-use crate::objects::ShellObject;
-use std::collections::HashMap;
+use crate::objects::{range_items, range_len, resolve_index, ClosureObject, FileObject, FunctionObject, ShellObject};
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
 use syn::{Expr, Lit, BinOp, UnOp};
 use quote::ToTokens;
 
+/// Methods a range answers without turning into an array first
+const LAZY_RANGE_METHODS: &[&str] = &["len", "is_empty", "contains", "sum", "step_by", "map", "filter", "for_each"];
+
 pub struct EvaluationEngine {
     variables: HashMap<String, ShellObject>,
     functions: HashMap<String, ShellObject>,
@@ -27,12 +32,145 @@ impl EvaluationEngine {
     /// Evaluate a Rust expression string
     pub fn evaluate_expression(&self, expr_str: &str) -> AnvilResult<ShellObject> {
         // Parse the expression
-        let expr: Expr = syn::parse_str(expr_str)
+        let expr: Expr = syn::parse_str(&escape_typeof(expr_str))
             .map_err(|e| AnvilError::parse(format!("Failed to parse expression: {}", e)))?;
 
         self.evaluate_expr(&expr)
     }
 
+    /// Evaluate a statement, allowing `let` bindings and assignments to
+    /// mutate the engine's variables
+    pub fn evaluate_statement(&mut self, code: &str) -> AnvilResult<ShellObject> {
+        let code = escape_typeof(code);
+        let code = code.trim().trim_end_matches(';').trim_end();
+
+        if code.starts_with("fn ") {
+            let function = parse_function(code)?;
+            let object = ShellObject::Function(function.clone());
+            self.variables.insert(function.name, object.clone());
+            return Ok(object);
+        }
+
+        if code.starts_with("let ") {
+            let stmt: syn::Stmt = syn::parse_str(&format!("{};", code))
+                .map_err(|e| AnvilError::parse(format!("Failed to parse statement: {}", e)))?;
+            return match stmt {
+                syn::Stmt::Local(local) => self.evaluate_local(&local),
+                _ => Err(AnvilError::eval("Unsupported statement")),
+            };
+        }
+
+        let expr: Expr = syn::parse_str(code)
+            .map_err(|e| AnvilError::parse(format!("Failed to parse expression: {}", e)))?;
+
+        match &expr {
+            Expr::Assign(assign) => self.evaluate_assign(assign),
+            _ => self.evaluate_expr(&expr),
+        }
+    }
+
+    fn evaluate_assign(&mut self, assign: &syn::ExprAssign) -> AnvilResult<ShellObject> {
+        let value = self.evaluate_expr(&assign.right)?;
+        self.assign(&assign.left, value)?;
+        Ok(ShellObject::Unit)
+    }
+
+    /// Run the statements of a block in this engine's scope. The block's
+    /// value is its trailing expression, or unit if it ends in a statement.
+    fn run_block(&mut self, block: &syn::Block) -> AnvilResult<ShellObject> {
+        let mut result = ShellObject::Unit;
+
+        for stmt in &block.stmts {
+            result = match stmt {
+                syn::Stmt::Local(local) => {
+                    self.evaluate_local(local)?;
+                    ShellObject::Unit
+                }
+                syn::Stmt::Expr(expr, semi) => {
+                    let value = match expr {
+                        Expr::Assign(assign) => self.evaluate_assign(assign)?,
+                        _ => self.evaluate_expr(expr)?,
+                    };
+                    if semi.is_some() { ShellObject::Unit } else { value }
+                }
+                _ => return Err(AnvilError::eval(format!(
+                    "Unsupported statement: {}",
+                    stmt.to_token_stream()
+                ))),
+            };
+        }
+
+        Ok(result)
+    }
+
+    fn evaluate_local(&mut self, local: &syn::Local) -> AnvilResult<ShellObject> {
+        let pat = match &local.pat {
+            syn::Pat::Type(pat_type) => &*pat_type.pat,
+            pat => pat,
+        };
+        let name = match pat {
+            syn::Pat::Ident(ident) => ident.ident.to_string(),
+            _ => return Err(AnvilError::eval(format!(
+                "Unsupported let pattern: {}",
+                pat.to_token_stream()
+            ))),
+        };
+        let init = local.init.as_ref()
+            .ok_or_else(|| AnvilError::eval(format!("Variable '{}' must be initialized", name)))?;
+
+        let value = self.evaluate_expr(&init.expr)?;
+        self.variables.insert(name, value.clone());
+        Ok(value)
+    }
+
+    /// Store a value into an assignable place expression
+    fn assign(&mut self, target: &Expr, value: ShellObject) -> AnvilResult<()> {
+        match target {
+            Expr::Path(path) if path.path.segments.len() == 1 => {
+                let name = path.path.segments[0].ident.to_string();
+                self.variables.insert(name, value);
+                Ok(())
+            }
+            Expr::Field(field) => match &field.member {
+                syn::Member::Named(name) => self.place_mut(&field.base)?.set_field(&name.to_string(), value),
+                syn::Member::Unnamed(_) => Err(AnvilError::eval("Tuple field assignment not supported")),
+            },
+            Expr::Index(index) => {
+                let idx = self.evaluate_expr(&index.index)?;
+                self.place_mut(&index.expr)?.set_index(idx, value)
+            }
+            Expr::Paren(paren) => self.assign(&paren.expr, value),
+            _ => Err(AnvilError::eval(format!(
+                "Invalid assignment target: {}",
+                target.to_token_stream()
+            ))),
+        }
+    }
+
+    /// Resolve a place expression to a mutable reference into a variable
+    fn place_mut(&mut self, expr: &Expr) -> AnvilResult<&mut ShellObject> {
+        match expr {
+            Expr::Path(path) if path.path.segments.len() == 1 => {
+                let name = path.path.segments[0].ident.to_string();
+                self.variables.get_mut(&name)
+                    .ok_or_else(|| AnvilError::eval(format!("Unknown identifier: {}", name)))
+            }
+            Expr::Field(field) => match &field.member {
+                syn::Member::Named(name) => self.place_mut(&field.base)?.field_mut(&name.to_string()),
+                syn::Member::Unnamed(_) => Err(AnvilError::eval("Tuple field access not supported")),
+            },
+            Expr::Index(index) => {
+                let idx = self.evaluate_expr(&index.index)?;
+                self.place_mut(&index.expr)?.index_mut(idx)
+            }
+            Expr::Paren(paren) => self.place_mut(&paren.expr),
+            _ => Err(AnvilError::eval(format!(
+                "Invalid assignment target: {}",
+                expr.to_token_stream()
+            ))),
+        }
+    }
+
     /// Evaluate a parsed expression
     pub fn evaluate_expr(&self, expr: &Expr) -> AnvilResult<ShellObject> {
         match expr {
@@ -48,8 +186,12 @@ impl EvaluationEngine {
             Expr::Field(field) => self.evaluate_field_access(field),
             Expr::Index(index) => self.evaluate_index(index),
             Expr::Block(block) => self.evaluate_block(block),
+            Expr::Range(range) => self.evaluate_range(range),
+            Expr::TryBlock(try_block) => self.evaluate_try_block(try_block),
             Expr::If(if_expr) => self.evaluate_if(if_expr),
             Expr::Match(match_expr) => self.evaluate_match(match_expr),
+            Expr::Closure(closure) => self.evaluate_closure(closure),
+            Expr::Macro(mac) => self.evaluate_macro(&mac.mac),
             _ => Err(AnvilError::eval(format!(
                 "Unsupported expression type: {}",
                 expr.to_token_stream()
@@ -57,12 +199,49 @@ impl EvaluationEngine {
         }
     }
 
+    /// Evaluate the formatting macros by their function equivalents
+    fn evaluate_macro(&self, mac: &syn::Macro) -> AnvilResult<ShellObject> {
+        let name = mac.path.get_ident().map(|i| i.to_string()).unwrap_or_default();
+        if !matches!(name.as_str(), "println" | "print" | "format") {
+            return Err(AnvilError::eval(format!("Unsupported macro: {}!", mac.path.to_token_stream())));
+        }
+
+        let args = mac
+            .parse_body_with(syn::punctuated::Punctuated::<Expr, syn::Token![,]>::parse_terminated)
+            .map_err(|e| AnvilError::parse(format!("Invalid arguments to {}!: {}", name, e)))?;
+
+        let mut values = Vec::new();
+        for arg in &args {
+            values.push(self.evaluate_expr(arg)?);
+        }
+
+        let template = match values.first() {
+            Some(ShellObject::String(s)) => s.clone(),
+            Some(other) => return Err(AnvilError::type_error("string literal", other.type_name())),
+            None if name == "println" => {
+                println!();
+                return Ok(ShellObject::Unit);
+            }
+            None => return Err(AnvilError::eval(format!("{}! requires a format string", name))),
+        };
+        let output = format_template(&template, &values[1..])?;
+
+        match name.as_str() {
+            "println" => println!("{}", output),
+            "print" => print!("{}", output),
+            _ => return Ok(ShellObject::String(output)),
+        }
+        Ok(ShellObject::Unit)
+    }
+
     fn evaluate_literal(&self, lit: &Lit) -> AnvilResult<ShellObject> {
         match lit {
             Lit::Str(s) => Ok(ShellObject::String(s.value())),
             Lit::Int(i) => {
+                // syn normalizes hex/octal/binary and `_` separators into
+                // base10_digits, so every Rust integer form parses here
                 let value = i.base10_parse::<i64>()
-                    .map_err(|e| AnvilError::eval(format!("Invalid integer: {}", e)))?;
+                    .map_err(|e| AnvilError::eval(format!("Invalid integer {}: {}", i, e)))?;
                 Ok(ShellObject::Integer(value))
             }
             Lit::Float(f) => {
@@ -94,6 +273,7 @@ impl EvaluationEngine {
         match path_str.as_str() {
             "true" => Ok(ShellObject::Boolean(true)),
             "false" => Ok(ShellObject::Boolean(false)),
+            "None" => Ok(ShellObject::Null),
             _ => Err(AnvilError::eval(format!("Unknown identifier: {}", path_str))),
         }
     }
@@ -115,10 +295,10 @@ impl EvaluationEngine {
             BinOp::BitOr(_) => self.bitor_objects(left, right),
             BinOp::Shl(_) => self.shl_objects(left, right),
             BinOp::Shr(_) => self.shr_objects(left, right),
-            BinOp::Eq(_) => Ok(ShellObject::Boolean(self.eq_objects(&left, &right))),
+            BinOp::Eq(_) => Ok(ShellObject::Boolean(left == right)),
             BinOp::Lt(_) => Ok(ShellObject::Boolean(self.lt_objects(&left, &right)?)),
             BinOp::Le(_) => Ok(ShellObject::Boolean(self.le_objects(&left, &right)?)),
-            BinOp::Ne(_) => Ok(ShellObject::Boolean(!self.eq_objects(&left, &right))),
+            BinOp::Ne(_) => Ok(ShellObject::Boolean(left != right)),
             BinOp::Ge(_) => Ok(ShellObject::Boolean(self.ge_objects(&left, &right)?)),
             BinOp::Gt(_) => Ok(ShellObject::Boolean(self.gt_objects(&left, &right)?)),
             _ => Err(AnvilError::eval(format!("Unsupported binary operator: {:?}", binary.op))),
@@ -131,7 +311,9 @@ impl EvaluationEngine {
         match unary.op {
             UnOp::Not(_) => match operand {
                 ShellObject::Boolean(b) => Ok(ShellObject::Boolean(!b)),
-                _ => Err(AnvilError::type_error("boolean", operand.type_name())),
+                // Like Rust, `!` on an integer flips its bits
+                ShellObject::Integer(i) => Ok(ShellObject::Integer(!i)),
+                _ => Err(AnvilError::type_error("boolean or integer", operand.type_name())),
             },
             UnOp::Neg(_) => match operand {
                 ShellObject::Integer(i) => Ok(ShellObject::Integer(-i)),
@@ -170,16 +352,32 @@ impl EvaluationEngine {
                 .collect::<Vec<_>>()
                 .join("::");
 
+            // Calling a closure or function stored in a variable
+            match self.variables.get(&func_name) {
+                Some(ShellObject::Closure(closure)) => {
+                    let mut args = Vec::new();
+                    for arg in &call.args {
+                        args.push(self.evaluate_expr(arg)?);
+                    }
+                    return self.call_closure(closure, args);
+                }
+                Some(ShellObject::Function(function)) => {
+                    let mut args = Vec::new();
+                    for arg in &call.args {
+                        args.push(self.evaluate_expr(arg)?);
+                    }
+                    return self.call_function(function, args);
+                }
+                _ => {}
+            }
+
             match func_name.as_str() {
                 "println" | "print" => {
-                    let mut output = String::new();
-                    for (i, arg) in call.args.iter().enumerate() {
-                        if i > 0 {
-                            output.push(' ');
-                        }
-                        let value = self.evaluate_expr(arg)?;
-                        output.push_str(&value.to_display_string());
+                    let mut args = Vec::new();
+                    for arg in &call.args {
+                        args.push(self.evaluate_expr(arg)?);
                     }
+                    let output = print_output(&args)?;
                     if func_name == "println" {
                         println!("{}", output);
                     } else {
@@ -188,26 +386,89 @@ impl EvaluationEngine {
                     Ok(ShellObject::Unit)
                 }
                 "format" => {
-                    // Simplified format implementation
                     if call.args.is_empty() {
-                        return Ok(ShellObject::String(String::new()));
+                        return Err(AnvilError::eval("format() requires a format string"));
                     }
-                    let format_str = self.evaluate_expr(&call.args[0])?;
-                    if let ShellObject::String(s) = format_str {
-                        Ok(ShellObject::String(s))
-                    } else {
-                        Ok(ShellObject::String(format_str.to_display_string()))
+                    let template = match self.evaluate_expr(&call.args[0])? {
+                        ShellObject::String(s) => s,
+                        other => return Err(AnvilError::type_error("string", other.type_name())),
+                    };
+                    let mut args = Vec::new();
+                    for arg in call.args.iter().skip(1) {
+                        args.push(self.evaluate_expr(arg)?);
+                    }
+                    Ok(ShellObject::String(format_template(&template, &args)?))
+                }
+                "file" => {
+                    if call.args.len() != 1 {
+                        return Err(AnvilError::eval("file() requires exactly one argument"));
+                    }
+                    match self.evaluate_expr(&call.args[0])? {
+                        ShellObject::String(path) => Ok(ShellObject::File(FileObject::from_path(path)?)),
+                        ShellObject::Path(path) => Ok(ShellObject::File(FileObject::from_path(path.path)?)),
+                        other => Err(AnvilError::type_error("string or path", other.type_name())),
                     }
                 }
+                "parse_csv" => {
+                    if call.args.len() != 2 {
+                        return Err(AnvilError::eval("parse_csv() requires a path and a has_header flag"));
+                    }
+                    let path = match self.evaluate_expr(&call.args[0])? {
+                        ShellObject::String(path) => PathBuf::from(path),
+                        ShellObject::Path(path) => path.path,
+                        other => return Err(AnvilError::type_error("string or path", other.type_name())),
+                    };
+                    let has_header = match self.evaluate_expr(&call.args[1])? {
+                        ShellObject::Boolean(b) => b,
+                        other => return Err(AnvilError::type_error("bool", other.type_name())),
+                    };
+                    parse_csv(&path, has_header)
+                }
+                "parse_toml" => {
+                    if call.args.len() != 1 {
+                        return Err(AnvilError::eval("parse_toml() requires exactly one argument"));
+                    }
+                    match self.evaluate_expr(&call.args[0])? {
+                        ShellObject::String(text) => {
+                            let value: toml::Value = toml::from_str(&text)
+                                .map_err(|e| AnvilError::parse(format!("Invalid TOML: {}", e)))?;
+                            Ok(ShellObject::from_toml(&value))
+                        }
+                        other => Err(AnvilError::type_error("string", other.type_name())),
+                    }
+                }
+                "duration_ms" => {
+                    if call.args.len() != 1 {
+                        return Err(AnvilError::eval("duration_ms() requires exactly one argument"));
+                    }
+                    match self.evaluate_expr(&call.args[0])? {
+                        ShellObject::Integer(ms) if ms >= 0 => Ok(ShellObject::Duration(
+                            std::time::Duration::from_millis(ms as u64)
+                        )),
+                        ShellObject::Integer(ms) => Err(AnvilError::runtime(format!(
+                            "Duration can't be negative: {}ms", ms
+                        ))),
+                        other => Err(AnvilError::type_error("integer", other.type_name())),
+                    }
+                }
+                // Parsed from `typeof(..)`, see escape_typeof
+                "r#typeof" => {
+                    if call.args.len() != 1 {
+                        return Err(AnvilError::eval("typeof() requires exactly one argument"));
+                    }
+                    let arg = self.evaluate_expr(&call.args[0])?;
+                    Ok(ShellObject::String(arg.type_name().to_string()))
+                }
                 "len" => {
                     if call.args.len() != 1 {
                         return Err(AnvilError::eval("len() requires exactly one argument"));
                     }
                     let arg = self.evaluate_expr(&call.args[0])?;
                     match arg {
-                        ShellObject::String(s) => Ok(ShellObject::Integer(s.len() as i64)),
+                        ShellObject::String(s) => Ok(ShellObject::Integer(s.chars().count() as i64)),
                         ShellObject::Array(arr) => Ok(ShellObject::Integer(arr.len() as i64)),
-                        _ => Err(AnvilError::type_error("string or array", arg.type_name())),
+                        ShellObject::Map(map) => Ok(ShellObject::Integer(map.len() as i64)),
+                        _ => Err(AnvilError::type_error("string, array or map", arg.type_name())),
                     }
                 }
                 _ => Err(AnvilError::eval(format!("Unknown function: {}", func_name))),
@@ -218,20 +479,228 @@ impl EvaluationEngine {
     }
 
     fn evaluate_method_call(&self, method_call: &syn::ExprMethodCall) -> AnvilResult<ShellObject> {
-        let receiver = self.evaluate_expr(&method_call.receiver)?;
         let method_name = method_call.method.to_string();
+        let receiver = match self.evaluate_expr(&method_call.receiver) {
+            // unwrap_or falls back on a failed receiver instead of aborting
+            Err(e) if method_name == "unwrap_or" && e.is_recoverable() => ShellObject::Error(e.to_string()),
+            result => result?,
+        };
+        // Ranges answer what they can from their bounds and only become an
+        // array for the methods that need every item
+        let receiver = match receiver {
+            ShellObject::Range { start, end, inclusive, step }
+                if !LAZY_RANGE_METHODS.contains(&method_name.as_str())
+                    && ShellObject::Array(Vec::new()).get_method_names().contains(&method_name) =>
+            {
+                ShellObject::Array(range_items(start, end, inclusive, step).map(ShellObject::Integer).collect())
+            }
+            receiver => receiver,
+        };
 
         match method_name.as_str() {
+            "is_null" | "is_some" => {
+                if !method_call.args.is_empty() {
+                    return Err(AnvilError::eval(format!("{}() takes no arguments", method_name)));
+                }
+                let is_null = matches!(receiver, ShellObject::Null);
+                Ok(ShellObject::Boolean(if method_name == "is_null" { is_null } else { !is_null }))
+            }
+            "unwrap_or" => {
+                if method_call.args.len() != 1 {
+                    return Err(AnvilError::eval("unwrap_or() requires exactly one argument"));
+                }
+                match receiver {
+                    ShellObject::Null | ShellObject::Error(_) => self.evaluate_expr(&method_call.args[0]),
+                    value => Ok(value),
+                }
+            }
+            "to_string" => {
+                if !method_call.args.is_empty() {
+                    return Err(AnvilError::eval("to_string() takes no arguments"));
+                }
+                Ok(ShellObject::String(receiver.to_display_string()))
+            }
+            "parse" | "parse_int" | "parse_float" => {
+                if !method_call.args.is_empty() {
+                    return Err(AnvilError::eval(format!("{}() takes no arguments", method_name)));
+                }
+                let as_int = match method_name.as_str() {
+                    "parse_int" => true,
+                    "parse_float" => false,
+                    _ => {
+                        let target = method_call.turbofish.as_ref()
+                            .map(|turbofish| turbofish.args.to_token_stream().to_string());
+                        match target.as_deref() {
+                            Some("i64") => true,
+                            Some("f64") => false,
+                            Some(other) => return Err(AnvilError::unsupported(format!("parse::<{}>(), use i64 or f64", other))),
+                            None => return Err(AnvilError::eval("parse() needs a type, e.g. parse::<i64>()")),
+                        }
+                    }
+                };
+                let text = match receiver {
+                    ShellObject::String(text) => text,
+                    _ => return Err(AnvilError::eval(format!("Type {} has no method {}", receiver.type_name(), method_name))),
+                };
+                if as_int {
+                    text.parse::<i64>()
+                        .map(ShellObject::Integer)
+                        .map_err(|e| AnvilError::parse(format!("Cannot parse {:?} as an integer: {}", text, e)))
+                } else {
+                    text.parse::<f64>()
+                        .map(ShellObject::Float)
+                        .map_err(|e| AnvilError::parse(format!("Cannot parse {:?} as a float: {}", text, e)))
+                }
+            }
+            "is_error" => {
+                if !method_call.args.is_empty() {
+                    return Err(AnvilError::eval("is_error() takes no arguments"));
+                }
+                Ok(ShellObject::Boolean(matches!(receiver, ShellObject::Error(_))))
+            }
+            "message" => match receiver {
+                ShellObject::Error(message) if method_call.args.is_empty() => Ok(ShellObject::String(message)),
+                ShellObject::Error(_) => Err(AnvilError::eval("message() takes no arguments")),
+                _ => Err(AnvilError::eval(format!("Type {} has no method message", receiver.type_name()))),
+            },
             "len" => match receiver {
-                ShellObject::String(s) => Ok(ShellObject::Integer(s.len() as i64)),
+                ShellObject::String(s) => Ok(ShellObject::Integer(s.chars().count() as i64)),
+                ShellObject::Bytes(bytes) => Ok(ShellObject::Integer(bytes.len() as i64)),
                 ShellObject::Array(arr) => Ok(ShellObject::Integer(arr.len() as i64)),
+                ShellObject::Map(map) => Ok(ShellObject::Integer(map.len() as i64)),
+                ShellObject::Range { start, end, inclusive, step } => i64::try_from(range_len(start, end, inclusive, step))
+                    .map(ShellObject::Integer)
+                    .map_err(|_| AnvilError::runtime("Range is too long to count")),
                 _ => Err(AnvilError::eval(format!("Type {} has no method len", receiver.type_name()))),
             },
             "is_empty" => match receiver {
                 ShellObject::String(s) => Ok(ShellObject::Boolean(s.is_empty())),
+                ShellObject::Bytes(bytes) => Ok(ShellObject::Boolean(bytes.is_empty())),
                 ShellObject::Array(arr) => Ok(ShellObject::Boolean(arr.is_empty())),
+                ShellObject::Map(map) => Ok(ShellObject::Boolean(map.is_empty())),
+                ShellObject::Range { start, end, inclusive, step } => Ok(ShellObject::Boolean(range_len(start, end, inclusive, step) == 0)),
                 _ => Err(AnvilError::eval(format!("Type {} has no method is_empty", receiver.type_name()))),
             },
+            "to_uppercase" | "to_lowercase" | "trim" => match receiver {
+                ShellObject::String(s) => Ok(ShellObject::String(match method_name.as_str() {
+                    "to_uppercase" => s.to_uppercase(),
+                    "to_lowercase" => s.to_lowercase(),
+                    _ => s.trim().to_string(),
+                })),
+                _ => Err(AnvilError::eval(format!("Type {} has no method {}", receiver.type_name(), method_name))),
+            },
+            "encode_utf8" => match receiver {
+                ShellObject::String(s) => Ok(ShellObject::Bytes(s.into_bytes())),
+                _ => Err(AnvilError::eval(format!("Type {} has no method encode_utf8", receiver.type_name()))),
+            },
+            "decode_utf8" => match receiver {
+                ShellObject::Bytes(bytes) => String::from_utf8(bytes)
+                    .map(ShellObject::String)
+                    .map_err(|e| AnvilError::parse(format!("Bytes are not valid UTF-8: {}", e.utf8_error()))),
+                _ => Err(AnvilError::eval(format!("Type {} has no method decode_utf8", receiver.type_name()))),
+            },
+            "split" => {
+                if method_call.args.len() != 1 {
+                    return Err(AnvilError::eval("split() requires exactly one argument"));
+                }
+                let sep = self.evaluate_expr(&method_call.args[0])?;
+                match (receiver, sep) {
+                    (ShellObject::String(s), ShellObject::String(sep)) => Ok(ShellObject::Array(
+                        s.split(sep.as_str()).map(|part| ShellObject::String(part.to_string())).collect()
+                    )),
+                    (ShellObject::String(_), sep) => Err(AnvilError::type_error("string", sep.type_name())),
+                    (receiver, _) => Err(AnvilError::eval(format!("Type {} has no method split", receiver.type_name()))),
+                }
+            }
+            "read" | "lines" => match receiver {
+                ShellObject::File(file) => {
+                    if method_name == "read" {
+                        Ok(ShellObject::String(file.read()?))
+                    } else {
+                        Ok(ShellObject::Array(file.lines()?.into_iter().map(ShellObject::String).collect()))
+                    }
+                }
+                _ => Err(AnvilError::eval(format!("Type {} has no method {}", receiver.type_name(), method_name))),
+            },
+            "write" => {
+                if method_call.args.len() != 1 {
+                    return Err(AnvilError::eval("write() requires exactly one argument"));
+                }
+                let contents = self.evaluate_expr(&method_call.args[0])?;
+                match receiver {
+                    ShellObject::File(file) => {
+                        file.write(&contents.to_display_string())?;
+                        Ok(ShellObject::Unit)
+                    }
+                    _ => Err(AnvilError::eval(format!("Type {} has no method write", receiver.type_name()))),
+                }
+            }
+            "sum" => match receiver {
+                ShellObject::Array(arr) => {
+                    let mut total = ShellObject::Integer(0);
+                    for item in arr {
+                        total = self.add_objects(total, item)?;
+                    }
+                    Ok(total)
+                }
+                ShellObject::Range { start, end, inclusive, step } => {
+                    // Arithmetic series: count * (first + last) / 2
+                    let count = range_len(start, end, inclusive, step) as i128;
+                    let total = if count == 0 { 0 } else { count * (2 * start as i128 + (count - 1) * step as i128) / 2 };
+                    i64::try_from(total)
+                        .map(ShellObject::Integer)
+                        .map_err(|_| AnvilError::runtime(format!("Sum of {} does not fit in an integer", receiver.to_display_string())))
+                }
+                _ => Err(AnvilError::eval(format!("Type {} has no method sum", receiver.type_name()))),
+            },
+            "step_by" => {
+                if method_call.args.len() != 1 {
+                    return Err(AnvilError::eval("step_by() requires exactly one argument"));
+                }
+                let n = match self.evaluate_expr(&method_call.args[0])? {
+                    ShellObject::Integer(n) if n > 0 => n as u64,
+                    ShellObject::Integer(n) => return Err(AnvilError::eval(format!("step_by() needs a positive step, got {}", n))),
+                    other => return Err(AnvilError::type_error("integer", other.type_name())),
+                };
+                match receiver {
+                    ShellObject::Range { start, end, inclusive, step } => Ok(ShellObject::Range {
+                        start,
+                        end,
+                        inclusive,
+                        step: step.checked_mul(n).ok_or_else(|| AnvilError::runtime("Range step is too large"))?,
+                    }),
+                    ShellObject::Array(arr) => Ok(ShellObject::Array(arr.into_iter().step_by(n as usize).collect())),
+                    _ => Err(AnvilError::eval(format!("Type {} has no method step_by", receiver.type_name()))),
+                }
+            }
+            "to_array" => match receiver {
+                ShellObject::Range { start, end, inclusive, step } if method_call.args.is_empty() => {
+                    Ok(ShellObject::Array(range_items(start, end, inclusive, step).map(ShellObject::Integer).collect()))
+                }
+                ShellObject::Range { .. } => Err(AnvilError::eval("to_array() takes no arguments")),
+                _ => Err(AnvilError::eval(format!("Type {} has no method to_array", receiver.type_name()))),
+            },
+            "reverse" => match receiver {
+                ShellObject::Array(mut arr) => {
+                    arr.reverse();
+                    Ok(ShellObject::Array(arr))
+                }
+                _ => Err(AnvilError::eval(format!("Type {} has no method reverse", receiver.type_name()))),
+            },
+            "dedup" => match receiver {
+                ShellObject::Array(mut arr) => {
+                    arr.dedup();
+                    Ok(ShellObject::Array(arr))
+                }
+                _ => Err(AnvilError::eval(format!("Type {} has no method dedup", receiver.type_name()))),
+            },
+            "unique" => match receiver {
+                ShellObject::Array(arr) => {
+                    let mut seen = HashSet::new();
+                    Ok(ShellObject::Array(arr.into_iter().filter(|item| seen.insert(item.clone())).collect()))
+                }
+                _ => Err(AnvilError::eval(format!("Type {} has no method unique", receiver.type_name()))),
+            },
             "push" => {
                 if method_call.args.len() != 1 {
                     return Err(AnvilError::eval("push() requires exactly one argument"));
@@ -252,18 +721,154 @@ impl EvaluationEngine {
                 let key = self.evaluate_expr(&method_call.args[0])?;
                 match (receiver, key) {
                     (ShellObject::Map(map), ShellObject::String(key_str)) => {
-                        Ok(map.get(&key_str).cloned().unwrap_or(ShellObject::Unit))
+                        Ok(map.get(&key_str).cloned().unwrap_or(ShellObject::Null))
                     }
                     (ShellObject::Array(arr), ShellObject::Integer(idx)) => {
                         if idx >= 0 && (idx as usize) < arr.len() {
                             Ok(arr[idx as usize].clone())
                         } else {
-                            Ok(ShellObject::Unit)
+                            Ok(ShellObject::Null)
                         }
                     }
                     _ => Err(AnvilError::eval("Invalid get() operation")),
                 }
             }
+            "join" => {
+                if method_call.args.len() != 1 {
+                    return Err(AnvilError::eval("join() requires exactly one argument"));
+                }
+                let sep = self.evaluate_expr(&method_call.args[0])?;
+                match (receiver, sep) {
+                    (ShellObject::Array(arr), ShellObject::String(sep)) => Ok(ShellObject::String(
+                        arr.iter().map(|item| item.to_display_string()).collect::<Vec<_>>().join(&sep)
+                    )),
+                    (ShellObject::Array(_), sep) => Err(AnvilError::type_error("string", sep.type_name())),
+                    (receiver, _) => Err(AnvilError::eval(format!("Type {} has no method join", receiver.type_name()))),
+                }
+            }
+            "to_toml" => match receiver {
+                ShellObject::Map(_) => Ok(ShellObject::String(receiver.to_toml()?)),
+                _ => Err(AnvilError::eval(format!("Type {} has no method to_toml", receiver.type_name()))),
+            },
+            "to_csv" => match receiver {
+                ShellObject::Array(_) => Ok(ShellObject::String(receiver.to_csv()?)),
+                _ => Err(AnvilError::eval(format!("Type {} has no method to_csv", receiver.type_name()))),
+            },
+            "keys" | "values" | "entries" => {
+                if !method_call.args.is_empty() {
+                    return Err(AnvilError::eval(format!("{}() takes no arguments", method_name)));
+                }
+                let map = match receiver {
+                    ShellObject::Map(map) => map,
+                    _ => return Err(AnvilError::eval(format!("Type {} has no method {}", receiver.type_name(), method_name))),
+                };
+                Ok(ShellObject::Array(map.into_iter().map(|(key, value)| match method_name.as_str() {
+                    "keys" => ShellObject::String(key),
+                    "values" => value,
+                    _ => ShellObject::Array(vec![ShellObject::String(key), value]),
+                }).collect()))
+            }
+            "contains" | "index_of" => {
+                if method_call.args.len() != 1 {
+                    return Err(AnvilError::eval(format!("{}() requires exactly one argument", method_name)));
+                }
+                let needle = self.evaluate_expr(&method_call.args[0])?;
+                match receiver {
+                    ShellObject::Range { start, end, inclusive, step } if method_name == "contains" => {
+                        let inside = match needle {
+                            ShellObject::Integer(n) => {
+                                let below_end = if inclusive { n <= end } else { n < end };
+                                n >= start && below_end && (n as i128 - start as i128) % step as i128 == 0
+                            }
+                            _ => false,
+                        };
+                        Ok(ShellObject::Boolean(inside))
+                    }
+                    ShellObject::Array(arr) => {
+                        let position = arr.iter().position(|item| *item == needle);
+                        if method_name == "contains" {
+                            Ok(ShellObject::Boolean(position.is_some()))
+                        } else {
+                            Ok(ShellObject::Integer(position.map_or(-1, |i| i as i64)))
+                        }
+                    }
+                    _ => Err(AnvilError::eval(format!("Type {} has no method {}", receiver.type_name(), method_name))),
+                }
+            }
+            "sort" => {
+                if !method_call.args.is_empty() {
+                    return Err(AnvilError::eval("sort() takes no arguments"));
+                }
+                match receiver {
+                    ShellObject::Array(arr) => {
+                        let keyed = arr.into_iter().map(|item| (item.clone(), item)).collect();
+                        Ok(ShellObject::Array(self.sort_keyed(keyed)?))
+                    }
+                    _ => Err(AnvilError::eval(format!("Type {} has no method sort", receiver.type_name()))),
+                }
+            }
+            "sort_by_key" => {
+                if method_call.args.len() != 1 {
+                    return Err(AnvilError::eval("sort_by_key() requires exactly one closure argument"));
+                }
+                let closure = match self.evaluate_expr(&method_call.args[0])? {
+                    ShellObject::Closure(closure) => closure,
+                    other => return Err(AnvilError::type_error("closure", other.type_name())),
+                };
+                match receiver {
+                    ShellObject::Array(arr) => {
+                        let mut keyed = Vec::with_capacity(arr.len());
+                        for item in arr {
+                            keyed.push((self.call_closure(&closure, vec![item.clone()])?, item));
+                        }
+                        Ok(ShellObject::Array(self.sort_keyed(keyed)?))
+                    }
+                    _ => Err(AnvilError::eval(format!("Type {} has no method sort_by_key", receiver.type_name()))),
+                }
+            }
+            "map" | "filter" | "for_each" => {
+                if method_call.args.len() != 1 {
+                    return Err(AnvilError::eval(format!("{}() requires exactly one closure argument", method_name)));
+                }
+                let closure = match self.evaluate_expr(&method_call.args[0])? {
+                    ShellObject::Closure(closure) => closure,
+                    other => return Err(AnvilError::type_error("closure", other.type_name())),
+                };
+                let arr: Box<dyn Iterator<Item = ShellObject>> = match receiver {
+                    ShellObject::Array(arr) => Box::new(arr.into_iter()),
+                    ShellObject::Range { start, end, inclusive, step } => {
+                        Box::new(range_items(start, end, inclusive, step).map(ShellObject::Integer))
+                    }
+                    _ => return Err(AnvilError::eval(format!("Type {} has no method {}", receiver.type_name(), method_name))),
+                };
+
+                match method_name.as_str() {
+                    "map" => {
+                        let mut mapped = Vec::with_capacity(arr.size_hint().0);
+                        for item in arr {
+                            mapped.push(self.call_closure(&closure, vec![item])?);
+                        }
+                        Ok(ShellObject::Array(mapped))
+                    }
+                    "filter" => {
+                        let mut kept = Vec::new();
+                        for item in arr {
+                            match self.call_closure(&closure, vec![item.clone()])? {
+                                ShellObject::Boolean(true) => kept.push(item),
+                                ShellObject::Boolean(false) => {}
+                                other => return Err(AnvilError::type_error("boolean", other.type_name())),
+                            }
+                        }
+                        Ok(ShellObject::Array(kept))
+                    }
+                    _ => {
+                        for item in arr {
+                            self.call_closure(&closure, vec![item])?;
+                        }
+                        Ok(ShellObject::Unit)
+                    }
+                }
+            }
             _ => {
                 // Try to get field from the object
                 receiver.get_field(&method_name)
@@ -271,6 +876,75 @@ impl EvaluationEngine {
         }
     }
 
+    fn evaluate_closure(&self, closure: &syn::ExprClosure) -> AnvilResult<ShellObject> {
+        let mut params = Vec::new();
+        for input in &closure.inputs {
+            let pat = match input {
+                syn::Pat::Type(pat_type) => &*pat_type.pat,
+                pat => pat,
+            };
+            match pat {
+                syn::Pat::Ident(ident) => params.push(ident.ident.to_string()),
+                syn::Pat::Wild(_) => params.push("_".to_string()),
+                _ => return Err(AnvilError::eval(format!(
+                    "Unsupported closure parameter: {}",
+                    pat.to_token_stream()
+                ))),
+            }
+        }
+
+        Ok(ShellObject::Closure(ClosureObject {
+            params,
+            body: closure.body.to_token_stream().to_string(),
+        }))
+    }
+
+    /// Apply a closure to arguments, evaluating its body in a child scope
+    pub fn call_closure(&self, closure: &ClosureObject, args: Vec<ShellObject>) -> AnvilResult<ShellObject> {
+        if args.len() != closure.params.len() {
+            return Err(AnvilError::eval(format!(
+                "Closure expects {} argument(s), got {}",
+                closure.params.len(),
+                args.len()
+            )));
+        }
+
+        let body: Expr = syn::parse_str(&closure.body)
+            .map_err(|e| AnvilError::parse(format!("Failed to parse closure body: {}", e)))?;
+
+        let mut scope = self.variables.clone();
+        for (param, arg) in closure.params.iter().zip(args) {
+            if param != "_" {
+                scope.insert(param.clone(), arg);
+            }
+        }
+
+        EvaluationEngine::with_variables(scope).evaluate_expr(&body)
+    }
+
+    /// Call a user-defined function, binding arguments to its parameters
+    /// and running the body in a child scope
+    pub fn call_function(&self, function: &FunctionObject, args: Vec<ShellObject>) -> AnvilResult<ShellObject> {
+        if args.len() != function.params.len() {
+            return Err(AnvilError::eval(format!(
+                "Function {} expects {} argument(s), got {}",
+                function.name,
+                function.params.len(),
+                args.len()
+            )));
+        }
+
+        let body: syn::Block = syn::parse_str(&function.body)
+            .map_err(|e| AnvilError::parse(format!("Failed to parse body of {}: {}", function.name, e)))?;
+
+        let mut scope = self.variables.clone();
+        for (param, arg) in function.params.iter().zip(args) {
+            scope.insert(param.clone(), arg);
+        }
+
+        EvaluationEngine::with_variables(scope).run_block(&body)
+    }
+
     fn evaluate_field_access(&self, field: &syn::ExprField) -> AnvilResult<ShellObject> {
         let base = self.evaluate_expr(&field.base)?;
         
@@ -287,30 +961,61 @@ impl EvaluationEngine {
 
         match (base, index_val) {
             (ShellObject::Array(arr), ShellObject::Integer(idx)) => {
-                if idx >= 0 && (idx as usize) < arr.len() {
-                    Ok(arr[idx as usize].clone())
-                } else {
-                    Err(AnvilError::runtime(format!("Index {} out of bounds for array of length {}", idx, arr.len())))
+                match resolve_index(idx, arr.len()) {
+                    Some(i) => Ok(arr[i].clone()),
+                    None => Err(AnvilError::runtime(format!("Index {} out of bounds for array of length {}", idx, arr.len()))),
+                }
+            }
+            (ShellObject::Range { start, end, inclusive, step }, ShellObject::Integer(idx)) => {
+                let len = range_len(start, end, inclusive, step);
+                match usize::try_from(len).ok().and_then(|len| resolve_index(idx, len)) {
+                    Some(i) => Ok(ShellObject::Integer((start as i128 + i as i128 * step as i128) as i64)),
+                    None => Err(AnvilError::runtime(format!("Index {} out of bounds for range of length {}", idx, len))),
                 }
             }
             (ShellObject::Map(map), ShellObject::String(key)) => {
-                Ok(map.get(&key).cloned().unwrap_or(ShellObject::Unit))
+                Ok(map.get(&key).cloned().unwrap_or(ShellObject::Null))
             }
             (ShellObject::String(s), ShellObject::Integer(idx)) => {
-                if idx >= 0 && (idx as usize) < s.len() {
-                    let chars: Vec<char> = s.chars().collect();
-                    Ok(ShellObject::String(chars[idx as usize].to_string()))
-                } else {
-                    Err(AnvilError::runtime(format!("Index {} out of bounds for string of length {}", idx, s.len())))
+                let chars: Vec<char> = s.chars().collect();
+                match resolve_index(idx, chars.len()) {
+                    Some(i) => Ok(ShellObject::String(chars[i].to_string())),
+                    None => Err(AnvilError::runtime(format!("Index {} out of bounds for string of length {}", idx, chars.len()))),
                 }
             }
             _ => Err(AnvilError::eval("Invalid index operation")),
         }
     }
 
-    fn evaluate_block(&self, _block: &syn::ExprBlock) -> AnvilResult<ShellObject> {
-        // Block evaluation would require more complex state management
-        Err(AnvilError::eval("Block expressions not supported in simple evaluation"))
+    fn evaluate_range(&self, range: &syn::ExprRange) -> AnvilResult<ShellObject> {
+        let bound = |expr: &Option<Box<Expr>>| match expr {
+            Some(expr) => match self.evaluate_expr(expr)? {
+                ShellObject::Integer(n) => Ok(n),
+                other => Err(AnvilError::type_error("integer", other.type_name())),
+            },
+            None => Err(AnvilError::eval("Ranges need both a start and an end")),
+        };
+        Ok(ShellObject::Range {
+            start: bound(&range.start)?,
+            end: bound(&range.end)?,
+            inclusive: matches!(range.limits, syn::RangeLimits::Closed(_)),
+            step: 1,
+        })
+    }
+
+    fn evaluate_block(&self, block: &syn::ExprBlock) -> AnvilResult<ShellObject> {
+        // Bindings made inside the block don't leak out of it
+        EvaluationEngine::with_variables(self.variables.clone()).run_block(&block.block)
+    }
+
+    /// Run a `try { ... }` block, turning a recoverable failure inside it
+    /// into an Error object rather than aborting the whole expression
+    fn evaluate_try_block(&self, try_block: &syn::ExprTryBlock) -> AnvilResult<ShellObject> {
+        let mut engine = EvaluationEngine::with_variables(self.variables.clone());
+        match engine.run_block(&try_block.block) {
+            Err(e) if e.is_recoverable() => Ok(ShellObject::Error(e.to_string())),
+            result => result,
+        }
     }
 
     fn evaluate_if(&self, _if_expr: &syn::ExprIf) -> AnvilResult<ShellObject> {
@@ -399,10 +1104,31 @@ impl EvaluationEngine {
                 if b == 0 {
                     Err(AnvilError::runtime("Division by zero"))
                 } else {
-                    Ok(ShellObject::Integer(a % b))
+                    Ok(ShellObject::Integer(a.wrapping_rem(b)))
+                }
+            }
+            (ShellObject::Float(a), ShellObject::Float(b)) => {
+                if b == 0.0 {
+                    Err(AnvilError::runtime("Division by zero"))
+                } else {
+                    Ok(ShellObject::Float(a % b))
+                }
+            }
+            (ShellObject::Integer(a), ShellObject::Float(b)) => {
+                if b == 0.0 {
+                    Err(AnvilError::runtime("Division by zero"))
+                } else {
+                    Ok(ShellObject::Float(a as f64 % b))
+                }
+            }
+            (ShellObject::Float(a), ShellObject::Integer(b)) => {
+                if b == 0 {
+                    Err(AnvilError::runtime("Division by zero"))
+                } else {
+                    Ok(ShellObject::Float(a % b as f64))
                 }
             }
-            (a, b) => Err(AnvilError::type_error("integer types for remainder", &format!("{} % {}", a.type_name(), b.type_name()))),
+            (a, b) => Err(AnvilError::type_error("numeric types for remainder", &format!("{} % {}", a.type_name(), b.type_name()))),
         }
     }
 
@@ -470,16 +1196,28 @@ impl EvaluationEngine {
     }
 
     // Comparison operations
-    fn eq_objects(&self, left: &ShellObject, right: &ShellObject) -> bool {
-        match (left, right) {
-            (ShellObject::Integer(a), ShellObject::Integer(b)) => a == b,
-            (ShellObject::Float(a), ShellObject::Float(b)) => a == b,
-            (ShellObject::Integer(a), ShellObject::Float(b)) => *a as f64 == *b,
-            (ShellObject::Float(a), ShellObject::Integer(b)) => *a == *b as f64,
-            (ShellObject::String(a), ShellObject::String(b)) => a == b,
-            (ShellObject::Boolean(a), ShellObject::Boolean(b)) => a == b,
-            (ShellObject::Unit, ShellObject::Unit) => true,
-            _ => false,
+    /// Stable-sort items by their paired keys, failing if any two keys
+    /// can't be compared
+    fn sort_keyed(&self, mut keyed: Vec<(ShellObject, ShellObject)>) -> AnvilResult<Vec<ShellObject>> {
+        let mut error = None;
+        keyed.sort_by(|(a, _), (b, _)| {
+            if error.is_some() {
+                return Ordering::Equal;
+            }
+            match (self.lt_objects(a, b), self.lt_objects(b, a)) {
+                (Ok(true), _) => Ordering::Less,
+                (_, Ok(true)) => Ordering::Greater,
+                (Ok(false), Ok(false)) => Ordering::Equal,
+                (Err(e), _) | (_, Err(e)) => {
+                    error = Some(e);
+                    Ordering::Equal
+                }
+            }
+        });
+
+        match error {
+            Some(e) => Err(e),
+            None => Ok(keyed.into_iter().map(|(_, item)| item).collect()),
         }
     }
 
@@ -490,12 +1228,22 @@ impl EvaluationEngine {
             (ShellObject::Integer(a), ShellObject::Float(b)) => Ok((*a as f64) < *b),
             (ShellObject::Float(a), ShellObject::Integer(b)) => Ok(*a < (*b as f64)),
             (ShellObject::String(a), ShellObject::String(b)) => Ok(a < b),
+            // Lexicographic like Vec: the first differing element decides,
+            // otherwise the shorter array comes first
+            (ShellObject::Array(a), ShellObject::Array(b)) => {
+                for (x, y) in a.iter().zip(b) {
+                    if x != y {
+                        return self.lt_objects(x, y);
+                    }
+                }
+                Ok(a.len() < b.len())
+            }
             (a, b) => Err(AnvilError::type_error("comparable types", &format!("{} < {}", a.type_name(), b.type_name()))),
         }
     }
 
     fn le_objects(&self, left: &ShellObject, right: &ShellObject) -> AnvilResult<bool> {
-        Ok(self.lt_objects(left, right)? || self.eq_objects(left, right))
+        Ok(self.lt_objects(left, right)? || left == right)
     }
 
     fn gt_objects(&self, left: &ShellObject, right: &ShellObject) -> AnvilResult<bool> {
@@ -526,6 +1274,10 @@ impl EvaluationEngine {
     pub fn variables(&self) -> &HashMap<String, ShellObject> {
         &self.variables
     }
+
+    pub fn into_variables(self) -> HashMap<String, ShellObject> {
+        self.variables
+    }
 }
 
 impl Default for EvaluationEngine {
@@ -534,32 +1286,316 @@ impl Default for EvaluationEngine {
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn test_literal_evaluation() {
-        let engine = EvaluationEngine::new();
-        
-        let result = engine.evaluate_expression("42").unwrap();
-        assert!(matches!(result, ShellObject::Integer(42)));
-        
-        let result = engine.evaluate_expression("3.14").unwrap();
-        assert!(matches!(result, ShellObject::Float(f) if (f - 3.14).abs() < f64::EPSILON));
-        
-        let result = engine.evaluate_expression("\"hello\"").unwrap();
-        assert!(matches!(result, ShellObject::String(s) if s == "hello"));
-        
-        let result = engine.evaluate_expression("true").unwrap();
-        assert!(matches!(result, ShellObject::Boolean(true)));
-    }
+/// Substitute `{}` and `{N}` placeholders in a format string with the
+/// display form of `args`. `{{` and `}}` produce literal braces, and every
+/// argument must be used, as with Rust's `format!`.
+pub fn format_template(template: &str, args: &[ShellObject]) -> AnvilResult<String> {
+    let mut output = String::new();
+    let mut used = vec![false; args.len()];
+    let mut next = 0;
+    let mut chars = template.chars().peekable();
 
-    #[test]
-    fn test_arithmetic_operations() {
-        let engine = EvaluationEngine::new();
-        
-        let result = engine.evaluate_expression("2 + 3").unwrap();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                output.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                output.push('}');
+            }
+            '{' => {
+                let mut spec = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => spec.push(c),
+                        None => return Err(AnvilError::eval(format!(
+                            "Unterminated placeholder in format string: {}", template
+                        ))),
+                    }
+                }
+                let index = if spec.trim().is_empty() {
+                    next += 1;
+                    next - 1
+                } else {
+                    spec.trim().parse::<usize>().map_err(|_| AnvilError::eval(format!(
+                        "Invalid placeholder {{{}}} in format string", spec
+                    )))?
+                };
+                let arg = args.get(index).ok_or_else(|| AnvilError::eval(format!(
+                    "Format string refers to argument {} but only {} given",
+                    index,
+                    args.len()
+                )))?;
+                used[index] = true;
+                output.push_str(&arg.to_display_string());
+            }
+            '}' => return Err(AnvilError::eval(format!(
+                "Unmatched '}}' in format string: {}", template
+            ))),
+            c => output.push(c),
+        }
+    }
+
+    if let Some(unused) = used.iter().position(|u| !u) {
+        return Err(AnvilError::eval(format!(
+            "Argument {} is never used by the format string", unused
+        )));
+    }
+
+    Ok(output)
+}
+
+/// Read a CSV file into an array of rows. With a header each row is a map
+/// keyed by column name, otherwise an array of fields.
+fn parse_csv(path: &Path, has_header: bool) -> AnvilResult<ShellObject> {
+    let csv_error = |e: csv::Error| AnvilError::parse(format!("{}: {}", path.display(), e));
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(has_header)
+        .flexible(true)
+        .from_path(path)
+        .map_err(csv_error)?;
+
+    let headers: Vec<String> = if has_header {
+        reader.headers().map_err(csv_error)?.iter().map(str::to_string).collect()
+    } else {
+        Vec::new()
+    };
+
+    let mut rows = Vec::new();
+    for record in reader.records() {
+        let record = record.map_err(csv_error)?;
+        let fields = record.iter().map(|field| ShellObject::String(field.to_string()));
+        rows.push(if has_header {
+            ShellObject::Map(headers.iter().cloned().zip(fields).collect())
+        } else {
+            ShellObject::Array(fields.collect())
+        });
+    }
+
+    Ok(ShellObject::Array(rows))
+}
+
+/// Render `println`/`print` arguments. A leading string with placeholders
+/// is used as a format template; otherwise the arguments are space-joined.
+fn print_output(args: &[ShellObject]) -> AnvilResult<String> {
+    if let Some(ShellObject::String(template)) = args.first() {
+        if has_placeholder(template) {
+            return format_template(template, &args[1..]);
+        }
+    }
+
+    Ok(args.iter().map(|a| a.to_display_string()).collect::<Vec<_>>().join(" "))
+}
+
+/// Whether a string contains a `{}` or `{N}` placeholder (escaped `{{`
+/// doesn't count)
+fn has_placeholder(template: &str) -> bool {
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '{' {
+            continue;
+        }
+        if chars.peek() == Some(&'{') {
+            chars.next();
+            continue;
+        }
+        let mut spec = String::new();
+        for c in chars.by_ref() {
+            if c == '}' {
+                let spec = spec.trim();
+                if spec.is_empty() || spec.chars().all(|c| c.is_ascii_digit()) {
+                    return true;
+                }
+                break;
+            }
+            spec.push(c);
+        }
+    }
+    false
+}
+
+/// `typeof` is a reserved word in Rust, so spell the builtin as the raw
+/// identifier `r#typeof` before handing the code to syn. String and char
+/// literals are left alone.
+fn escape_typeof(code: &str) -> String {
+    let is_ident = |c: char| c.is_alphanumeric() || c == '_';
+    let mut escaped = String::with_capacity(code.len());
+    let mut rest = code;
+    let mut prev = None;
+
+    while let Some(c) = rest.chars().next() {
+        let len = match literal_len(rest) {
+            Some(len) => len,
+            None => {
+                if rest.starts_with("typeof")
+                    && !prev.is_some_and(|p| is_ident(p) || p == '#')
+                    && !rest[6..].starts_with(is_ident)
+                {
+                    escaped.push_str("r#");
+                }
+                c.len_utf8()
+            }
+        };
+        escaped.push_str(&rest[..len]);
+        prev = rest[..len].chars().last();
+        rest = &rest[len..];
+    }
+    escaped
+}
+
+/// Length of the string or char literal `text` starts with, if any. An
+/// unterminated string runs to the end; lifetimes and labels aren't literals.
+fn literal_len(text: &str) -> Option<usize> {
+    let mut chars = text.char_indices().skip(1);
+    if text.starts_with('"') {
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '\\' => {
+                    chars.next();
+                }
+                '"' => return Some(i + 1),
+                _ => {}
+            }
+        }
+        return Some(text.len());
+    }
+    if text.starts_with('\'') {
+        return match chars.next()? {
+            // Escapes are ASCII, so the closing quote follows the escaped char
+            (_, '\\') => text.get(3..)?.find('\'').map(|end| end + 4),
+            (_, c) => text[1 + c.len_utf8()..].starts_with('\'').then_some(2 + c.len_utf8()),
+        };
+    }
+    None
+}
+
+/// Parse a shell function definition like `fn greet(name) { ... }`.
+/// Parameter types and a return type are allowed but ignored.
+fn parse_function(code: &str) -> AnvilResult<FunctionObject> {
+    let invalid = || AnvilError::parse(format!("Invalid function definition: {}", code));
+
+    let rest = code.strip_prefix("fn ").ok_or_else(invalid)?;
+    let open = rest.find('(').ok_or_else(invalid)?;
+    let close = open + closing_paren(&rest[open..]).ok_or_else(invalid)?;
+
+    let name = rest[..open].trim();
+    syn::parse_str::<syn::Ident>(name).map_err(|_| invalid())?;
+
+    let mut params = Vec::new();
+    for param in split_params(&rest[open + 1..close]).into_iter().map(str::trim).filter(|p| !p.is_empty()) {
+        let param = param.split(':').next().unwrap_or_default().trim();
+        syn::parse_str::<syn::Ident>(param).map_err(|_| invalid())?;
+        params.push(param.to_string());
+    }
+
+    let after = &rest[close + 1..];
+    let body = after[after.find('{').ok_or_else(invalid)?..].trim();
+    syn::parse_str::<syn::Block>(body)
+        .map_err(|e| AnvilError::parse(format!("Invalid body for function {}: {}", name, e)))?;
+
+    Ok(FunctionObject {
+        name: name.to_string(),
+        signature: format!("fn {}({})", name, params.join(", ")),
+        params,
+        body: body.to_string(),
+    })
+}
+
+/// Byte index of the `)` closing the `(` that `text` starts with
+fn closing_paren(text: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in text.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Split a parameter list on the commas between parameters, leaving the
+/// ones inside tuple, array and generic types alone
+fn split_params(list: &str) -> Vec<&str> {
+    let mut params = Vec::new();
+    let mut depth = 0i32;
+    let mut start = 0;
+    let mut prev = ' ';
+    for (i, c) in list.char_indices() {
+        match c {
+            '(' | '[' | '<' => depth += 1,
+            ')' | ']' => depth -= 1,
+            // The arrow of a `fn(i64) -> i64` type doesn't close anything
+            '>' if prev != '-' => depth -= 1,
+            ',' if depth == 0 => {
+                params.push(&list[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+        prev = c;
+    }
+    params.push(&list[start..]);
+    params
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_literal_evaluation() {
+        let engine = EvaluationEngine::new();
+        
+        let result = engine.evaluate_expression("42").unwrap();
+        assert!(matches!(result, ShellObject::Integer(42)));
+        
+        let result = engine.evaluate_expression("3.14").unwrap();
+        assert!(matches!(result, ShellObject::Float(f) if (f - 3.14).abs() < f64::EPSILON));
+        
+        let result = engine.evaluate_expression("\"hello\"").unwrap();
+        assert!(matches!(result, ShellObject::String(s) if s == "hello"));
+        
+        let result = engine.evaluate_expression("true").unwrap();
+        assert!(matches!(result, ShellObject::Boolean(true)));
+    }
+
+    #[test]
+    fn test_integer_literal_forms() {
+        let engine = EvaluationEngine::new();
+
+        for (code, expected) in [
+            ("0xff", 255),
+            ("0xFF_FF", 65535),
+            ("0o17", 15),
+            ("0b1010", 10),
+            ("0b1111_0000", 240),
+            ("1_000_000", 1_000_000),
+            ("0x10u8", 16),
+            ("-0x10", -16),
+        ] {
+            let result = engine.evaluate_expression(code).unwrap();
+            assert!(matches!(result, ShellObject::Integer(n) if n == expected), "{}", code);
+        }
+
+        let err = engine.evaluate_expression("0xFFFF_FFFF_FFFF_FFFF").unwrap_err();
+        assert!(err.to_string().contains("0xFFFF_FFFF_FFFF_FFFF"));
+    }
+
+    #[test]
+    fn test_arithmetic_operations() {
+        let engine = EvaluationEngine::new();
+        
+        let result = engine.evaluate_expression("2 + 3").unwrap();
         assert!(matches!(result, ShellObject::Integer(5)));
         
         let result = engine.evaluate_expression("10 - 4").unwrap();
@@ -572,6 +1608,80 @@ mod tests {
         assert!(matches!(result, ShellObject::Float(f) if (f - 5.0).abs() < f64::EPSILON));
     }
 
+    #[test]
+    fn test_not_operator() {
+        let engine = EvaluationEngine::new();
+
+        assert_eq!(engine.evaluate_expression("!true").unwrap(), ShellObject::Boolean(false));
+        assert_eq!(engine.evaluate_expression("!5").unwrap(), ShellObject::Integer(-6));
+        assert_eq!(engine.evaluate_expression("!!5").unwrap(), ShellObject::Integer(5));
+        assert_eq!(engine.evaluate_expression("!0").unwrap(), ShellObject::Integer(-1));
+        assert!(engine.evaluate_expression("!1.5").is_err());
+        assert!(engine.evaluate_expression("!\"a\"").is_err());
+    }
+
+    #[test]
+    fn test_typeof() {
+        let mut engine = EvaluationEngine::new();
+        let eval = |engine: &EvaluationEngine, expr: &str| engine.evaluate_expression(expr).unwrap();
+
+        assert_eq!(eval(&engine, "typeof(42)"), ShellObject::String("Integer".to_string()));
+        assert_eq!(eval(&engine, "typeof(\"typeof(x)\")"), ShellObject::String("String".to_string()));
+        assert_eq!(eval(&engine, "typeof([1].get(5))"), ShellObject::String("Null".to_string()));
+        assert_eq!(eval(&engine, "typeof(1.5) == \"Float\""), ShellObject::Boolean(true));
+
+        engine.evaluate_statement("let kind = typeof(0..3)").unwrap();
+        assert_eq!(eval(&engine, "kind"), ShellObject::String("Range".to_string()));
+        assert!(engine.evaluate_expression("typeof()").is_err());
+
+        // Quotes inside string and char literals don't hide a later typeof
+        assert_eq!(escape_typeof("('\"', typeof(x))"), "('\"', r#typeof(x))");
+        assert_eq!(escape_typeof("['\\'', typeof('a')]"), "['\\'', r#typeof('a')]");
+        assert_eq!(escape_typeof("|s: &'a str| typeof(s)"), "|s: &'a str| r#typeof(s)");
+        assert_eq!(escape_typeof("\"typeof(\" + my_typeof(x)"), "\"typeof(\" + my_typeof(x)");
+    }
+
+    #[test]
+    fn test_string_conversions() {
+        let engine = EvaluationEngine::new();
+        let eval = |expr: &str| engine.evaluate_expression(expr).unwrap();
+
+        assert_eq!(eval("42.to_string()"), ShellObject::String("42".to_string()));
+        assert_eq!(eval("[1, 2].to_string()"), ShellObject::String("[1, 2]".to_string()));
+        assert_eq!(eval("\"x\".to_string()"), ShellObject::String("x".to_string()));
+
+        assert_eq!(eval("\"42\".parse_int()"), ShellObject::Integer(42));
+        assert_eq!(eval("\"-7\".parse::<i64>()"), ShellObject::Integer(-7));
+        assert_eq!(eval("\"2.5\".parse_float()"), ShellObject::Float(2.5));
+        assert_eq!(eval("\"3\".parse::<f64>()"), ShellObject::Float(3.0));
+        assert_eq!(eval("\"21\".parse_int() * 2"), ShellObject::Integer(42));
+        assert_eq!(eval("\"x\".parse_int().unwrap_or(0)"), ShellObject::Integer(0));
+
+        let err = engine.evaluate_expression("\"4x\".parse_int()").unwrap_err();
+        assert!(matches!(err, AnvilError::Parse { .. }), "{}", err);
+        assert!(engine.evaluate_expression("\"1.5\".parse::<i64>()").is_err());
+        assert!(engine.evaluate_expression("\"1\".parse()").is_err());
+        assert!(engine.evaluate_expression("\"1\".parse::<u8>()").is_err());
+        assert!(engine.evaluate_expression("1.parse_int()").is_err());
+    }
+
+    #[test]
+    fn test_remainder() {
+        let engine = EvaluationEngine::new();
+
+        assert_eq!(engine.evaluate_expression("7 % 3").unwrap(), ShellObject::Integer(1));
+        assert_eq!(engine.evaluate_expression("-7 % 3").unwrap(), ShellObject::Integer(-1));
+        assert_eq!(engine.evaluate_expression("5.5 % 2.0").unwrap(), ShellObject::Float(1.5));
+        assert_eq!(engine.evaluate_expression("7 % 2.5").unwrap(), ShellObject::Float(2.0));
+        assert_eq!(engine.evaluate_expression("7.5 % 2").unwrap(), ShellObject::Float(1.5));
+
+        for expr in ["1 % 0", "1.5 % 0.0", "1 % 0.0", "1.5 % 0"] {
+            let err = engine.evaluate_expression(expr).unwrap_err();
+            assert!(err.to_string().contains("Division by zero"), "{}: {}", expr, err);
+        }
+        assert!(engine.evaluate_expression("\"a\" % 2").is_err());
+    }
+
     #[test]
     fn test_string_operations() {
         let engine = EvaluationEngine::new();
@@ -595,6 +1705,86 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_negative_indexing() {
+        let mut engine = EvaluationEngine::new();
+
+        let result = engine.evaluate_expression("[1, 2, 3][-1]").unwrap();
+        assert!(matches!(result, ShellObject::Integer(3)));
+
+        let result = engine.evaluate_expression("\"abc\"[-2]").unwrap();
+        assert!(matches!(result, ShellObject::String(s) if s == "b"));
+
+        let err = engine.evaluate_expression("[1, 2, 3][-4]").unwrap_err();
+        assert!(matches!(err, AnvilError::Runtime { .. }));
+        let err = engine.evaluate_expression("\"abc\"[3]").unwrap_err();
+        assert!(matches!(err, AnvilError::Runtime { .. }));
+
+        engine.evaluate_statement("let xs = [1, 2, 3];").unwrap();
+        engine.evaluate_statement("xs[-1] = 9").unwrap();
+        let result = engine.evaluate_expression("xs").unwrap();
+        assert_eq!(result.to_display_string(), "[1, 2, 9]");
+    }
+
+    #[test]
+    fn test_unicode_string_indexing() {
+        let engine = EvaluationEngine::new();
+
+        let result = engine.evaluate_expression("\"héllo\"[1]").unwrap();
+        assert!(matches!(result, ShellObject::String(s) if s == "é"));
+
+        let result = engine.evaluate_expression("\"héllo\"[4]").unwrap();
+        assert!(matches!(result, ShellObject::String(s) if s == "o"));
+        assert!(engine.evaluate_expression("\"héllo\"[5]").is_err());
+
+        let result = engine.evaluate_expression("\"héllo\".len()").unwrap();
+        assert!(matches!(result, ShellObject::Integer(5)));
+        let result = engine.evaluate_expression("len(\"日本\")").unwrap();
+        assert!(matches!(result, ShellObject::Integer(2)));
+    }
+
+    #[test]
+    fn test_array_comparison() {
+        let engine = EvaluationEngine::new();
+        let eval = |expr: &str| engine.evaluate_expression(expr).unwrap();
+
+        assert_eq!(eval("[1, 2] == [1, 2]"), ShellObject::Boolean(true));
+        assert_eq!(eval("[1, 2] == [1, 2.0]"), ShellObject::Boolean(true));
+        assert_eq!(eval("[1, 2] != [1, 3]"), ShellObject::Boolean(true));
+        assert_eq!(eval("(1, \"a\") == (1, \"a\")"), ShellObject::Boolean(true));
+
+        // Element-differing arrays compare at the first difference
+        assert_eq!(eval("[1] < [2]"), ShellObject::Boolean(true));
+        assert_eq!(eval("[1, 9] < [2, 0]"), ShellObject::Boolean(true));
+        assert_eq!(eval("[[1, 2], [3]] > [[1, 2], [2, 9]]"), ShellObject::Boolean(true));
+
+        // A prefix sorts before the longer array
+        assert_eq!(eval("[1, 2] < [1, 2, 0]"), ShellObject::Boolean(true));
+        assert_eq!(eval("[] < [0]"), ShellObject::Boolean(true));
+        assert_eq!(eval("[1, 2] <= [1, 2]"), ShellObject::Boolean(true));
+        assert_eq!(eval("[1, 2] < [1, 2]"), ShellObject::Boolean(false));
+        assert_eq!(eval("[1, 2] >= [1]"), ShellObject::Boolean(true));
+
+        let sorted = eval("[[2, 1], [1, 5], [1], [1, 2]].sort()");
+        assert_eq!(sorted.to_display_string(), "[[1], [1, 2], [1, 5], [2, 1]]");
+
+        assert!(engine.evaluate_expression("[1] < [\"a\"]").is_err());
+        assert!(engine.evaluate_expression("[1] < 1").is_err());
+    }
+
+    #[test]
+    fn test_string_length_fields() {
+        let engine = EvaluationEngine::new();
+
+        for (field, expected) in [("length", 5), ("chars_len", 5), ("bytes_len", 6)] {
+            let result = engine.evaluate_expression(&format!("\"héllo\".{}", field)).unwrap();
+            assert!(matches!(result, ShellObject::Integer(n) if n == expected), "{}", field);
+        }
+
+        let err = engine.evaluate_expression("\"héllo\".size").unwrap_err();
+        assert!(err.to_string().contains("bytes_len"));
+    }
+
     #[test]
     fn test_comparison_operations() {
         let engine = EvaluationEngine::new();
@@ -617,4 +1807,459 @@ mod tests {
         let result = engine.evaluate_expression("x + 8").unwrap();
         assert!(matches!(result, ShellObject::Integer(50)));
     }
+
+    #[test]
+    fn test_closures() {
+        let mut engine = EvaluationEngine::new();
+
+        let result = engine.evaluate_expression("[1, 2, 3].map(|x| x * 2)").unwrap();
+        assert_eq!(result.to_display_string(), "[2, 4, 6]");
+
+        let result = engine.evaluate_expression("[1, 2, 3, 4].filter(|x| x % 2 == 0)").unwrap();
+        assert_eq!(result.to_display_string(), "[2, 4]");
+
+        let result = engine.evaluate_expression("[1, 2].for_each(|x| x + 1)").unwrap();
+        assert!(matches!(result, ShellObject::Unit));
+
+        let double = engine.evaluate_expression("|x: i64| x * 2").unwrap();
+        engine.set_variable("double".to_string(), double);
+        let result = engine.evaluate_expression("double(21)").unwrap();
+        assert!(matches!(result, ShellObject::Integer(42)));
+
+        assert!(engine.evaluate_expression("[1, 2].filter(|x| x + 1)").is_err());
+        assert!(engine.evaluate_expression("double(1, 2)").is_err());
+    }
+
+    #[test]
+    fn test_format() {
+        let engine = EvaluationEngine::new();
+
+        let result = engine.evaluate_expression("format(\"{} + {} = {}\", 2, 3, 5)").unwrap();
+        assert!(matches!(result, ShellObject::String(s) if s == "2 + 3 = 5"));
+
+        let result = engine.evaluate_expression("format(\"{1}-{0}-{1}\", \"a\", \"b\")").unwrap();
+        assert!(matches!(result, ShellObject::String(s) if s == "b-a-b"));
+
+        let result = engine.evaluate_expression("format(\"{{}} {}\", 1)").unwrap();
+        assert!(matches!(result, ShellObject::String(s) if s == "{} 1"));
+
+        assert!(engine.evaluate_expression("format(\"{} {}\", 1)").is_err());
+        assert!(engine.evaluate_expression("format(\"{}\", 1, 2)").is_err());
+        assert!(engine.evaluate_expression("format(\"{\", 1)").is_err());
+    }
+
+    #[test]
+    fn test_print_formatting() {
+        let s = |v: &str| ShellObject::String(v.to_string());
+
+        let output = print_output(&[s("{} is {}"), s("x"), ShellObject::Integer(3)]).unwrap();
+        assert_eq!(output, "x is 3");
+
+        // No placeholders keeps the space-joined behavior
+        let output = print_output(&[s("x is"), ShellObject::Integer(3)]).unwrap();
+        assert_eq!(output, "x is 3");
+        let output = print_output(&[s("{{literal}}"), s("a")]).unwrap();
+        assert_eq!(output, "{{literal}} a");
+
+        assert!(print_output(&[s("{} {}"), s("a")]).is_err());
+
+        let engine = EvaluationEngine::new();
+        let result = engine.evaluate_expression("format!(\"{}-{}\", 1, 2)").unwrap();
+        assert!(matches!(result, ShellObject::String(s) if s == "1-2"));
+        let result = engine.evaluate_expression("println!(\"{}\", 1)").unwrap();
+        assert!(matches!(result, ShellObject::Unit));
+        assert!(engine.evaluate_expression("vec![1]").is_err());
+    }
+
+    #[test]
+    fn test_utf8_bridge() {
+        let engine = EvaluationEngine::new();
+
+        let bytes = engine.evaluate_expression("\"hello\".encode_utf8()").unwrap();
+        assert!(matches!(&bytes, ShellObject::Bytes(b) if b == b"hello"));
+
+        let mut variables = HashMap::new();
+        variables.insert("b".to_string(), bytes);
+        let engine = EvaluationEngine::with_variables(variables);
+        let result = engine.evaluate_expression("b.decode_utf8()").unwrap();
+        assert!(matches!(result, ShellObject::String(s) if s == "hello"));
+        assert!(matches!(engine.evaluate_expression("b.len()").unwrap(), ShellObject::Integer(5)));
+
+        let mut variables = HashMap::new();
+        variables.insert("b".to_string(), ShellObject::Bytes(vec![0x66, 0xff, 0xfe]));
+        let engine = EvaluationEngine::with_variables(variables);
+        let err = engine.evaluate_expression("b.decode_utf8()").unwrap_err();
+        assert!(matches!(err, AnvilError::Parse { .. }));
+        assert!(err.to_string().contains("not valid UTF-8"));
+    }
+
+    #[test]
+    fn test_object_equality() {
+        use std::collections::HashSet;
+
+        let engine = EvaluationEngine::new();
+        let result = engine.evaluate_expression("[1, 2] == [1, 2]").unwrap();
+        assert!(matches!(result, ShellObject::Boolean(true)));
+        let result = engine.evaluate_expression("1 == 1.0").unwrap();
+        assert!(matches!(result, ShellObject::Boolean(true)));
+
+        // Equal values hash alike, so they collapse in a set
+        let mut set = HashSet::new();
+        set.insert(ShellObject::Integer(1));
+        set.insert(ShellObject::Float(1.0));
+        set.insert(ShellObject::Float(f64::NAN));
+        set.insert(ShellObject::Float(f64::NAN));
+        assert_eq!(set.len(), 2);
+
+        // Around 2^53 neighbouring integers round to the same float, which
+        // equals only the one integer it represents exactly
+        let big = 1i64 << 53;
+        let float = ShellObject::Float(big as f64);
+        assert_eq!(ShellObject::Integer(big), float);
+        assert_ne!(ShellObject::Integer(big + 1), float);
+        assert_ne!(ShellObject::Integer(i64::MAX), ShellObject::Float(i64::MAX as f64));
+        let set: HashSet<_> = [ShellObject::Integer(big), ShellObject::Integer(big + 1), float].into_iter().collect();
+        assert_eq!(set.len(), 2);
+        let result = engine.evaluate_expression("[9007199254740992, 9007199254740993, 9007199254740992.0].unique()").unwrap();
+        assert_eq!(result, ShellObject::Array(vec![big.into(), (big + 1).into()]));
+
+        let result = engine.evaluate_expression("[1, 1, 2, 1, 3, 3].dedup()").unwrap();
+        assert_eq!(result, ShellObject::Array(vec![1i64.into(), 2i64.into(), 1i64.into(), 3i64.into()]));
+        let result = engine.evaluate_expression("[\"b\", \"a\", \"b\", \"c\", \"a\"].unique()").unwrap();
+        assert_eq!(result, ShellObject::Array(vec!["b".into(), "a".into(), "c".into()]));
+    }
+
+    #[test]
+    fn test_array_membership() {
+        let engine = EvaluationEngine::new();
+
+        assert!(matches!(engine.evaluate_expression("[1, 2, 3].contains(2)").unwrap(), ShellObject::Boolean(true)));
+        assert!(matches!(engine.evaluate_expression("[1, 2, 3].contains(4)").unwrap(), ShellObject::Boolean(false)));
+        assert!(matches!(engine.evaluate_expression("[\"a\", \"b\", \"b\"].index_of(\"b\")").unwrap(), ShellObject::Integer(1)));
+        assert!(matches!(engine.evaluate_expression("[1, 2].index_of(5)").unwrap(), ShellObject::Integer(-1)));
+        assert!(matches!(engine.evaluate_expression("[[1], [2]].index_of([2])").unwrap(), ShellObject::Integer(1)));
+
+        assert!(engine.evaluate_expression("[1].contains()").is_err());
+        assert!(engine.evaluate_expression("[1].index_of(1, 2)").is_err());
+    }
+
+    #[test]
+    fn test_array_join() {
+        let engine = EvaluationEngine::new();
+
+        let result = engine.evaluate_expression("[\"a\", \"b\", 3].join(\", \")").unwrap();
+        assert!(matches!(result, ShellObject::String(s) if s == "a, b, 3"));
+        let result = engine.evaluate_expression("\"x-y-z\".split(\"-\").join(\"+\")").unwrap();
+        assert!(matches!(result, ShellObject::String(s) if s == "x+y+z"));
+
+        assert!(engine.evaluate_expression("[1, 2].join(0)").is_err());
+    }
+
+    #[test]
+    fn test_array_sort() {
+        let engine = EvaluationEngine::new();
+        let ints = |v: &[i64]| ShellObject::Array(v.iter().map(|i| ShellObject::Integer(*i)).collect());
+
+        assert_eq!(engine.evaluate_expression("[3, 1, 2, -5].sort()").unwrap(), ints(&[-5, 1, 2, 3]));
+        let result = engine.evaluate_expression("[\"pear\", \"apple\", \"fig\"].sort()").unwrap();
+        assert_eq!(result, ShellObject::Array(vec!["apple".into(), "fig".into(), "pear".into()]));
+        let result = engine.evaluate_expression("[\"pear\", \"apple\", \"fig\"].sort_by_key(|s| s.len())").unwrap();
+        assert_eq!(result, ShellObject::Array(vec!["fig".into(), "pear".into(), "apple".into()]));
+
+        // Sorting returns a new array and leaves the original alone
+        let mut variables = HashMap::new();
+        variables.insert("xs".to_string(), ints(&[2, 1]));
+        let engine = EvaluationEngine::with_variables(variables);
+        assert_eq!(engine.evaluate_expression("xs.sort()").unwrap(), ints(&[1, 2]));
+        assert_eq!(engine.evaluate_expression("xs").unwrap(), ints(&[2, 1]));
+
+        assert!(engine.evaluate_expression("[1, \"a\"].sort()").is_err());
+        assert!(engine.evaluate_expression("[1].sort(1)").is_err());
+    }
+
+    #[test]
+    fn test_map_enumeration() {
+        let mut map = BTreeMap::new();
+        map.insert("b".to_string(), ShellObject::Integer(2));
+        map.insert("a".to_string(), ShellObject::Integer(1));
+        let mut variables = HashMap::new();
+        variables.insert("m".to_string(), ShellObject::Map(map));
+        let engine = EvaluationEngine::with_variables(variables);
+
+        assert_eq!(engine.evaluate_expression("m.keys()").unwrap(), ShellObject::Array(vec!["a".into(), "b".into()]));
+        assert_eq!(engine.evaluate_expression("m.values()").unwrap(), ShellObject::Array(vec![1i64.into(), 2i64.into()]));
+        assert_eq!(
+            engine.evaluate_expression("m.entries()").unwrap(),
+            ShellObject::Array(vec![
+                ShellObject::Array(vec!["a".into(), 1i64.into()]),
+                ShellObject::Array(vec!["b".into(), 2i64.into()]),
+            ])
+        );
+
+        assert!(engine.evaluate_expression("m.keys(1)").is_err());
+
+        assert_eq!(engine.evaluate_expression("m.len()").unwrap(), ShellObject::Integer(2));
+        assert_eq!(engine.evaluate_expression("len(m)").unwrap(), ShellObject::Integer(2));
+        assert_eq!(engine.evaluate_expression("m.is_empty()").unwrap(), ShellObject::Boolean(false));
+        assert_eq!(engine.evaluate_expression("parse_toml(\"\").is_empty()").unwrap(), ShellObject::Boolean(true));
+        assert!(engine.evaluate_expression("[1].keys()").is_err());
+    }
+
+    #[test]
+    fn test_durations() {
+        let engine = EvaluationEngine::new();
+
+        let duration = engine.evaluate_expression("duration_ms(1500)").unwrap();
+        assert_eq!(duration, ShellObject::Duration(std::time::Duration::from_millis(1500)));
+        assert_eq!(duration.to_display_string(), "1.5s");
+        assert!(matches!(engine.evaluate_expression("duration_ms(1500).secs").unwrap(), ShellObject::Integer(1)));
+        assert!(matches!(engine.evaluate_expression("duration_ms(1500).millis").unwrap(), ShellObject::Integer(1500)));
+        assert!(matches!(engine.evaluate_expression("duration_ms(1500).as_secs_f64").unwrap(), ShellObject::Float(f) if f == 1.5));
+
+        let json = serde_json::to_string(&duration).unwrap();
+        assert_eq!(serde_json::from_str::<ShellObject>(&json).unwrap(), duration);
+
+        assert!(engine.evaluate_expression("duration_ms(-1)").is_err());
+        assert!(engine.evaluate_expression("duration_ms(\"1s\")").is_err());
+    }
+
+    #[test]
+    fn test_functions() {
+        let mut engine = EvaluationEngine::new();
+
+        engine.evaluate_statement("fn greet(name) { let greeting = \"hello \"; greeting + name }").unwrap();
+        let result = engine.evaluate_expression("greet(\"world\")").unwrap();
+        assert!(matches!(result, ShellObject::String(s) if s == "hello world"));
+
+        engine.evaluate_statement("fn add(a: i64, b: i64) -> i64 { a + b }").unwrap();
+        let result = engine.evaluate_expression("add(2, 3)").unwrap();
+        assert!(matches!(result, ShellObject::Integer(5)));
+
+        assert!(engine.evaluate_expression("add(1)").is_err());
+        assert!(engine.evaluate_statement("fn broken(a { a }").is_err());
+
+        // Commas and parentheses inside parameter types don't split parameters
+        engine.evaluate_statement("fn second(p: (i64, i64), m: HashMap<String, Vec<i64>>, f: fn(i64) -> i64, n) { n }").unwrap();
+        let result = engine.evaluate_expression("second(1, 2, 3, 4)").unwrap();
+        assert!(matches!(result, ShellObject::Integer(4)));
+        assert!(engine.evaluate_statement("fn unclosed(p: (i64, i64) { p }").is_err());
+
+        // Blocks get their own scope
+        let result = engine.evaluate_expression("{ let x = 2; x * 3 }").unwrap();
+        assert!(matches!(result, ShellObject::Integer(6)));
+        assert!(engine.evaluate_expression("x").is_err());
+    }
+
+    #[test]
+    fn test_assignment() {
+        let mut engine = EvaluationEngine::new();
+        engine.set_variable("m".to_string(), ShellObject::Map(BTreeMap::new()));
+
+        engine.evaluate_statement("m.name = \"anvil\"").unwrap();
+        engine.evaluate_statement("m[\"version\"] = 1;").unwrap();
+        let result = engine.evaluate_expression("m.name").unwrap();
+        assert!(matches!(result, ShellObject::String(s) if s == "anvil"));
+        let result = engine.evaluate_expression("m[\"version\"]").unwrap();
+        assert!(matches!(result, ShellObject::Integer(1)));
+
+        engine.evaluate_statement("let arr = [1, 2, 3];").unwrap();
+        engine.evaluate_statement("arr[1] = 20").unwrap();
+        let result = engine.evaluate_expression("arr").unwrap();
+        assert_eq!(result.to_display_string(), "[1, 20, 3]");
+
+        let err = engine.evaluate_statement("arr[3] = 4").unwrap_err();
+        assert!(matches!(err, AnvilError::Runtime { .. }));
+    }
+
+    #[test]
+    fn test_file_methods() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.txt");
+        let mut engine = EvaluationEngine::new();
+        engine.set_variable("p".to_string(), ShellObject::String(path.to_string_lossy().to_string()));
+
+        engine.evaluate_statement("let f = file(p)").unwrap();
+        engine.evaluate_statement("f.write(\"one\\ntwo\")").unwrap();
+
+        let result = engine.evaluate_expression("f.read()").unwrap();
+        assert!(matches!(result, ShellObject::String(s) if s == "one\ntwo"));
+        let result = engine.evaluate_expression("f.lines()").unwrap();
+        assert_eq!(result.to_display_string(), "[one, two]");
+
+        engine.set_variable("missing".to_string(), ShellObject::String(dir.path().join("nope").to_string_lossy().to_string()));
+        let err = engine.evaluate_expression("file(missing).read()").unwrap_err();
+        assert!(!err.is_fatal(), "{:?}", err);
+    }
+
+    #[test]
+    fn test_csv() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("people.csv");
+        std::fs::write(&path, "name,city\nAda,\"London, UK\"\n\"Bob \"\"B\"\"\",Paris\n").unwrap();
+
+        let mut engine = EvaluationEngine::new();
+        engine.set_variable("path".to_string(), ShellObject::String(path.to_string_lossy().to_string()));
+
+        let rows = engine.evaluate_expression("parse_csv(path, true)").unwrap();
+        let ShellObject::Array(items) = &rows else { panic!("Expected rows") };
+        assert_eq!(items.len(), 2);
+        assert!(matches!(&items[0], ShellObject::Map(m) if m["city"] == ShellObject::String("London, UK".to_string())));
+
+        // Quoting survives the round trip
+        let csv = engine.evaluate_expression("parse_csv(path, true).to_csv()").unwrap();
+        assert_eq!(csv.to_display_string(), "city,name\n\"London, UK\",Ada\nParis,\"Bob \"\"B\"\"\"\n");
+
+        let rows = engine.evaluate_expression("parse_csv(path, false)").unwrap();
+        assert_eq!(rows.to_display_string(), "[[name, city], [Ada, London, UK], [Bob \"B\", Paris]]");
+        assert_eq!(rows.to_csv().unwrap(), "name,city\nAda,\"London, UK\"\n\"Bob \"\"B\"\"\",Paris\n");
+
+        assert!(ShellObject::Array(vec![ShellObject::Integer(1)]).to_csv().is_err());
+        assert!(engine.evaluate_expression("parse_csv(\"/no/such/anvil.csv\", true)").is_err());
+    }
+
+    #[test]
+    fn test_toml() {
+        let mut engine = EvaluationEngine::new();
+        let text = "[package]\nname = \"anvil\"\nedition = 2021\nkeywords = [\"shell\", \"repl\"]\n\n[profile]\nlto = true\n";
+        engine.set_variable("text".to_string(), ShellObject::String(text.to_string()));
+
+        let config = engine.evaluate_expression("parse_toml(text)").unwrap();
+        let ShellObject::Map(sections) = &config else { panic!("Expected a map") };
+        assert!(matches!(&sections["package"], ShellObject::Map(p) if p["edition"] == ShellObject::Integer(2021)));
+        assert!(matches!(&sections["profile"], ShellObject::Map(p) if p["lto"] == ShellObject::Boolean(true)));
+
+        let round_trip = engine.evaluate_expression("parse_toml(parse_toml(text).to_toml())").unwrap();
+        assert_eq!(round_trip, config);
+
+        assert!(engine.evaluate_expression("parse_toml(\"not = = toml\")").is_err());
+        assert!(ShellObject::Integer(1).to_toml().is_err());
+        let mut with_unit = BTreeMap::new();
+        with_unit.insert("missing".to_string(), ShellObject::Unit);
+        assert!(ShellObject::Map(with_unit).to_toml().is_err());
+    }
+
+    #[test]
+    fn test_method_names_are_supported() {
+        let engine = EvaluationEngine::new();
+
+        let result = engine.evaluate_expression("\"a,b\".split(\",\")").unwrap();
+        assert_eq!(result.to_display_string(), "[a, b]");
+
+        let result = engine.evaluate_expression("[1, 2, 3].reverse().sum()").unwrap();
+        assert!(matches!(result, ShellObject::Integer(6)));
+
+        let methods = ShellObject::String(String::new()).get_method_names();
+        assert!(methods.contains(&"to_uppercase".to_string()));
+        let methods = ShellObject::Array(vec![]).get_method_names();
+        assert!(methods.contains(&"map".to_string()));
+    }
+
+    #[test]
+    fn test_null_is_distinct_from_unit() {
+        let mut map = BTreeMap::new();
+        map.insert("unit".to_string(), ShellObject::Unit);
+        let mut variables = HashMap::new();
+        variables.insert("m".to_string(), ShellObject::Map(map));
+        let engine = EvaluationEngine::with_variables(variables);
+
+        assert_eq!(engine.evaluate_expression("m.get(\"unit\")").unwrap(), ShellObject::Unit);
+        assert_eq!(engine.evaluate_expression("m.get(\"absent\")").unwrap(), ShellObject::Null);
+        assert_eq!(engine.evaluate_expression("m[\"absent\"]").unwrap(), ShellObject::Null);
+        assert_eq!(engine.evaluate_expression("[1].get(5)").unwrap(), ShellObject::Null);
+        assert_ne!(ShellObject::Null, ShellObject::Unit);
+
+        assert_eq!(engine.evaluate_expression("m.get(\"absent\").is_null()").unwrap(), ShellObject::Boolean(true));
+        assert_eq!(engine.evaluate_expression("m.get(\"unit\").is_some()").unwrap(), ShellObject::Boolean(true));
+        assert_eq!(engine.evaluate_expression("m[\"absent\"].unwrap_or(3)").unwrap(), ShellObject::Integer(3));
+        assert_eq!(engine.evaluate_expression("m[\"unit\"].unwrap_or(3)").unwrap(), ShellObject::Unit);
+        assert_eq!(engine.evaluate_expression("None.unwrap_or(\"x\")").unwrap(), ShellObject::String("x".to_string()));
+        assert!(engine.evaluate_expression("None.unwrap_or()").is_err());
+
+        assert_eq!(ShellObject::from_json(&serde_json::Value::Null), ShellObject::Null);
+        assert_eq!(ShellObject::Null.to_json(), serde_json::Value::Null);
+    }
+
+    #[test]
+    fn test_lazy_ranges() {
+        let engine = EvaluationEngine::new();
+
+        let big = engine.evaluate_expression("0..1_000_000_000_000").unwrap();
+        assert!(matches!(big, ShellObject::Range { start: 0, end: 1_000_000_000_000, inclusive: false, step: 1 }));
+        assert_eq!(big.to_display_string(), "0..1000000000000");
+        assert_eq!(engine.evaluate_expression("0..=10").unwrap().to_display_string(), "0..=10");
+
+        assert_eq!(engine.evaluate_expression("(0..1_000_000_000_000).len()").unwrap(), ShellObject::Integer(1_000_000_000_000));
+        assert_eq!(engine.evaluate_expression("(0..1_000_000_000_000).contains(999_999_999_999)").unwrap(), ShellObject::Boolean(true));
+        assert_eq!(engine.evaluate_expression("(0..10).contains(10)").unwrap(), ShellObject::Boolean(false));
+        assert_eq!(engine.evaluate_expression("(0..=10).contains(10)").unwrap(), ShellObject::Boolean(true));
+        assert_eq!(engine.evaluate_expression("(1..=1_000_000_000).sum()").unwrap(), ShellObject::Integer(500_000_000_500_000_000));
+        assert_eq!(engine.evaluate_expression("(5..5).sum()").unwrap(), ShellObject::Integer(0));
+        assert_eq!(engine.evaluate_expression("(5..1).is_empty()").unwrap(), ShellObject::Boolean(true));
+        assert_eq!(engine.evaluate_expression("(0..1_000_000_000_000)[-1]").unwrap(), ShellObject::Integer(999_999_999_999));
+
+        let result = engine.evaluate_expression("(1..4).to_array()").unwrap();
+        assert_eq!(result.to_display_string(), "[1, 2, 3]");
+        let result = engine.evaluate_expression("(1..=4).filter(|x| x % 2 == 0)").unwrap();
+        assert_eq!(result.to_display_string(), "[2, 4]");
+        let result = engine.evaluate_expression("(1..4).reverse()").unwrap();
+        assert_eq!(result.to_display_string(), "[3, 2, 1]");
+
+        assert!(engine.evaluate_expression("(0..)").is_err());
+        assert!(engine.evaluate_expression("0..\"a\"").is_err());
+        assert!(engine.evaluate_expression("(0..10)[10]").is_err());
+    }
+
+    #[test]
+    fn test_step_by() {
+        let engine = EvaluationEngine::new();
+        let eval = |expr: &str| engine.evaluate_expression(expr).unwrap().to_display_string();
+
+        assert_eq!(eval("(0..100).step_by(10).to_array()"), "[0, 10, 20, 30, 40, 50, 60, 70, 80, 90]");
+        assert_eq!(eval("(0..100).step_by(10)"), "(0..100).step_by(10)");
+
+        // Steps that don't evenly divide the range stop at the last item inside it
+        assert_eq!(eval("(0..10).step_by(3).to_array()"), "[0, 3, 6, 9]");
+        assert_eq!(eval("(0..10).step_by(3).len()"), "4");
+        assert_eq!(eval("(0..10).step_by(3).sum()"), "18");
+        assert_eq!(eval("(0..10).step_by(3)[-1]"), "9");
+        assert_eq!(eval("(0..=10).step_by(4).to_array()"), "[0, 4, 8]");
+        assert_eq!(eval("(0..=10).step_by(4).contains(10)"), "false");
+        assert_eq!(eval("(0..=10).step_by(4).contains(8)"), "true");
+        assert_eq!(eval("(1..8).step_by(3).map(|x| x * 2)"), "[2, 8, 14]");
+        assert_eq!(eval("(0..20).step_by(2).step_by(3).to_array()"), "[0, 6, 12, 18]");
+        assert_eq!(eval("(0..1_000_000_000_000).step_by(7).len()"), "142857142858");
+
+        assert_eq!(eval("[1, 2, 3, 4, 5].step_by(2)"), "[1, 3, 5]");
+        assert_eq!(eval("[1, 2, 3, 4, 5].step_by(10)"), "[1]");
+
+        assert!(engine.evaluate_expression("(0..10).step_by(0)").is_err());
+        assert!(engine.evaluate_expression("[1, 2].step_by(-1)").is_err());
+        assert!(engine.evaluate_expression("(0..10).step_by(\"2\")").is_err());
+        assert!(engine.evaluate_expression("\"abc\".step_by(2)").is_err());
+    }
+
+    #[test]
+    fn test_catching_errors() {
+        let engine = EvaluationEngine::new();
+
+        let result = engine.evaluate_expression("file(\"/no/such/file\").read().unwrap_or(\"fallback\")").unwrap();
+        assert_eq!(result, ShellObject::String("fallback".to_string()));
+        let result = engine.evaluate_expression("(1 + 1).unwrap_or(0)").unwrap();
+        assert_eq!(result, ShellObject::Integer(2));
+
+        let caught = engine.evaluate_expression("try { [1, 2][5] }").unwrap();
+        assert!(matches!(&caught, ShellObject::Error(message) if message.contains("out of bounds")));
+        let result = engine.evaluate_expression("try { [1, 2][5] }.is_error()").unwrap();
+        assert_eq!(result, ShellObject::Boolean(true));
+        let result = engine.evaluate_expression("try { [1, 2][5] }.message()").unwrap();
+        assert!(matches!(result, ShellObject::String(message) if message.contains("out of bounds")));
+        let result = engine.evaluate_expression("try { [1, 2][5] }.unwrap_or(-1)").unwrap();
+        assert_eq!(result, ShellObject::Integer(-1));
+
+        let result = engine.evaluate_expression("try { 3 }").unwrap();
+        assert_eq!(result, ShellObject::Integer(3));
+        assert_eq!(engine.evaluate_expression("3.is_error()").unwrap(), ShellObject::Boolean(false));
+        assert!(engine.evaluate_expression("3.message()").is_err());
+        assert!(engine.evaluate_expression("[1][5]").is_err());
+    }
 }
\ No newline at end of file
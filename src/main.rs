@@ -1,4 +1,4 @@
-use anvil::{shell::Shell, config::Config, error::AnvilResult};
+use anvil::{shell::Shell, config::Config, error::{AnvilError, AnvilResult}, eval::EvaluationEngine, utils::print_error};
 use clap::{Parser, Subcommand};
 use log::info;
 use std::path::PathBuf;
@@ -11,10 +11,14 @@ struct Cli {
     #[command(subcommand)]
     command: Option<Commands>,
 
-    /// Configuration file path
+    /// Configuration file path (default: ./anvil.toml, then the user config directory)
     #[arg(long)]
     config: Option<PathBuf>,
 
+    /// Confine file commands to these directories (comma-separated)
+    #[arg(long, value_delimiter = ',')]
+    allow_dirs: Vec<PathBuf>,
+
     /// Enable verbose logging
     #[arg(short, long)]
     verbose: bool,
@@ -23,6 +27,14 @@ struct Cli {
     #[arg(short = 'c', long)]
     command_string: Option<String>,
 
+    /// Evaluate an expression and print only its value
+    #[arg(short = 'e', long, conflicts_with_all = ["command_string", "script", "repl"])]
+    eval: Option<String>,
+
+    /// Print the result of a -c command as JSON
+    #[arg(long, requires = "command_string")]
+    json: bool,
+
     /// Execute a script file
     #[arg(short, long)]
     script: Option<PathBuf>,
@@ -52,11 +64,28 @@ enum Commands {
 async fn main() -> AnvilResult<()> {
     let cli = Cli::parse();
 
-    // Initialize logging
-    let log_level = if cli.verbose { "debug" } else { "info" };
+    // Initialize logging; -e output must be the value alone
+    let log_level = if cli.eval.is_some() {
+        "off"
+    } else if cli.verbose {
+        "debug"
+    } else {
+        "info"
+    };
     env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(log_level))
         .init();
 
+    if let Some(expr) = cli.eval {
+        match EvaluationEngine::new().evaluate_statement(&expr) {
+            Ok(result) => println!("{}", result.to_display_string()),
+            Err(e) => {
+                print_error(&e);
+                std::process::exit(e.exit_code());
+            }
+        }
+        return Ok(());
+    }
+
     info!("Starting Anvil shell v{}", env!("CARGO_PKG_VERSION"));
 
     // Load configuration
@@ -69,12 +98,25 @@ async fn main() -> AnvilResult<()> {
 
     // Create shell instance
     let mut shell = Shell::new(config).await?;
+    shell.allow_dirs(cli.allow_dirs);
 
     // Handle different execution modes
     match (cli.command_string, cli.script, cli.repl) {
         (Some(cmd), None, false) => {
-            // Execute single command
-            shell.execute_command(&cmd).await?;
+            // Execute single command, exiting with the command's own status
+            // on failure so scripts can branch on it
+            let result = match shell.execute_command(&cmd).await {
+                Ok(result) => result,
+                Err(e) => {
+                    if !matches!(e, AnvilError::ExternalCommand { .. }) {
+                        print_error(&e);
+                    }
+                    std::process::exit(e.exit_code());
+                }
+            };
+            if cli.json {
+                println!("{}", serde_json::to_string(&result.to_json())?);
+            }
         }
         (None, Some(script_path), false) => {
             // Execute script file
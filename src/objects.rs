@@ -1,6 +1,7 @@
 use crate::error::{AnvilError, AnvilResult};
+use crate::utils::{format_duration, TextUtils};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt;
 use std::path::PathBuf;
 use std::time::SystemTime;
@@ -40,10 +41,17 @@ pub enum ShellObject {
     Float(f64),
     Boolean(bool),
     Unit,
+    /// A missing value, such as a map lookup miss, kept apart from an
+    /// intentional `()`
+    Null,
+    Bytes(Vec<u8>),
+    Duration(std::time::Duration),
     
     // Collections
+    /// An integer range kept as its bounds so big ranges stay cheap
+    Range { start: i64, end: i64, inclusive: bool, step: u64 },
     Array(Vec<ShellObject>),
-    Map(HashMap<String, ShellObject>),
+    Map(BTreeMap<String, ShellObject>),
     
     // File system objects
     File(FileObject),
@@ -59,6 +67,7 @@ pub enum ShellObject {
     
     // Function objects
     Function(FunctionObject),
+    Closure(ClosureObject),
     
     // Error object
     Error(String),
@@ -72,6 +81,10 @@ impl ShellObject {
             ShellObject::Float(_) => "Float",
             ShellObject::Boolean(_) => "Boolean",
             ShellObject::Unit => "Unit",
+            ShellObject::Null => "Null",
+            ShellObject::Bytes(_) => "Bytes",
+            ShellObject::Duration(_) => "Duration",
+            ShellObject::Range { .. } => "Range",
             ShellObject::Array(_) => "Array",
             ShellObject::Map(_) => "Map",
             ShellObject::File(_) => "File",
@@ -81,6 +94,7 @@ impl ShellObject {
             ShellObject::Command(_) => "Command",
             ShellObject::Environment(_) => "Environment",
             ShellObject::Function(_) => "Function",
+            ShellObject::Closure(_) => "Closure",
             ShellObject::Error(_) => "Error",
         }
     }
@@ -88,11 +102,26 @@ impl ShellObject {
     pub fn get_field(&self, name: &str) -> AnvilResult<ShellObject> {
         match self {
             ShellObject::String(s) => match name {
-                "length" => Ok(ShellObject::Integer(s.len() as i64)),
+                "length" | "chars_len" => Ok(ShellObject::Integer(s.chars().count() as i64)),
+                "bytes_len" => Ok(ShellObject::Integer(s.len() as i64)),
                 "chars" => Ok(ShellObject::Array(s.chars().map(|c| ShellObject::String(c.to_string())).collect())),
                 "bytes" => Ok(ShellObject::Array(s.bytes().map(|b| ShellObject::Integer(b as i64)).collect())),
                 "is_empty" => Ok(ShellObject::Boolean(s.is_empty())),
-                _ => Err(AnvilError::object(format!("String has no field '{}'", name))),
+                _ => Err(AnvilError::object(format!(
+                    "String has no field '{}' (length and chars_len count characters, bytes_len counts UTF-8 bytes)",
+                    name
+                ))),
+            },
+            ShellObject::Bytes(bytes) => match name {
+                "length" => Ok(ShellObject::Integer(bytes.len() as i64)),
+                "is_empty" => Ok(ShellObject::Boolean(bytes.is_empty())),
+                _ => Err(AnvilError::object(format!("Bytes has no field '{}'", name))),
+            },
+            ShellObject::Duration(duration) => match name {
+                "secs" => Ok(ShellObject::Integer(duration.as_secs() as i64)),
+                "millis" => Ok(ShellObject::Integer(duration.as_millis() as i64)),
+                "as_secs_f64" => Ok(ShellObject::Float(duration.as_secs_f64())),
+                _ => Err(AnvilError::object(format!("Duration has no field '{}'", name))),
             },
             ShellObject::Array(arr) => match name {
                 "length" => Ok(ShellObject::Integer(arr.len() as i64)),
@@ -107,6 +136,7 @@ impl ShellObject {
             ShellObject::Process(proc) => proc.get_field(name),
             ShellObject::Command(cmd) => cmd.get_field(name),
             ShellObject::Environment(env) => env.get_field(name),
+            ShellObject::Closure(closure) => closure.get_field(name),
             ShellObject::Map(map) => {
                 map.get(name).cloned().ok_or_else(|| AnvilError::object(format!("Map has no field '{}'", name)))
             },
@@ -116,7 +146,12 @@ impl ShellObject {
 
     pub fn field_names(&self) -> Vec<String> {
         match self {
-            ShellObject::String(_) => vec!["length".to_string(), "chars".to_string(), "bytes".to_string(), "is_empty".to_string()],
+            ShellObject::String(_) => vec![
+                "length".to_string(), "chars_len".to_string(), "bytes_len".to_string(),
+                "chars".to_string(), "bytes".to_string(), "is_empty".to_string(),
+            ],
+            ShellObject::Bytes(_) => vec!["length".to_string(), "is_empty".to_string()],
+            ShellObject::Duration(_) => vec!["secs".to_string(), "millis".to_string(), "as_secs_f64".to_string()],
             ShellObject::Array(_) => vec!["length".to_string(), "is_empty".to_string(), "first".to_string(), "last".to_string()],
             ShellObject::File(file) => file.field_names(),
             ShellObject::Directory(dir) => dir.field_names(),
@@ -124,11 +159,90 @@ impl ShellObject {
             ShellObject::Process(proc) => proc.field_names(),
             ShellObject::Command(cmd) => cmd.field_names(),
             ShellObject::Environment(env) => env.field_names(),
+            ShellObject::Closure(closure) => closure.field_names(),
             ShellObject::Map(map) => map.keys().cloned().collect(),
             _ => vec![],
         }
     }
 
+    /// Set a field value by name, mutating the object in place
+    pub fn set_field(&mut self, name: &str, value: ShellObject) -> AnvilResult<()> {
+        match self {
+            ShellObject::Map(map) => {
+                map.insert(name.to_string(), value);
+                Ok(())
+            }
+            ShellObject::Path(path) => path.set_field(name, value),
+            ShellObject::Command(cmd) => cmd.set_field(name, value),
+            _ => Err(AnvilError::object(format!("Cannot set field '{}' on {}", name, self.type_name()))),
+        }
+    }
+
+    /// Get a mutable reference to a nested value by field name
+    pub fn field_mut(&mut self, name: &str) -> AnvilResult<&mut ShellObject> {
+        match self {
+            ShellObject::Map(map) => map.get_mut(name)
+                .ok_or_else(|| AnvilError::object(format!("Map has no field '{}'", name))),
+            _ => Err(AnvilError::object(format!("Cannot mutate field '{}' of {}", name, self.type_name()))),
+        }
+    }
+
+    /// Assign a value at an index (array position or map key)
+    pub fn set_index(&mut self, index: ShellObject, value: ShellObject) -> AnvilResult<()> {
+        match (self, index) {
+            (ShellObject::Array(arr), ShellObject::Integer(idx)) => {
+                let len = arr.len();
+                let slot = resolve_index(idx, len)
+                    .and_then(|i| arr.get_mut(i))
+                    .ok_or_else(|| AnvilError::runtime(format!("Index {} out of bounds for array of length {}", idx, len)))?;
+                *slot = value;
+                Ok(())
+            }
+            (ShellObject::Map(map), ShellObject::String(key)) => {
+                map.insert(key, value);
+                Ok(())
+            }
+            (target, index) => Err(AnvilError::eval(format!(
+                "Cannot index {} with {}", target.type_name(), index.type_name()
+            ))),
+        }
+    }
+
+    /// Get a mutable reference to the value at an index
+    pub fn index_mut(&mut self, index: ShellObject) -> AnvilResult<&mut ShellObject> {
+        match (self, index) {
+            (ShellObject::Array(arr), ShellObject::Integer(idx)) => {
+                let len = arr.len();
+                resolve_index(idx, len)
+                    .and_then(|i| arr.get_mut(i))
+                    .ok_or_else(|| AnvilError::runtime(format!("Index {} out of bounds for array of length {}", idx, len)))
+            }
+            (ShellObject::Map(map), ShellObject::String(key)) => map.get_mut(&key)
+                .ok_or_else(|| AnvilError::runtime(format!("Key '{}' not found", key))),
+            (target, index) => Err(AnvilError::eval(format!(
+                "Cannot index {} with {}", target.type_name(), index.type_name()
+            ))),
+        }
+    }
+
+    /// List the methods the evaluator supports on this object
+    pub fn get_method_names(&self) -> Vec<String> {
+        let methods: &[&str] = match self {
+            ShellObject::String(_) => &["len", "is_empty", "to_uppercase", "to_lowercase", "trim", "split", "encode_utf8", "parse", "parse_int", "parse_float"],
+            ShellObject::Bytes(_) => &["len", "is_empty", "decode_utf8"],
+            ShellObject::Array(_) => &["len", "is_empty", "push", "get", "map", "filter", "for_each", "sum", "reverse", "dedup", "unique", "contains", "index_of", "join", "sort", "sort_by_key", "step_by", "to_csv"],
+            ShellObject::Map(_) => &["len", "is_empty", "get", "keys", "values", "entries", "to_toml"],
+            ShellObject::Range { .. } => &["len", "is_empty", "contains", "to_array", "sum", "step_by", "map", "filter", "for_each"],
+            ShellObject::File(_) => &["read", "lines", "write"],
+            ShellObject::Error(_) => &["message"],
+            _ => &[],
+        };
+        methods.iter()
+            .chain(["to_string", "is_null", "is_some", "is_error", "unwrap_or"].iter())
+            .map(|m| m.to_string())
+            .collect()
+    }
+
     pub fn to_display_string(&self) -> String {
         match self {
             ShellObject::String(s) => s.clone(),
@@ -136,6 +250,13 @@ impl ShellObject {
             ShellObject::Float(f) => f.to_string(),
             ShellObject::Boolean(b) => b.to_string(),
             ShellObject::Unit => "()".to_string(),
+            ShellObject::Null => "null".to_string(),
+            ShellObject::Bytes(bytes) => format!("b\"{}\"", bytes.escape_ascii()),
+            ShellObject::Duration(duration) => format_duration(*duration),
+            ShellObject::Range { start, end, inclusive, step } => {
+                let range = format!("{}{}{}", start, if *inclusive { "..=" } else { ".." }, end);
+                if *step == 1 { range } else { format!("({}).step_by({})", range, step) }
+            }
             ShellObject::Array(arr) => {
                 let items: Vec<String> = arr.iter().map(|obj| obj.to_display_string()).collect();
                 format!("[{}]", items.join(", "))
@@ -153,16 +274,393 @@ impl ShellObject {
             ShellObject::Command(cmd) => cmd.to_display_string(),
             ShellObject::Environment(env) => env.to_display_string(),
             ShellObject::Function(func) => func.to_display_string(),
+            ShellObject::Closure(closure) => closure.to_display_string(),
             ShellObject::Error(err) => format!("Error: {}", err),
         }
     }
 
+    /// Render nested arrays and maps over several lines, indenting each
+    /// level by `indent` spaces.
+    pub fn to_pretty_string(&self, indent: usize) -> String {
+        self.to_bounded_pretty_string(indent, usize::MAX, usize::MAX)
+    }
+
+    /// Display string showing at most `max_items` entries of each array or
+    /// map, with strings cut to `max_width` characters. Elided entries are
+    /// counted, as in `[1, 2, 3, ... (997 more)]`.
+    pub fn to_bounded_string(&self, max_items: usize, max_width: usize) -> String {
+        match self {
+            ShellObject::String(s) => TextUtils::truncate(s, max_width),
+            ShellObject::Array(arr) => {
+                let mut items: Vec<String> = arr.iter()
+                    .take(max_items)
+                    .map(|item| item.to_bounded_string(max_items, max_width))
+                    .collect();
+                items.extend(elided(arr.len(), max_items));
+                format!("[{}]", items.join(", "))
+            }
+            ShellObject::Map(map) => {
+                let mut items: Vec<String> = map.iter()
+                    .take(max_items)
+                    .map(|(k, v)| format!("{}: {}", k, v.to_bounded_string(max_items, max_width)))
+                    .collect();
+                items.extend(elided(map.len(), max_items));
+                format!("{{{}}}", items.join(", "))
+            }
+            _ => self.to_display_string(),
+        }
+    }
+
+    /// Pretty string with the same limits as `to_bounded_string`
+    pub fn to_bounded_pretty_string(&self, indent: usize, max_items: usize, max_width: usize) -> String {
+        self.pretty_at(indent, 0, max_items, max_width)
+    }
+
+    fn pretty_at(&self, indent: usize, depth: usize, max_items: usize, max_width: usize) -> String {
+        let pad = " ".repeat(indent * (depth + 1));
+        let close = " ".repeat(indent * depth);
+        match self {
+            ShellObject::Array(arr) if !arr.is_empty() => {
+                let mut items: Vec<String> = arr.iter()
+                    .take(max_items)
+                    .map(|item| format!("{}{}", pad, item.pretty_at(indent, depth + 1, max_items, max_width)))
+                    .collect();
+                items.extend(elided(arr.len(), max_items).map(|more| format!("{}{}", pad, more)));
+                format!("[\n{}\n{}]", items.join(",\n"), close)
+            }
+            ShellObject::Map(map) if !map.is_empty() => {
+                let mut items: Vec<String> = map.iter()
+                    .take(max_items)
+                    .map(|(k, v)| format!("{}{}: {}", pad, k, v.pretty_at(indent, depth + 1, max_items, max_width)))
+                    .collect();
+                items.extend(elided(map.len(), max_items).map(|more| format!("{}{}", pad, more)));
+                format!("{{\n{}\n{}}}", items.join(",\n"), close)
+            }
+            _ => self.to_bounded_string(max_items, max_width),
+        }
+    }
+
+    /// Convert to JSON. Objects without a JSON counterpart become their
+    /// display string.
+    pub fn to_json(&self) -> serde_json::Value {
+        match self {
+            ShellObject::Unit | ShellObject::Null => serde_json::Value::Null,
+            ShellObject::Boolean(b) => serde_json::Value::Bool(*b),
+            ShellObject::Integer(i) => serde_json::Value::from(*i),
+            ShellObject::Float(f) => serde_json::Number::from_f64(*f)
+                .map(serde_json::Value::Number)
+                .unwrap_or(serde_json::Value::Null),
+            ShellObject::String(s) => serde_json::Value::String(s.clone()),
+            ShellObject::Bytes(bytes) => serde_json::Value::from(bytes.clone()),
+            ShellObject::Array(arr) => serde_json::Value::Array(arr.iter().map(ShellObject::to_json).collect()),
+            ShellObject::Map(map) => serde_json::Value::Object(
+                map.iter().map(|(k, v)| (k.clone(), v.to_json())).collect()
+            ),
+            other => serde_json::Value::String(other.to_display_string()),
+        }
+    }
+
+    /// JSON with the same limits as `to_bounded_string`. An elided array
+    /// tail becomes a final `"... (N more)"` string, and elided map entries
+    /// a `"... (N more)"` key with a null value.
+    pub fn to_bounded_json(&self, max_items: usize, max_width: usize) -> serde_json::Value {
+        match self {
+            ShellObject::String(s) => serde_json::Value::String(TextUtils::truncate(s, max_width)),
+            ShellObject::Array(arr) => {
+                let mut items: Vec<serde_json::Value> = arr.iter()
+                    .take(max_items)
+                    .map(|item| item.to_bounded_json(max_items, max_width))
+                    .collect();
+                items.extend(elided(arr.len(), max_items).map(serde_json::Value::String));
+                serde_json::Value::Array(items)
+            }
+            ShellObject::Map(map) => {
+                let mut items: serde_json::Map<String, serde_json::Value> = map.iter()
+                    .take(max_items)
+                    .map(|(k, v)| (k.clone(), v.to_bounded_json(max_items, max_width)))
+                    .collect();
+                items.extend(elided(map.len(), max_items).map(|more| (more, serde_json::Value::Null)));
+                serde_json::Value::Object(items)
+            }
+            _ => self.to_json(),
+        }
+    }
+
+    /// Render an array of maps that share the same keys as an aligned
+    /// table, one row per map. Returns None for anything else.
+    pub fn to_table(&self) -> Option<String> {
+        let ShellObject::Array(rows) = self else { return None };
+        let maps = rows.iter()
+            .map(|row| match row {
+                ShellObject::Map(map) => Some(map),
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>()?;
+
+        let columns: Vec<&String> = maps.first()?.keys().collect();
+        if maps.iter().any(|m| m.len() != columns.len() || columns.iter().any(|c| !m.contains_key(*c))) {
+            return None;
+        }
+
+        let cells: Vec<Vec<String>> = maps.iter()
+            .map(|m| columns.iter().map(|c| m[*c].to_display_string()).collect())
+            .collect();
+        let widths: Vec<usize> = columns.iter().enumerate()
+            .map(|(i, c)| cells.iter().map(|row| row[i].chars().count()).fold(c.chars().count(), usize::max))
+            .collect();
+
+        let render = |row: Vec<&str>| {
+            row.iter().zip(&widths)
+                .map(|(cell, width)| TextUtils::pad_right(cell, *width))
+                .collect::<Vec<_>>()
+                .join(" | ")
+                .trim_end()
+                .to_string()
+        };
+
+        let mut lines = vec![
+            render(columns.iter().map(|c| c.as_str()).collect()),
+            widths.iter().map(|w| "-".repeat(*w)).collect::<Vec<_>>().join("-+-"),
+        ];
+        lines.extend(cells.iter().map(|row| render(row.iter().map(String::as_str).collect())));
+        Some(lines.join("\n"))
+    }
+
+    /// Serialize an array of maps or an array of arrays as CSV. Maps get a
+    /// header row of every key seen, and missing fields are left empty.
+    pub fn to_csv(&self) -> AnvilResult<String> {
+        let ShellObject::Array(rows) = self else {
+            return Err(AnvilError::type_error("array", self.type_name()));
+        };
+        let field = |value: &ShellObject| match value {
+            ShellObject::Unit | ShellObject::Null => String::new(),
+            other => other.to_display_string(),
+        };
+        let csv_error = |e: csv::Error| AnvilError::runtime(format!("CSV error: {}", e));
+
+        let mut writer = csv::WriterBuilder::new().flexible(true).from_writer(Vec::new());
+        if rows.iter().all(|row| matches!(row, ShellObject::Map(_))) && !rows.is_empty() {
+            let mut columns: Vec<&String> = Vec::new();
+            for row in rows {
+                if let ShellObject::Map(map) = row {
+                    columns.extend(map.keys().filter(|k| !columns.contains(k)).collect::<Vec<_>>());
+                }
+            }
+            writer.write_record(&columns).map_err(csv_error)?;
+            for row in rows {
+                if let ShellObject::Map(map) = row {
+                    writer.write_record(columns.iter().map(|c| map.get(*c).map(field).unwrap_or_default()))
+                        .map_err(csv_error)?;
+                }
+            }
+        } else {
+            for row in rows {
+                match row {
+                    ShellObject::Array(fields) => writer.write_record(fields.iter().map(field)).map_err(csv_error)?,
+                    other => return Err(AnvilError::type_error("array of maps or arrays", other.type_name())),
+                }
+            }
+        }
+
+        let bytes = writer.into_inner().map_err(|e| AnvilError::runtime(format!("CSV error: {}", e)))?;
+        Ok(String::from_utf8_lossy(&bytes).into_owned())
+    }
+
+    /// Convert a JSON value into the closest matching shell object
+    pub fn from_json(value: &serde_json::Value) -> ShellObject {
+        match value {
+            serde_json::Value::Null => ShellObject::Null,
+            serde_json::Value::Bool(b) => ShellObject::Boolean(*b),
+            serde_json::Value::Number(n) => match n.as_i64() {
+                Some(i) => ShellObject::Integer(i),
+                None => ShellObject::Float(n.as_f64().unwrap_or(f64::NAN)),
+            },
+            serde_json::Value::String(s) => ShellObject::String(s.clone()),
+            serde_json::Value::Array(items) => {
+                ShellObject::Array(items.iter().map(ShellObject::from_json).collect())
+            }
+            serde_json::Value::Object(fields) => ShellObject::Map(
+                fields.iter()
+                    .map(|(k, v)| (k.clone(), ShellObject::from_json(v)))
+                    .collect()
+            ),
+        }
+    }
+
+    /// Convert a TOML value into the closest matching shell object. Dates
+    /// and times become strings.
+    pub fn from_toml(value: &toml::Value) -> ShellObject {
+        match value {
+            toml::Value::String(s) => ShellObject::String(s.clone()),
+            toml::Value::Integer(i) => ShellObject::Integer(*i),
+            toml::Value::Float(f) => ShellObject::Float(*f),
+            toml::Value::Boolean(b) => ShellObject::Boolean(*b),
+            toml::Value::Datetime(dt) => ShellObject::String(dt.to_string()),
+            toml::Value::Array(items) => {
+                ShellObject::Array(items.iter().map(ShellObject::from_toml).collect())
+            }
+            toml::Value::Table(table) => ShellObject::Map(
+                table.iter()
+                    .map(|(k, v)| (k.clone(), ShellObject::from_toml(v)))
+                    .collect()
+            ),
+        }
+    }
+
+    /// Serialize a map as a TOML document
+    pub fn to_toml(&self) -> AnvilResult<String> {
+        match self.to_toml_value()? {
+            toml::Value::Table(table) => toml::to_string(&table)
+                .map_err(|e| AnvilError::runtime(format!("TOML error: {}", e))),
+            _ => Err(AnvilError::type_error("map", self.type_name())),
+        }
+    }
+
+    fn to_toml_value(&self) -> AnvilResult<toml::Value> {
+        Ok(match self {
+            ShellObject::String(s) => toml::Value::String(s.clone()),
+            ShellObject::Integer(i) => toml::Value::Integer(*i),
+            ShellObject::Float(f) => toml::Value::Float(*f),
+            ShellObject::Boolean(b) => toml::Value::Boolean(*b),
+            ShellObject::Array(items) => toml::Value::Array(
+                items.iter().map(ShellObject::to_toml_value).collect::<AnvilResult<_>>()?
+            ),
+            ShellObject::Map(map) => toml::Value::Table(
+                map.iter()
+                    .map(|(k, v)| Ok((k.clone(), v.to_toml_value()?)))
+                    .collect::<AnvilResult<_>>()?
+            ),
+            ShellObject::Unit | ShellObject::Null => return Err(AnvilError::runtime("TOML has no null value")),
+            other => toml::Value::String(other.to_display_string()),
+        })
+    }
+
     /// Convert Rust types to ShellObject
     pub fn from_rust_value<T: Into<ShellObject>>(value: T) -> ShellObject {
         value.into()
     }
 }
 
+/// The integer a float is exactly equal to, if any. Comparing through this
+/// instead of `i as f64` keeps equality transitive past 2^53, where
+/// neighbouring integers round to the same float.
+fn float_as_integer(f: f64) -> Option<i64> {
+    // i64::MIN is a power of two, so both bounds are exact
+    (f.fract() == 0.0 && f >= i64::MIN as f64 && f < -(i64::MIN as f64)).then_some(f as i64)
+}
+
+// Values compare structurally. Integers and floats with the same numeric
+// value are equal, and NaN equals itself so that `Eq` holds. Objects
+// without value semantics (files, processes, ...) compare by display form.
+impl PartialEq for ShellObject {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (ShellObject::Integer(a), ShellObject::Integer(b)) => a == b,
+            (ShellObject::Float(a), ShellObject::Float(b)) => a == b || (a.is_nan() && b.is_nan()),
+            (ShellObject::Integer(a), ShellObject::Float(b))
+            | (ShellObject::Float(b), ShellObject::Integer(a)) => float_as_integer(*b) == Some(*a),
+            (ShellObject::String(a), ShellObject::String(b)) => a == b,
+            (ShellObject::Boolean(a), ShellObject::Boolean(b)) => a == b,
+            (ShellObject::Unit, ShellObject::Unit) => true,
+            (ShellObject::Null, ShellObject::Null) => true,
+            (ShellObject::Bytes(a), ShellObject::Bytes(b)) => a == b,
+            (ShellObject::Duration(a), ShellObject::Duration(b)) => a == b,
+            (ShellObject::Array(a), ShellObject::Array(b)) => a == b,
+            (ShellObject::Map(a), ShellObject::Map(b)) => a == b,
+            (a, b) => {
+                std::mem::discriminant(a) == std::mem::discriminant(b)
+                    && !a.is_value()
+                    && a.to_display_string() == b.to_display_string()
+            }
+        }
+    }
+}
+
+impl Eq for ShellObject {}
+
+impl std::hash::Hash for ShellObject {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        match self {
+            ShellObject::Integer(i) => {
+                "number".hash(state);
+                i.hash(state);
+            }
+            ShellObject::Float(f) => {
+                "number".hash(state);
+                match float_as_integer(*f) {
+                    // Whole floats hash like the integer they equal
+                    Some(i) => i.hash(state),
+                    None => {
+                        let bits = if f.is_nan() { f64::NAN.to_bits() } else { f.to_bits() };
+                        bits.hash(state);
+                    }
+                }
+            }
+            ShellObject::String(s) => s.hash(state),
+            ShellObject::Boolean(b) => b.hash(state),
+            ShellObject::Unit => {}
+            ShellObject::Null => "null".hash(state),
+            ShellObject::Bytes(bytes) => bytes.hash(state),
+            ShellObject::Duration(duration) => duration.hash(state),
+            ShellObject::Array(arr) => arr.hash(state),
+            ShellObject::Map(map) => map.hash(state),
+            other => {
+                other.type_name().hash(state);
+                other.to_display_string().hash(state);
+            }
+        }
+    }
+}
+
+impl ShellObject {
+    /// Whether equality is decided by the fields in `PartialEq` rather
+    /// than by display form
+    fn is_value(&self) -> bool {
+        matches!(
+            self,
+            ShellObject::Integer(_) | ShellObject::Float(_) | ShellObject::String(_)
+                | ShellObject::Boolean(_) | ShellObject::Unit | ShellObject::Null | ShellObject::Bytes(_) | ShellObject::Duration(_)
+                | ShellObject::Array(_) | ShellObject::Map(_)
+        )
+    }
+}
+
+/// Map an index onto a collection of `len` items, counting negative
+/// indices back from the end. Returns None when it falls outside.
+pub fn resolve_index(idx: i64, len: usize) -> Option<usize> {
+    let position = if idx < 0 {
+        len.checked_sub(usize::try_from(idx.unsigned_abs()).ok()?)?
+    } else {
+        usize::try_from(idx).ok()?
+    };
+    (position < len).then_some(position)
+}
+
+/// The marker for entries left out of a bounded display, if any were
+fn elided(len: usize, shown: usize) -> Option<String> {
+    (len > shown).then(|| format!("... ({} more)", len - shown))
+}
+
+/// The integers a range yields, in order
+pub fn range_items(start: i64, end: i64, inclusive: bool, step: u64) -> std::iter::StepBy<std::ops::RangeInclusive<i64>> {
+    let last = if inclusive { Some(end) } else { end.checked_sub(1) };
+    let items = match last {
+        Some(last) => start..=last,
+        // Nothing comes before i64::MIN, so the range is empty
+        None => std::ops::RangeInclusive::new(1, 0),
+    };
+    items.step_by(usize::try_from(step).unwrap_or(usize::MAX))
+}
+
+/// How many integers a range yields, without walking it
+pub fn range_len(start: i64, end: i64, inclusive: bool, step: u64) -> u64 {
+    let span = end as i128 - start as i128 + inclusive as i128;
+    if span <= 0 {
+        return 0;
+    }
+    ((span + step as i128 - 1) / step as i128) as u64
+}
+
 // Implement conversions from Rust types
 impl From<String> for ShellObject {
     fn from(s: String) -> Self { ShellObject::String(s) }
@@ -189,7 +687,11 @@ impl From<Vec<ShellObject>> for ShellObject {
 }
 
 impl From<HashMap<String, ShellObject>> for ShellObject {
-    fn from(map: HashMap<String, ShellObject>) -> Self { ShellObject::Map(map) }
+    fn from(map: HashMap<String, ShellObject>) -> Self { ShellObject::Map(map.into_iter().collect()) }
+}
+
+impl From<BTreeMap<String, ShellObject>> for ShellObject {
+    fn from(map: BTreeMap<String, ShellObject>) -> Self { ShellObject::Map(map) }
 }
 
 // File system objects
@@ -202,6 +704,44 @@ pub struct FileObject {
 }
 
 impl FileObject {
+    /// Build a file object from a path, reading its metadata if it exists
+    pub fn from_path<P: Into<PathBuf>>(path: P) -> AnvilResult<Self> {
+        let path = path.into();
+        match std::fs::metadata(&path) {
+            Ok(metadata) if metadata.is_dir() => Err(AnvilError::object(format!(
+                "{} is a directory", path.display()
+            ))),
+            Ok(metadata) => Ok(Self {
+                size: metadata.len(),
+                modified: metadata.modified().ok(),
+                permissions: permissions_string(&metadata),
+                path,
+            }),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self {
+                path,
+                size: 0,
+                modified: None,
+                permissions: String::new(),
+            }),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Read the whole file as a string
+    pub fn read(&self) -> AnvilResult<String> {
+        std::fs::read_to_string(&self.path).map_err(|e| AnvilError::io_at(self.path.display().to_string(), e))
+    }
+
+    /// Read the file as a list of lines
+    pub fn lines(&self) -> AnvilResult<Vec<String>> {
+        Ok(self.read()?.lines().map(|line| line.to_string()).collect())
+    }
+
+    /// Overwrite the file with the given contents
+    pub fn write(&self, contents: &str) -> AnvilResult<()> {
+        std::fs::write(&self.path, contents).map_err(|e| AnvilError::io_at(self.path.display().to_string(), e))
+    }
+
     pub fn get_field(&self, name: &str) -> AnvilResult<ShellObject> {
         match name {
             "path" => Ok(ShellObject::String(self.path.to_string_lossy().to_string())),
@@ -226,6 +766,17 @@ impl FileObject {
     }
 }
 
+#[cfg(unix)]
+fn permissions_string(metadata: &std::fs::Metadata) -> String {
+    use std::os::unix::fs::PermissionsExt;
+    format!("{:o}", metadata.permissions().mode() & 0o777)
+}
+
+#[cfg(not(unix))]
+fn permissions_string(metadata: &std::fs::Metadata) -> String {
+    if metadata.permissions().readonly() { "r--" } else { "rw-" }.to_string()
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DirectoryObject {
     pub path: PathBuf,
@@ -281,6 +832,17 @@ impl PathObject {
         }
     }
 
+    pub fn set_field(&mut self, name: &str, value: ShellObject) -> AnvilResult<()> {
+        match (name, value) {
+            ("path", ShellObject::String(path)) => {
+                self.path = PathBuf::from(path);
+                Ok(())
+            }
+            ("path", other) => Err(AnvilError::type_error("string", other.type_name())),
+            _ => Err(AnvilError::object(format!("Path field '{}' is read-only", name))),
+        }
+    }
+
     pub fn field_names(&self) -> Vec<String> {
         vec!["path".to_string(), "parent".to_string(), "filename".to_string(), 
              "extension".to_string(), "exists".to_string(), "is_file".to_string(), "is_dir".to_string()]
@@ -341,6 +903,27 @@ impl CommandObject {
         }
     }
 
+    pub fn set_field(&mut self, name: &str, value: ShellObject) -> AnvilResult<()> {
+        match (name, value) {
+            ("name", ShellObject::String(new_name)) => {
+                self.name = new_name;
+                Ok(())
+            }
+            ("args", ShellObject::Array(args)) => {
+                self.args = args.iter().map(|a| a.to_display_string()).collect();
+                Ok(())
+            }
+            ("env", ShellObject::Map(env)) => {
+                self.env = env.iter().map(|(k, v)| (k.clone(), v.to_display_string())).collect();
+                Ok(())
+            }
+            ("name", other) => Err(AnvilError::type_error("string", other.type_name())),
+            ("args", other) => Err(AnvilError::type_error("array", other.type_name())),
+            ("env", other) => Err(AnvilError::type_error("map", other.type_name())),
+            _ => Err(AnvilError::object(format!("Command has no field '{}'", name))),
+        }
+    }
+
     pub fn field_names(&self) -> Vec<String> {
         vec!["name".to_string(), "args".to_string(), "env".to_string()]
     }
@@ -375,6 +958,9 @@ impl EnvironmentObject {
 pub struct FunctionObject {
     pub name: String,
     pub signature: String,
+    /// Parameter names, bound positionally when the function is called
+    pub params: Vec<String>,
+    /// Body block source, re-parsed on each call like closures
     pub body: String,
 }
 
@@ -383,13 +969,16 @@ impl FunctionObject {
         match name {
             "name" => Ok(ShellObject::String(self.name.clone())),
             "signature" => Ok(ShellObject::String(self.signature.clone())),
+            "params" => Ok(ShellObject::Array(
+                self.params.iter().map(|p| ShellObject::String(p.clone())).collect()
+            )),
             "body" => Ok(ShellObject::String(self.body.clone())),
             _ => Err(AnvilError::object(format!("Function has no field '{}'", name))),
         }
     }
 
     pub fn field_names(&self) -> Vec<String> {
-        vec!["name".to_string(), "signature".to_string(), "body".to_string()]
+        vec!["name".to_string(), "signature".to_string(), "params".to_string(), "body".to_string()]
     }
 
     pub fn to_display_string(&self) -> String {
@@ -397,6 +986,37 @@ impl FunctionObject {
     }
 }
 
+/// A closure captured from an `|args| body` expression.
+///
+/// The body is kept as its token string because `syn::Expr` is not
+/// serializable; the evaluator re-parses it on every call.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClosureObject {
+    pub params: Vec<String>,
+    pub body: String,
+}
+
+impl ClosureObject {
+    pub fn get_field(&self, name: &str) -> AnvilResult<ShellObject> {
+        match name {
+            "params" => Ok(ShellObject::Array(
+                self.params.iter().map(|p| ShellObject::String(p.clone())).collect()
+            )),
+            "body" => Ok(ShellObject::String(self.body.clone())),
+            "arity" => Ok(ShellObject::Integer(self.params.len() as i64)),
+            _ => Err(AnvilError::object(format!("Closure has no field '{}'", name))),
+        }
+    }
+
+    pub fn field_names(&self) -> Vec<String> {
+        vec!["params".to_string(), "body".to_string(), "arity".to_string()]
+    }
+
+    pub fn to_display_string(&self) -> String {
+        format!("|{}| {}", self.params.join(", "), self.body)
+    }
+}
+
 impl fmt::Display for ShellObject {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{}", self.to_display_string())
@@ -1,23 +1,171 @@
-use crate::config::Config;
+use crate::config::{Config, DisplayMode, ReplBackend, ReplConfig};
 use crate::error::{AnvilError, AnvilResult};
+use crate::eval::EvaluationEngine;
 use crate::objects::ShellObject;
-use reedline::{Reedline, Signal, DefaultPrompt, Prompt, PromptHistorySearch, PromptEditMode};
+use crate::utils::{color_enabled, format_duration, output_interruptible, print_error, Spinner, SystemUtils};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use reedline::{
+    default_emacs_keybindings, ColumnarMenu, Completer, EditCommand, EditMode, Emacs, FileBackedHistory,
+    KeyCode, KeyModifiers, MenuBuilder, Reedline, ReedlineEvent, ReedlineMenu, ReedlineRawEvent, Signal,
+    DefaultPrompt, Prompt, PromptHistorySearch, PromptEditMode, Span, Suggestion,
+};
 use nu_ansi_term::{Color, Style};
 use std::borrow::Cow;
+use std::cell::RefCell;
 use crossterm::style::{Color as CrosstermColor, Stylize};
-use std::collections::{HashMap, HashSet};
-use std::path::PathBuf;
-use std::process::{Command, Stdio};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock, PoisonError, RwLock};
 use std::time::{Duration, Instant};
 use tempfile::NamedTempFile;
 use std::io::Write;
 use regex::Regex;
 
+/// Editor for `edit()` when neither $VISUAL nor $EDITOR is set
+const DEFAULT_EDITOR: &str = if cfg!(windows) { "notepad" } else { "vi" };
+
+/// Name of the Tab completion menu
+const COMPLETION_MENU: &str = "completion_menu";
+
+/// Runs for `:bench` without `-n`
+const DEFAULT_BENCH_RUNS: usize = 10;
+
+/// Flags every snippet is compiled with, on top of the source and output paths
+const RUSTC_FLAGS: &[&str] = &["--edition", "2021", "--crate-name", "anvil_repl"];
+
+/// Prefix of the line a compiled program prints its result on
+const RESULT_MARKER: &str = "__ANVIL_RESULT__";
+
+/// Lines bracketing the user's code in a generated program, so compiler
+/// diagnostics can be mapped back to what was typed
+const USER_CODE_START: &str = "// __anvil_user_code_start__";
+const USER_CODE_END: &str = "// __anvil_user_code_end__";
+
+/// Support code for compiled expressions. Values of common std types are
+/// written as JSON; anything else falls back to its Debug text, picked via
+/// autoref specialization so no bounds are needed at the call site.
+const RESULT_SUPPORT: &str = r#"
+#[allow(dead_code)]
+mod __anvil {
+    use std::collections::{BTreeMap, HashMap};
+    use std::fmt::Debug;
+    use std::path::{Path, PathBuf};
+
+    pub trait Json {
+        fn json(&self) -> String;
+    }
+
+    pub fn escape(s: &str) -> String {
+        let mut out = String::from("\"");
+        for c in s.chars() {
+            match c {
+                '"' => out.push_str("\\\""),
+                '\\' => out.push_str("\\\\"),
+                '\n' => out.push_str("\\n"),
+                '\r' => out.push_str("\\r"),
+                '\t' => out.push_str("\\t"),
+                c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+                c => out.push(c),
+            }
+        }
+        out.push('"');
+        out
+    }
+
+    macro_rules! json_number {
+        ($($t:ty),*) => { $(impl Json for $t {
+            fn json(&self) -> String { self.to_string() }
+        })* };
+    }
+    json_number!(i8, i16, i32, i64, i128, isize, u8, u16, u32, u64, u128, usize);
+
+    macro_rules! json_float {
+        ($($t:ty),*) => { $(impl Json for $t {
+            fn json(&self) -> String {
+                if self.is_finite() { format!("{:?}", self) } else { "null".to_string() }
+            }
+        })* };
+    }
+    json_float!(f32, f64);
+
+    impl Json for () { fn json(&self) -> String { "null".to_string() } }
+    impl Json for bool { fn json(&self) -> String { self.to_string() } }
+    impl Json for char { fn json(&self) -> String { escape(&self.to_string()) } }
+    impl Json for str { fn json(&self) -> String { escape(self) } }
+    impl Json for String { fn json(&self) -> String { escape(self) } }
+    impl Json for Path { fn json(&self) -> String { escape(&self.to_string_lossy()) } }
+    impl Json for PathBuf { fn json(&self) -> String { escape(&self.to_string_lossy()) } }
+
+    impl<T: Json + ?Sized> Json for &T {
+        fn json(&self) -> String { (**self).json() }
+    }
+    impl<T: Json + ?Sized> Json for Box<T> {
+        fn json(&self) -> String { (**self).json() }
+    }
+    impl<T: Json> Json for Option<T> {
+        fn json(&self) -> String {
+            match self { Some(v) => v.json(), None => "null".to_string() }
+        }
+    }
+    impl<T: Json> Json for [T] {
+        fn json(&self) -> String {
+            let items: Vec<String> = self.iter().map(Json::json).collect();
+            format!("[{}]", items.join(","))
+        }
+    }
+    impl<T: Json, const N: usize> Json for [T; N] {
+        fn json(&self) -> String { self[..].json() }
+    }
+    impl<T: Json> Json for Vec<T> {
+        fn json(&self) -> String { self[..].json() }
+    }
+    impl<V: Json> Json for HashMap<String, V> {
+        fn json(&self) -> String {
+            let items: Vec<String> = self.iter()
+                .map(|(k, v)| format!("{}:{}", escape(k), v.json()))
+                .collect();
+            format!("{{{}}}", items.join(","))
+        }
+    }
+    impl<V: Json> Json for BTreeMap<String, V> {
+        fn json(&self) -> String {
+            let items: Vec<String> = self.iter()
+                .map(|(k, v)| format!("{}:{}", escape(k), v.json()))
+                .collect();
+            format!("{{{}}}", items.join(","))
+        }
+    }
+
+    pub struct Wrap<'a, T>(pub &'a T);
+
+    pub trait ViaJson {
+        fn result_json(&self) -> String;
+    }
+    impl<T: Json> ViaJson for Wrap<'_, T> {
+        fn result_json(&self) -> String { self.0.json() }
+    }
+
+    pub trait ViaDebug {
+        fn result_json(&self) -> String;
+    }
+    impl<T: Debug> ViaDebug for &Wrap<'_, T> {
+        fn result_json(&self) -> String { escape(&format!("{:?}", self.0)) }
+    }
+}
+"#;
+
 pub struct ReplEngine {
     config: Config,
     editor: Reedline,
     context: ReplContext,
     prompt: AnvilPrompt,
+    /// Copy of the session variables the completer reads from
+    completion_variables: SharedVariables,
+    /// Indentation a closing bracket typed first on the current line removes
+    dedent: Arc<AtomicUsize>,
 }
 
 #[derive(Debug, Clone)]
@@ -34,6 +182,34 @@ pub struct ReplContext {
     pub multiline_mode: bool,
     /// Current line continuation buffer
     pub continuation_buffer: String,
+    /// Value produced by the most recent successful evaluation
+    pub last_result: Option<ShellObject>,
+    /// Crates declared with `:dep`, mapped to their version requirement
+    pub dependencies: BTreeMap<String, String>,
+    /// How long the most recent compiled command took
+    pub last_timings: Option<CompileTimings>,
+}
+
+/// Time spent building and running one compiled command
+#[derive(Debug, Clone, Copy)]
+pub struct CompileTimings {
+    pub compile: Duration,
+    pub execute: Duration,
+    /// Whether the binary came from the cache instead of being built
+    pub cached: bool,
+}
+
+/// On-disk form of a REPL session, written by `:save` and read by `:load`.
+/// Variables are stored as individual JSON values so one that can't be
+/// written or read back doesn't lose the rest of the session.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct SessionSnapshot {
+    variables: BTreeMap<String, serde_json::Value>,
+    functions: BTreeMap<String, String>,
+    imports: Vec<String>,
+    code_history: Vec<String>,
+    #[serde(default)]
+    dependencies: BTreeMap<String, String>,
 }
 
 impl Default for ReplContext {
@@ -51,6 +227,9 @@ impl Default for ReplContext {
             code_history: Vec::new(),
             multiline_mode: false,
             continuation_buffer: String::new(),
+            last_result: None,
+            dependencies: BTreeMap::new(),
+            last_timings: None,
         }
     }
 }
@@ -59,6 +238,12 @@ struct AnvilPrompt {
     base_prompt: String,
     continuation_prompt: String,
     multiline_mode: bool,
+    show_git_branch: bool,
+    show_exit_status: bool,
+    last_status: i32,
+    /// Branch lookup for the directory it was computed in, so rendering
+    /// doesn't hit the filesystem on every keystroke
+    git_branch_cache: RefCell<Option<(PathBuf, Option<String>)>>,
 }
 
 impl AnvilPrompt {
@@ -67,34 +252,78 @@ impl AnvilPrompt {
             base_prompt: config.shell.prompt.clone(),
             continuation_prompt: config.shell.continuation_prompt.clone(),
             multiline_mode: false,
+            show_git_branch: config.shell.show_git_branch,
+            show_exit_status: config.shell.show_exit_status,
+            last_status: 0,
+            git_branch_cache: RefCell::new(None),
         }
     }
 
+    /// Pick up changed prompt settings, keeping the session state
+    fn apply_config(&mut self, config: &Config) {
+        self.base_prompt = config.shell.prompt.clone();
+        self.continuation_prompt = config.shell.continuation_prompt.clone();
+        self.show_git_branch = config.shell.show_git_branch;
+        self.show_exit_status = config.shell.show_exit_status;
+    }
+
     fn set_multiline(&mut self, multiline: bool) {
         self.multiline_mode = multiline;
     }
+
+    /// Record the outcome of a command; it may also have switched branches
+    fn set_last_status(&mut self, status: i32) {
+        self.last_status = status;
+        self.git_branch_cache.get_mut().take();
+    }
+
+    fn git_branch(&self, dir: &Path) -> Option<String> {
+        let mut cache = self.git_branch_cache.borrow_mut();
+        match cache.as_ref() {
+            Some((cached_dir, branch)) if cached_dir == dir => branch.clone(),
+            _ => {
+                let branch = crate::utils::find_git_branch(dir);
+                *cache = Some((dir.to_path_buf(), branch.clone()));
+                branch
+            }
+        }
+    }
 }
 
 impl Prompt for AnvilPrompt {
     fn render_prompt_left(&self) -> Cow<str> {
         if self.multiline_mode {
             Cow::Borrowed(&self.continuation_prompt)
+        } else if self.base_prompt.contains('{') {
+            Cow::Owned(expand_prompt_template(&self.base_prompt))
         } else {
             Cow::Borrowed(&self.base_prompt)
         }
     }
 
     fn render_prompt_right(&self) -> Cow<str> {
+        let mut parts = Vec::new();
+
+        if self.show_exit_status && self.last_status != 0 {
+            parts.push(format!("✗ {}", self.last_status));
+        }
+
         // Show current directory on the right
         if let Ok(current_dir) = std::env::current_dir() {
             let dir_name = current_dir
                 .file_name()
                 .unwrap_or_default()
                 .to_string_lossy();
-            Cow::Owned(format!("[{}]", dir_name))
-        } else {
-            Cow::Borrowed("")
+            parts.push(format!("[{}]", dir_name));
+
+            if self.show_git_branch {
+                if let Some(branch) = self.git_branch(&current_dir) {
+                    parts.push(format!("({})", branch));
+                }
+            }
         }
+
+        Cow::Owned(parts.join(" "))
     }
 
     fn render_prompt_indicator(&self, _edit_mode: PromptEditMode) -> Cow<str> {
@@ -110,13 +339,192 @@ impl Prompt for AnvilPrompt {
     }
 }
 
+/// Fill in `{user}`, `{host}`, `{cwd}` and `{time}` placeholders in a prompt.
+/// Unknown placeholders are left as written.
+fn expand_prompt_template(template: &str) -> String {
+    let mut result = String::new();
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        result.push_str(&rest[..start]);
+        let after = &rest[start..];
+
+        let Some(end) = after.find('}') else {
+            rest = after;
+            break;
+        };
+        let value = match &after[1..end] {
+            "user" => Some(SystemUtils::username()),
+            "host" => Some(SystemUtils::hostname()),
+            "cwd" => std::env::current_dir().ok().map(|dir| collapse_home(&dir)),
+            "time" => Some(chrono::Local::now().format("%H:%M:%S").to_string()),
+            _ => None,
+        };
+        result.push_str(value.as_deref().unwrap_or(&after[..=end]));
+        rest = &after[end + 1..];
+    }
+
+    result.push_str(rest);
+    result
+}
+
+/// Display a path with the home directory shortened to `~`
+fn collapse_home(path: &Path) -> String {
+    match dirs::home_dir().and_then(|home| path.strip_prefix(&home).ok().map(Path::to_path_buf)) {
+        Some(relative) if relative.as_os_str().is_empty() => "~".to_string(),
+        Some(relative) => format!("~/{}", relative.display()),
+        None => path.display().to_string(),
+    }
+}
+
+/// Session variables shared with the line editor's completer
+type SharedVariables = Arc<RwLock<HashMap<String, ShellObject>>>;
+
+/// Tab completion for the REPL, offering the fields and methods of a
+/// session variable after a `.`
+struct AnvilCompleter {
+    variables: SharedVariables,
+}
+
+impl Completer for AnvilCompleter {
+    fn complete(&mut self, line: &str, pos: usize) -> Vec<Suggestion> {
+        let variables = self.variables.read().unwrap_or_else(PoisonError::into_inner);
+        let Some((start, candidates)) = member_completions(&variables, &line[..pos]) else {
+            return Vec::new();
+        };
+        candidates.into_iter()
+            .map(|value| Suggestion {
+                value,
+                span: Span::new(start, pos),
+                ..Default::default()
+            })
+            .collect()
+    }
+}
+
+/// Emacs editing, except that a closing bracket typed before anything else
+/// on an auto-indented continuation line takes one indent level off it
+struct DedentingEmacs {
+    emacs: Emacs,
+    /// Characters a leading closing bracket removes, set by the REPL loop
+    /// when it indents a line and cleared by the first key that edits it
+    dedent: Arc<AtomicUsize>,
+}
+
+impl EditMode for DedentingEmacs {
+    fn parse_event(&mut self, event: ReedlineRawEvent) -> ReedlineEvent {
+        let parsed = self.emacs.parse_event(event);
+        if matches!(parsed, ReedlineEvent::None) {
+            return parsed;
+        }
+        dedent_closing_bracket(parsed, self.dedent.swap(0, Ordering::Relaxed))
+    }
+
+    fn edit_mode(&self) -> PromptEditMode {
+        self.emacs.edit_mode()
+    }
+}
+
+/// Turn the insertion of a closing bracket into one that first deletes
+/// `dedent` characters of indentation
+fn dedent_closing_bracket(event: ReedlineEvent, dedent: usize) -> ReedlineEvent {
+    match &event {
+        ReedlineEvent::Edit(commands) if dedent > 0 => match commands.as_slice() {
+            [EditCommand::InsertChar(bracket @ ('}' | ')' | ']'))] => {
+                let mut commands = vec![EditCommand::Backspace; dedent];
+                commands.push(EditCommand::InsertChar(*bracket));
+                ReedlineEvent::Edit(commands)
+            }
+            _ => event,
+        },
+        _ => event,
+    }
+}
+
+/// Completions for the member being typed at the end of `line`: the fields
+/// and methods of the value before the last `.`, when that value is a
+/// session variable or a field or index of one. Returns where the member
+/// name starts along with the candidates, methods ending in `(`.
+fn member_completions(variables: &HashMap<String, ShellObject>, line: &str) -> Option<(usize, Vec<String>)> {
+    let is_ident = |c: char| c.is_alphanumeric() || c == '_';
+    let start = line.trim_end_matches(is_ident).len();
+    let partial = &line[start..];
+    let before = line[..start].strip_suffix('.')?;
+
+    // Only plain field and index accesses, so completing never runs a method
+    let receiver = &before[before.trim_end_matches(|c| is_ident(c) || matches!(c, '.' | '[' | ']')).len()..];
+    let root = receiver.split(['.', '[']).next().unwrap_or_default();
+    if !variables.contains_key(root) {
+        return None;
+    }
+    let object = EvaluationEngine::with_variables(variables.clone())
+        .evaluate_expression(receiver)
+        .ok()?;
+
+    // Map keys that aren't identifiers can't follow a `.`
+    let fields = object.field_names().into_iter()
+        .filter(|field| field.chars().all(is_ident) && !field.starts_with(|c: char| c.is_ascii_digit()));
+    let methods = object.get_method_names().into_iter().map(|method| format!("{}(", method));
+    let candidates: BTreeSet<String> = fields.chain(methods)
+        .filter(|candidate| !candidate.is_empty() && candidate.starts_with(partial))
+        .collect();
+    Some((start, candidates.into_iter().collect()))
+}
+
 impl ReplEngine {
+    /// Replace the REPL's settings, e.g. after `config set` in the shell.
+    /// Prelude imports that are new are added to the session.
+    pub fn set_config(&mut self, config: Config) {
+        self.prompt.apply_config(&config);
+        self.config = config;
+        self.add_prelude_imports();
+    }
+
+    /// Load the config file again and apply it to the running session
+    pub async fn reload_config(&mut self) -> AnvilResult<()> {
+        let config = Config::load(self.config.loaded_from.as_deref()).await?;
+        self.set_config(config);
+        Ok(())
+    }
+
+    /// Add prelude imports the session doesn't have yet
+    fn add_prelude_imports(&mut self) {
+        for import in &self.config.repl.prelude {
+            if !self.context.imports.contains(import) {
+                self.context.imports.push(import.clone());
+            }
+        }
+    }
+
     pub fn new(config: Config) -> AnvilResult<Self> {
-        let mut editor = Reedline::create();
+        let completion_variables = SharedVariables::default();
+        let completer = AnvilCompleter { variables: completion_variables.clone() };
+        let menu = ColumnarMenu::default().with_name(COMPLETION_MENU);
+        let dedent = Arc::new(AtomicUsize::new(0));
+        let mut keybindings = default_emacs_keybindings();
+        keybindings.add_binding(
+            KeyModifiers::NONE,
+            KeyCode::Tab,
+            ReedlineEvent::UntilFound(vec![
+                ReedlineEvent::Menu(COMPLETION_MENU.to_string()),
+                ReedlineEvent::MenuNext,
+            ]),
+        );
+        let mut editor = Reedline::create()
+            .with_completer(Box::new(completer))
+            .with_menu(ReedlineMenu::EngineCompleter(Box::new(menu)))
+            .with_edit_mode(Box::new(DedentingEmacs { emacs: Emacs::new(keybindings), dedent: dedent.clone() }));
         
-        // Set up history if configured
-        if let Ok(history_file) = std::fs::File::create(&config.shell.history_file) {
-            drop(history_file); // Just ensure the file exists
+        // Persist history across sessions; a history file we can't open
+        // shouldn't stop the shell from starting
+        if let Some(parent) = config.shell.history_file.parent() {
+            let _ = std::fs::create_dir_all(parent);
+        }
+        if let Ok(history) = FileBackedHistory::with_file(
+            config.shell.max_history_size,
+            config.shell.history_file.clone(),
+        ) {
+            editor = editor.with_history(Box::new(history));
         }
 
         let prompt = AnvilPrompt::new(&config);
@@ -127,26 +535,34 @@ impl ReplEngine {
             editor,
             context,
             prompt,
+            completion_variables,
+            dedent,
         })
     }
 
     pub async fn run_interactive(&mut self) -> AnvilResult<()> {
-        println!("🔨 Anvil Rust Shell v{}", env!("CARGO_PKG_VERSION"));
-        println!("Type 'help()' for help, 'exit()' or Ctrl+D to quit");
-        println!();
-
-        // Add prelude imports (avoiding duplicates) - do this once at start
-        let mut existing_imports: HashSet<String> = 
-            self.context.imports.iter().cloned().collect();
-        
-        for import in &self.config.repl.prelude.clone() {
-            if !existing_imports.contains(import) {
-                self.context.imports.push(import.clone());
-                existing_imports.insert(import.clone());
-            }
+        if let Some(banner) = self.banner() {
+            println!("{}", banner);
+            println!();
         }
 
+        self.add_prelude_imports();
+
         loop {
+            // Start continuation lines at the depth of the open blocks,
+            // unless the line continues a string. A closing bracket typed
+            // first takes one level back off.
+            let scan = scan_input(&self.context.continuation_buffer);
+            let mut dedent = 0;
+            if self.context.multiline_mode && !scan.open_literal {
+                let indent = " ".repeat(scan.depth * self.config.repl.indent_size);
+                dedent = indent.len().min(self.config.repl.indent_size);
+                self.editor.run_edit_commands(&[EditCommand::InsertString(indent)]);
+            }
+            self.dedent.store(dedent, Ordering::Relaxed);
+
+            *self.completion_variables.write().unwrap_or_else(PoisonError::into_inner) =
+                self.context.variables.clone();
             let sig = self.editor.read_line(&self.prompt);
             
             match sig {
@@ -158,15 +574,19 @@ impl ReplEngine {
                     }
 
                     // Handle special commands
-                    if let Some(result) = self.handle_special_command(line).await? {
-                        if result {
-                            break; // Exit requested
+                    match self.handle_special_command(line).await {
+                        Ok(Some(true)) => break, // Exit requested
+                        Ok(Some(false)) => continue,
+                        Ok(None) => {}
+                        Err(e) => {
+                            self.report_error(e)?;
+                            continue;
                         }
-                        continue;
                     }
 
                     // Handle multiline input
-                    if self.is_incomplete_input(line) {
+                    let pending = format!("{}{}", self.context.continuation_buffer, line);
+                    if self.is_incomplete_input(&pending) {
                         self.context.continuation_buffer.push_str(line);
                         self.context.continuation_buffer.push('\n');
                         self.context.multiline_mode = true;
@@ -189,15 +609,18 @@ impl ReplEngine {
                     match self.execute_rust_code(&full_input).await {
                         Ok(result) => {
                             if self.config.repl.auto_print {
-                                println!("{}", result.to_display_string());
+                                println!("{}", self.render_result(&result));
                             }
+                            self.context.last_result = Some(result);
+                            self.prompt.set_last_status(0);
                         }
                         Err(e) => {
-                            if e.is_recoverable() {
-                                eprintln!("Error: {}", e);
-                            } else {
-                                return Err(e);
-                            }
+                            let status = match e {
+                                AnvilError::ExternalCommand { code, .. } => code,
+                                _ => 1,
+                            };
+                            self.prompt.set_last_status(status);
+                            self.report_error(e)?;
                         }
                     }
                 }
@@ -225,6 +648,17 @@ impl ReplEngine {
         Ok(())
     }
 
+    /// Print a recoverable error and keep the session alive, or hand a
+    /// non-recoverable one back to the caller
+    fn report_error(&self, error: AnvilError) -> AnvilResult<()> {
+        if error.is_recoverable() {
+            print_error(&error);
+            Ok(())
+        } else {
+            Err(error)
+        }
+    }
+
     pub async fn execute_line(&mut self, line: &str) -> AnvilResult<ShellObject> {
         if line.trim().is_empty() {
             return Ok(ShellObject::Unit);
@@ -235,152 +669,444 @@ impl ReplEngine {
             return Ok(ShellObject::Unit);
         }
 
-        self.execute_rust_code(line).await
+        let result = self.execute_rust_code(line).await?;
+        self.context.last_result = Some(result.clone());
+        Ok(result)
     }
 
     async fn execute_rust_code(&mut self, code: &str) -> AnvilResult<ShellObject> {
-        // First, try to parse as a simple expression or statement
-        if let Ok(object) = self.try_simple_evaluation(code).await {
-            return Ok(object);
+        // First, try to evaluate with the interpreter
+        match self.try_simple_evaluation(code) {
+            Ok(object) => return Ok(object),
+            // The interpreter understood the code but it failed at runtime;
+            // compiling it would only bury the real error
+            Err(e @ AnvilError::Runtime { .. }) => return Err(e),
+            Err(_) => {}
         }
 
         // If that fails, compile and execute as full Rust code
         self.compile_and_execute(code).await
     }
 
-    async fn try_simple_evaluation(&self, code: &str) -> AnvilResult<ShellObject> {
-        let trimmed = code.trim();
+    fn try_simple_evaluation(&mut self, code: &str) -> AnvilResult<ShellObject> {
+        // Evaluate against the session variables so `let` bindings and
+        // assignments update them in place
+        let mut engine = EvaluationEngine::with_variables(std::mem::take(&mut self.context.variables));
+        let result = engine.evaluate_statement(code);
+        self.context.variables = engine.into_variables();
+        result
+    }
 
-        // Handle variable assignments
-        if let Some(captures) = Regex::new(r"^let\s+(\w+)\s*=\s*(.+)$").unwrap().captures(trimmed) {
-            let var_name = captures.get(1).unwrap().as_str();
-            let value_expr = captures.get(2).unwrap().as_str();
-            
-            // Evaluate the right-hand side
-            let value = self.evaluate_expression(value_expr)?;
-            // Note: In a real implementation, we'd store this in context
-            return Ok(value);
+    /// Call a function defined earlier in the session, if `name` is one
+    pub fn call_function(&self, name: &str, args: Vec<ShellObject>) -> Option<AnvilResult<ShellObject>> {
+        match self.context.variables.get(name) {
+            Some(ShellObject::Function(function)) => Some(
+                EvaluationEngine::with_variables(self.context.variables.clone())
+                    .call_function(function, args)
+            ),
+            _ => None,
         }
+    }
 
-        // Handle simple expressions
-        self.evaluate_expression(trimmed)
+    /// Evaluate an expression against the session variables without
+    /// modifying them
+    pub fn evaluate_expression(&self, expr: &str) -> AnvilResult<ShellObject> {
+        EvaluationEngine::with_variables(self.context.variables.clone())
+            .evaluate_expression(expr)
     }
 
-    fn evaluate_expression(&self, expr: &str) -> AnvilResult<ShellObject> {
-        // Handle literals
-        if let Ok(num) = expr.parse::<i64>() {
-            return Ok(ShellObject::Integer(num));
-        }
-        
-        if let Ok(num) = expr.parse::<f64>() {
-            return Ok(ShellObject::Float(num));
-        }
+    async fn compile_and_execute(&mut self, code: &str) -> AnvilResult<ShellObject> {
+        // Generate the full Rust program
+        let full_program = self.generate_rust_program(code)?;
+        let cached = self.backend() == ReplBackend::Rustc
+            && self.cached_binary_path(&full_program).exists();
+        let compile_start = Instant::now();
+        let exe_path = self.compile_program(&full_program).await?;
+        let compile_duration = compile_start.elapsed();
 
-        if expr == "true" {
-            return Ok(ShellObject::Boolean(true));
+        // Execute the compiled program
+        let exec_start = Instant::now();
+        let spinner = self.spinner("Running");
+        let exec_result = self.run_compiled(&exe_path).await;
+        drop(spinner);
+
+        self.context.last_timings = Some(CompileTimings {
+            compile: compile_duration,
+            execute: exec_start.elapsed(),
+            cached,
+        });
+
+        // Parse the output back to a ShellObject
+        let result = self.parse_output(&exec_result?)?;
+
+        // Store successful code in history
+        self.context.code_history.push(code.to_string());
+
+        Ok(result)
+    }
+
+    /// Build a generated program with the session's backend
+    async fn compile_program(&self, program: &str) -> AnvilResult<PathBuf> {
+        let _spinner = self.spinner("Compiling");
+        match self.backend() {
+            ReplBackend::Rustc => self.compile_cached(program).await,
+            ReplBackend::Cargo => self.compile_with_cargo(program).await,
         }
-        
-        if expr == "false" {
-            return Ok(ShellObject::Boolean(false));
+    }
+
+    /// Run a compiled snippet under the sandbox settings and execution
+    /// timeout, returning what it printed
+    async fn run_compiled(&self, exe_path: &Path) -> AnvilResult<String> {
+        let mut command = Command::new(exe_path);
+        if self.config.repl.sandbox {
+            sandbox_command(&mut command, &self.config.repl);
         }
+        let limit = Duration::from_millis(self.config.repl.execution_timeout_ms);
+        let start = Instant::now();
 
-        // Handle string literals
-        if expr.starts_with('"') && expr.ends_with('"') && expr.len() >= 2 {
-            let content = &expr[1..expr.len()-1];
-            return Ok(ShellObject::String(content.to_string()));
+        // Dropping the timed out future kills the program
+        let Ok(exec_result) = tokio::time::timeout(limit, output_interruptible(command)).await else {
+            return Err(AnvilError::timeout("execution", start.elapsed().as_millis() as u64));
+        };
+
+        let exec_output = exec_result
+            .map_err(|e| if self.config.repl.sandbox {
+                AnvilError::runtime(format!(
+                    "Failed to set up the sandbox: {} (network isolation needs unprivileged user \
+                     namespaces; set repl.sandbox = false to run without it)",
+                    e
+                ))
+            } else {
+                AnvilError::runtime(format!("Failed to execute: {}", e))
+            })?
+            .ok_or_else(|| AnvilError::external_command("anvil_repl", 130))?;
+
+        if !exec_output.status.success() {
+            let stderr = String::from_utf8_lossy(&exec_output.stderr);
+            return Err(AnvilError::runtime(format!("Runtime error:\n{}", stderr)));
         }
 
-        // Handle variable references
-        if let Some(value) = self.context.variables.get(expr) {
-            return Ok(value.clone());
+        Ok(String::from_utf8_lossy(&exec_output.stdout).into_owned())
+    }
+
+    /// Time `runs` evaluations of an expression and summarize them. Code the
+    /// interpreter handles runs in-process; anything else is compiled once
+    /// and the binary run repeatedly.
+    async fn bench(&mut self, args: &str) -> AnvilResult<ShellObject> {
+        let (expr, runs) = parse_bench_args(args)?;
+        let mut samples = Vec::with_capacity(runs);
+
+        match self.evaluate_expression(expr) {
+            Ok(_) => {
+                let engine = EvaluationEngine::with_variables(self.context.variables.clone());
+                for _ in 0..runs {
+                    let start = Instant::now();
+                    engine.evaluate_expression(expr)?;
+                    samples.push(start.elapsed());
+                }
+            }
+            Err(e @ AnvilError::Runtime { .. }) => return Err(e),
+            Err(_) => {
+                let exe_path = self.compile_program(&self.generate_rust_program(expr)?).await?;
+                let _spinner = self.spinner("Benchmarking");
+                for _ in 0..runs {
+                    let start = Instant::now();
+                    self.run_compiled(&exe_path).await?;
+                    samples.push(start.elapsed());
+                }
+            }
         }
 
-        // If we can't evaluate it simply, we'll need to compile it
-        Err(AnvilError::eval("Cannot evaluate expression without compilation"))
+        Ok(bench_stats(samples))
     }
 
-    async fn compile_and_execute(&mut self, code: &str) -> AnvilResult<ShellObject> {
-        let start_time = Instant::now();
+    /// A spinner for a slow step, when enabled and stderr is a terminal
+    fn spinner(&self, label: &str) -> Option<Spinner> {
+        let enabled = self.config.repl.spinner && std::io::IsTerminal::is_terminal(&std::io::stderr());
+        enabled.then(|| Spinner::start(label))
+    }
+
+    /// Compile a program with rustc, reusing a previous binary for the same
+    /// source from the cache directory when one exists. The binary is built
+    /// in a scratch directory and only moved into place once rustc succeeds,
+    /// so an interrupted build can't leave a broken binary behind.
+    async fn compile_cached(&self, program: &str) -> AnvilResult<PathBuf> {
+        let exe_path = self.cached_binary_path(program);
+        if exe_path.exists() {
+            return Ok(exe_path);
+        }
+
+        std::fs::create_dir_all(&self.config.paths.cache_dir)
+            .map_err(|e| AnvilError::runtime(format!("Failed to create cache directory: {}", e)))?;
 
         // Create a temporary Rust file
         let mut temp_file = NamedTempFile::new()
             .map_err(|e| AnvilError::runtime(format!("Failed to create temp file: {}", e)))?;
 
-        // Generate the full Rust program
-        let full_program = self.generate_rust_program(code)?;
-        
-        temp_file.write_all(full_program.as_bytes())
+        temp_file.write_all(program.as_bytes())
             .map_err(|e| AnvilError::runtime(format!("Failed to write temp file: {}", e)))?;
 
-        let temp_path = temp_file.path().to_path_buf();
-        
+        // Build next to the cache so the final rename stays on one filesystem
+        let build_dir = tempfile::Builder::new()
+            .prefix("anvil_build_")
+            .tempdir_in(&self.config.paths.cache_dir)
+            .map_err(|e| AnvilError::runtime(format!("Failed to create build directory: {}", e)))?;
+        let build_path = build_dir.path().join(exe_path.file_name().unwrap_or_default());
+
         // Compile the program
-        let exe_path = temp_path.with_extension("exe");
-        let compile_result = Command::new("rustc")
-            .arg(&temp_path)
+        let start_time = Instant::now();
+        let mut rustc = Command::new("rustc");
+        rustc.arg(temp_file.path())
             .arg("-o")
-            .arg(&exe_path)
-            .arg("--edition")
-            .arg("2021")
-            .arg("--crate-name")
-            .arg("anvil_repl")
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .output();
+            .arg(&build_path)
+            .args(RUSTC_FLAGS);
+        if color_enabled() {
+            rustc.arg("--color").arg("always");
+        }
+        let compile_result = output_interruptible(rustc).await;
 
         let compile_duration = start_time.elapsed();
         
         if compile_duration > Duration::from_millis(self.config.repl.compile_timeout_ms) {
-            return Err(AnvilError::compilation("Compilation timeout"));
+            return Err(AnvilError::timeout("compilation", compile_duration.as_millis() as u64));
         }
 
         let output = compile_result
-            .map_err(|e| AnvilError::compilation(format!("Failed to run rustc: {}", e)))?;
+            .map_err(|e| AnvilError::compilation(format!("Failed to run rustc: {}", e)))?
+            .ok_or_else(|| AnvilError::external_command("rustc", 130))?;
 
         if !output.status.success() {
             let stderr = String::from_utf8_lossy(&output.stderr);
-            return Err(AnvilError::compilation(format!("Compilation failed:\n{}", stderr)));
+            let source = temp_file.path().display().to_string();
+            return Err(AnvilError::compilation(format!(
+                "Compilation failed:\n{}",
+                map_diagnostics(&stderr, &source, program)
+            )));
         }
 
-        // Execute the compiled program
-        let exec_start = Instant::now();
-        let exec_result = Command::new(&exe_path)
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .output();
+        std::fs::rename(&build_path, &exe_path)
+            .map_err(|e| AnvilError::runtime(format!("Failed to cache compiled binary: {}", e)))?;
+        Ok(exe_path)
+    }
 
-        let exec_duration = exec_start.elapsed();
-        
-        if exec_duration > Duration::from_millis(self.config.repl.execution_timeout_ms) {
-            return Err(AnvilError::runtime("Execution timeout"));
+    /// The backend to compile with; declaring dependencies requires cargo
+    fn backend(&self) -> ReplBackend {
+        if self.context.dependencies.is_empty() {
+            self.config.repl.backend
+        } else {
+            ReplBackend::Cargo
         }
+    }
 
-        let exec_output = exec_result
-            .map_err(|e| AnvilError::runtime(format!("Failed to execute: {}", e)))?;
+    /// Open `editor` on a temporary Rust file and return what was written,
+    /// or None if the file was left empty
+    fn edit_in_editor(&self, editor: &str) -> AnvilResult<Option<String>> {
+        let file = tempfile::Builder::new()
+            .prefix("anvil_edit_")
+            .suffix(".rs")
+            .tempfile()
+            .map_err(|e| AnvilError::runtime(format!("Failed to create temp file: {}", e)))?;
 
-        // Clean up
-        let _ = std::fs::remove_file(&exe_path);
+        // Go through the shell so editors configured with arguments work
+        let status = if cfg!(windows) {
+            let mut parts = editor.split_whitespace();
+            Command::new(parts.next().unwrap_or(DEFAULT_EDITOR))
+                .args(parts)
+                .arg(file.path())
+                .status()
+        } else {
+            Command::new("sh")
+                .arg("-c")
+                .arg(format!("{} \"$@\"", editor))
+                .arg(editor)
+                .arg(file.path())
+                .status()
+        }
+        .map_err(|e| AnvilError::command(format!("Failed to start editor {}: {}", editor, e)))?;
 
-        if !exec_output.status.success() {
-            let stderr = String::from_utf8_lossy(&exec_output.stderr);
-            return Err(AnvilError::runtime(format!("Runtime error:\n{}", stderr)));
+        if !status.success() {
+            return Err(AnvilError::external_command(editor, status.code().unwrap_or(-1)));
         }
 
-        // Parse the output back to a ShellObject
-        let stdout = String::from_utf8_lossy(&exec_output.stdout);
-        let result = self.parse_output(&stdout)?;
+        let code = std::fs::read_to_string(file.path())
+            .map_err(|e| AnvilError::io_at(file.path().display().to_string(), e))?;
+        let code = code.trim();
+        Ok((!code.is_empty()).then(|| code.to_string()))
+    }
 
-        // Store successful code in history
-        self.context.code_history.push(code.to_string());
+    /// Write the session's variables, functions, imports and history to a
+    /// JSON file
+    fn save_session(&self, path: &Path) -> AnvilResult<()> {
+        let mut snapshot = SessionSnapshot {
+            functions: self.context.functions.iter().map(|(k, v)| (k.clone(), v.clone())).collect(),
+            imports: self.context.imports.clone(),
+            code_history: self.context.code_history.clone(),
+            dependencies: self.context.dependencies.clone(),
+            ..Default::default()
+        };
+
+        for (name, value) in &self.context.variables {
+            match serde_json::to_value(value) {
+                Ok(json) => {
+                    snapshot.variables.insert(name.clone(), json);
+                }
+                Err(e) => eprintln!("Warning: not saving variable {}: {}", name, e),
+            }
+        }
 
-        Ok(result)
+        let content = serde_json::to_string_pretty(&snapshot)?;
+        std::fs::write(path, content)
+            .map_err(|e| AnvilError::io_at(path.display().to_string(), e))
+    }
+
+    /// Replace the session state with a snapshot written by `save_session`
+    fn load_session(&mut self, path: &Path) -> AnvilResult<()> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| AnvilError::io_at(path.display().to_string(), e))?;
+        let snapshot: SessionSnapshot = serde_json::from_str(&content)?;
+
+        let mut variables = HashMap::new();
+        for (name, json) in snapshot.variables {
+            match serde_json::from_value(json) {
+                Ok(value) => {
+                    variables.insert(name, value);
+                }
+                Err(e) => eprintln!("Warning: not loading variable {}: {}", name, e),
+            }
+        }
+
+        self.context.variables = variables;
+        self.context.functions = snapshot.functions.into_iter().collect();
+        self.context.imports = snapshot.imports;
+        self.context.code_history = snapshot.code_history;
+        self.context.dependencies = snapshot.dependencies;
+        Ok(())
+    }
+
+    /// Declare a crate dependency from a `:dep name = "version"` line and
+    /// rebuild the cargo project so resolution errors surface immediately
+    async fn add_dependency(&mut self, spec: &str) -> AnvilResult<()> {
+        let (name, version) = parse_dependency(spec)?;
+        let previous = self.context.dependencies.insert(name.clone(), version.clone());
+
+        let program = self.generate_rust_program("()")?;
+        if let Err(e) = self.compile_with_cargo(&program).await {
+            // Keep the session buildable by forgetting the bad declaration
+            match previous {
+                Some(previous) => self.context.dependencies.insert(name, previous),
+                None => self.context.dependencies.remove(&name),
+            };
+            return Err(e);
+        }
+
+        println!("✓ Added dependency {} = \"{}\"", name, version);
+        Ok(())
+    }
+
+    /// Build a program inside the persistent cargo project, letting cargo
+    /// handle incremental rebuilds and dependencies
+    async fn compile_with_cargo(&self, program: &str) -> AnvilResult<PathBuf> {
+        let project_dir = self.write_cargo_project(program)?;
+
+        let start_time = Instant::now();
+        let mut cargo = Command::new("cargo");
+        cargo.arg("build")
+            .arg("--quiet")
+            .arg("--manifest-path")
+            .arg(project_dir.join("Cargo.toml"));
+        if color_enabled() {
+            cargo.arg("--color").arg("always");
+        }
+        let compile_result = output_interruptible(cargo).await;
+
+        let compile_duration = start_time.elapsed();
+
+        if compile_duration > Duration::from_millis(self.config.repl.compile_timeout_ms) {
+            return Err(AnvilError::timeout("compilation", compile_duration.as_millis() as u64));
+        }
+
+        let output = compile_result
+            .map_err(|e| AnvilError::compilation(format!("Failed to run cargo: {}", e)))?
+            .ok_or_else(|| AnvilError::external_command("cargo", 130))?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(AnvilError::compilation(format!(
+                "Compilation failed:\n{}",
+                map_diagnostics(&stderr, "src/main.rs", program)
+            )));
+        }
+
+        Ok(project_dir
+            .join("target")
+            .join("debug")
+            .join(format!("anvil_repl{}", std::env::consts::EXE_SUFFIX)))
+    }
+
+    /// Create or update the cargo project used by the cargo backend,
+    /// returning its directory
+    fn write_cargo_project(&self, program: &str) -> AnvilResult<PathBuf> {
+        let project_dir = self.config.paths.cache_dir.join("repl_project");
+        let src_dir = project_dir.join("src");
+        std::fs::create_dir_all(&src_dir)
+            .map_err(|e| AnvilError::runtime(format!("Failed to create cargo project: {}", e)))?;
+
+        // Only rewrite files whose contents changed so cargo's freshness
+        // checks aren't defeated by new timestamps
+        let files = [
+            (project_dir.join("Cargo.toml"), self.cargo_manifest()),
+            (src_dir.join("main.rs"), program.to_string()),
+        ];
+        for (path, content) in files {
+            if std::fs::read_to_string(&path).ok().as_deref() != Some(content.as_str()) {
+                std::fs::write(&path, content)
+                    .map_err(|e| AnvilError::runtime(format!("Failed to write {}: {}", path.display(), e)))?;
+            }
+        }
+
+        Ok(project_dir)
+    }
+
+    fn cargo_manifest(&self) -> String {
+        let mut manifest = String::from(
+            "[package]\nname = \"anvil_repl\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n[dependencies]\n"
+        );
+        for (name, version) in &self.context.dependencies {
+            manifest.push_str(&format!("{} = \"{}\"\n", name, version));
+        }
+        manifest
+    }
+
+    /// Location of the cached binary for a program. The key is a SHA-256 of
+    /// the whole generated source, the `rustc -V` output and the compile
+    /// flags, so changing imports or functions, or upgrading the toolchain,
+    /// invalidates it.
+    fn cached_binary_path(&self, program: &str) -> PathBuf {
+        let key = format!("{}\n{}\n{}", rustc_version(), RUSTC_FLAGS.join(" "), program);
+        let digest: String = Sha256::digest(key.as_bytes())[..16].iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        self.config.paths.cache_dir.join(format!("anvil_repl_{}{}", digest, std::env::consts::EXE_SUFFIX))
     }
 
     fn generate_rust_program(&self, code: &str) -> AnvilResult<String> {
-        let mut program = String::new();
+        if !self.config.repl.enable_unsafe {
+            let sources = std::iter::once(code).chain(self.context.functions.values().map(String::as_str));
+            for source in sources {
+                if contains_unsafe(source) {
+                    return Err(AnvilError::compilation(
+                        "unsafe code is disabled; set repl.enable_unsafe = true to allow it"
+                    ));
+                }
+            }
+        }
+
+        // The prelude imports more than most snippets use
+        let mut program = String::from("#![allow(unused_imports)]\n");
         
-        // Deduplicate imports
-        let mut unique_imports = HashSet::new();
+        // Deduplicate imports, keeping a stable order so the compile cache
+        // sees the same source for the same session state
+        let mut unique_imports = BTreeSet::new();
         
         // Add context imports
         for import in &self.context.imports {
@@ -394,33 +1120,51 @@ impl ReplEngine {
         }
         
         // Add any user-defined functions
-        for (_, func_code) in &self.context.functions {
+        let mut functions: Vec<_> = self.context.functions.iter().collect();
+        functions.sort();
+        for (_, func_code) in functions {
             program.push_str(func_code);
             program.push('\n');
         }
         
         program.push_str("\nfn main() {\n");
         
-        // Add the user code, wrapping it appropriately
+        // Add the user code on lines of its own, starting in the first
+        // column, so compiler positions translate directly
+        let user_code = format!("{}\n{}\n{}\n", USER_CODE_START, code, USER_CODE_END);
         if code.trim().ends_with(';') || code.contains("let ") || code.contains("fn ") {
             // It's a statement
-            program.push_str("    ");
-            program.push_str(code);
-            program.push('\n');
+            program.push_str(&user_code);
         } else {
-            // It's an expression, print the result
-            program.push_str("    let result = ");
-            program.push_str(code);
-            program.push_str(";\n");
-            program.push_str("    println!(\"{:?}\", result);\n");
+            // It's an expression, report the result as JSON
+            program.push_str("    let result =\n");
+            program.push_str(&user_code);
+            program.push_str("    ;\n");
+            program.push_str("    #[allow(unused_imports)]\n");
+            program.push_str("    use __anvil::{ViaDebug, ViaJson};\n");
+            program.push_str(&format!(
+                "    println!(\"{}{{}}\", (&__anvil::Wrap(&result)).result_json());\n",
+                RESULT_MARKER
+            ));
         }
         
         program.push_str("}\n");
+        program.push_str(RESULT_SUPPORT);
         
         Ok(program)
     }
 
     fn parse_output(&self, output: &str) -> AnvilResult<ShellObject> {
+        // Expressions report their value on a marker line; anything else the
+        // program printed is passed through
+        if let Some(json) = output.lines().rev().find_map(|l| l.strip_prefix(RESULT_MARKER)) {
+            for line in output.lines().filter(|l| !l.starts_with(RESULT_MARKER)) {
+                println!("{}", line);
+            }
+            let value: serde_json::Value = serde_json::from_str(json)?;
+            return Ok(ShellObject::from_json(&value));
+        }
+
         let trimmed = output.trim();
         
         // Try to parse common Rust debug output formats
@@ -456,6 +1200,59 @@ impl ReplEngine {
     }
 
     async fn handle_special_command(&mut self, line: &str) -> AnvilResult<Option<bool>> {
+        if let Some(args) = line.trim().strip_prefix(":bench ") {
+            let stats = self.bench(args).await?;
+            println!("{}", self.render_result(&stats));
+            self.context.last_result = Some(stats);
+            return Ok(Some(false));
+        }
+
+        if let Some(spec) = line.trim().strip_prefix(":dep ") {
+            self.add_dependency(spec).await?;
+            return Ok(Some(false));
+        }
+
+        if let Some(path) = line.trim().strip_prefix(":save ") {
+            let path = crate::utils::expand_user_path(Path::new(path.trim()))?;
+            self.save_session(&path)?;
+            println!("✓ Session saved to {}", path.display());
+            return Ok(Some(false));
+        }
+
+        if let Some(path) = line.trim().strip_prefix(":load ") {
+            let path = crate::utils::expand_user_path(Path::new(path.trim()))?;
+            self.load_session(&path)?;
+            println!("✓ Session loaded from {}", path.display());
+            return Ok(Some(false));
+        }
+
+        if let Some(args) = line.trim().strip_prefix(":config ") {
+            let args = crate::utils::parse_command_line(args)?;
+            match self.config.run_command(&args).await {
+                Ok(value) => println!("{}", ShellObject::from_toml(&value).to_display_string()),
+                Err(e) => print_error(&e),
+            }
+            return Ok(Some(false));
+        }
+
+        if line.trim() == ":reload" {
+            match self.reload_config().await {
+                Ok(()) => println!("✓ Configuration reloaded"),
+                Err(e) => print_error(&e),
+            }
+            return Ok(Some(false));
+        }
+
+        let mode_arg = line.trim().strip_prefix(":mode")
+            .filter(|rest| rest.is_empty() || rest.starts_with(char::is_whitespace));
+        if let Some(mode) = mode_arg {
+            match mode.parse::<DisplayMode>() {
+                Ok(mode) => self.config.repl.display_mode = mode,
+                Err(e) => print_error(&e),
+            }
+            return Ok(Some(false));
+        }
+
         match line.trim() {
             "exit()" | "quit()" => Ok(Some(true)),
             "help()" => {
@@ -474,6 +1271,36 @@ impl ReplEngine {
                 self.show_history();
                 Ok(Some(false))
             }
+            "methods()" => {
+                self.show_methods();
+                Ok(Some(false))
+            }
+            "table()" => {
+                self.show_table();
+                Ok(Some(false))
+            }
+            "timings()" => {
+                self.show_timings();
+                Ok(Some(false))
+            }
+            "type?()" => {
+                self.show_type();
+                Ok(Some(false))
+            }
+            "edit()" => {
+                let editor = std::env::var("VISUAL")
+                    .or_else(|_| std::env::var("EDITOR"))
+                    .unwrap_or_else(|_| DEFAULT_EDITOR.to_string());
+
+                if let Some(code) = self.edit_in_editor(&editor)? {
+                    let result = self.compile_and_execute(&code).await?;
+                    if self.config.repl.auto_print {
+                        println!("{}", self.render_result(&result));
+                    }
+                    self.context.last_result = Some(result);
+                }
+                Ok(Some(false))
+            }
             _ => Ok(None),
         }
     }
@@ -487,14 +1314,29 @@ Special Commands:
   exit()       - Exit the shell
   quit()       - Exit the shell  
   clear()      - Clear the screen
+  edit()       - Write code in $EDITOR and run it when the editor exits
   vars()       - Show defined variables
   history()    - Show command history
+  methods()    - Show methods available on the last result
+  type?()      - Show the type and fields of the last result
+  table()      - Show the last result (an array of maps) as a table
+  timings()    - Show compile and run times of the last compiled command
+  :dep name = "version"
+               - Add a crate dependency (builds with cargo)
+  :mode MODE   - Print results as compact, pretty, json or table
+  :bench EXPR [-n RUNS]
+               - Time RUNS evaluations of EXPR (default 10)
+  :save FILE   - Save variables, functions and history to a file
+  :load FILE   - Restore a session saved with :save
+  :reload      - Reload the config file and apply it to this session
+  :config get KEY / :config set KEY VALUE
+               - Read or change a setting, e.g. repl.auto_print
 
 Features:
   • Type any Rust expression or statement
   • Variables persist across commands
   • Multiline input supported (use incomplete syntax)
-  • Tab completion and history available
+  • Tab completion of fields and methods after `var.`, and history
   • File system operations as typed objects
 
 Examples:
@@ -518,6 +1360,93 @@ Press Ctrl+D or type exit() to quit.
         }
     }
 
+    fn show_methods(&self) {
+        match &self.context.last_result {
+            Some(result) => {
+                let methods = result.get_method_names();
+                if methods.is_empty() {
+                    println!("{} has no methods.", result.type_name());
+                } else {
+                    println!("Methods on {}:", result.type_name());
+                    for method in methods {
+                        println!("  .{}()", method);
+                    }
+                }
+            }
+            None => println!("No result available."),
+        }
+    }
+
+    /// The startup banner, or None when it's turned off
+    fn banner(&self) -> Option<String> {
+        let shell = &self.config.shell;
+        if !shell.show_banner {
+            return None;
+        }
+
+        let version = env!("CARGO_PKG_VERSION");
+        Some(match &shell.banner {
+            Some(banner) => banner.replace("{version}", version),
+            None => format!(
+                "🔨 Anvil Rust Shell v{}\nType 'help()' for help, 'exit()' or Ctrl+D to quit",
+                version
+            ),
+        })
+    }
+
+    /// Render a result for auto-printing in the configured display mode
+    fn render_result(&self, result: &ShellObject) -> String {
+        let limit = |n: usize| if n == 0 { usize::MAX } else { n };
+        let max_items = limit(self.config.repl.max_display_items);
+        let max_width = limit(self.config.repl.max_display_width);
+        let compact = || result.to_bounded_string(max_items, max_width);
+        match self.config.repl.display_mode {
+            DisplayMode::Compact => compact(),
+            DisplayMode::Pretty => result.to_bounded_pretty_string(self.config.repl.indent_size, max_items, max_width),
+            DisplayMode::Json => serde_json::to_string_pretty(&result.to_bounded_json(max_items, max_width))
+                .unwrap_or_else(|_| compact()),
+            DisplayMode::Table => match result {
+                ShellObject::Array(rows) if rows.len() > max_items => {
+                    let shown = ShellObject::Array(rows[..max_items].to_vec());
+                    match shown.to_table() {
+                        Some(table) => format!("{}\n... ({} more rows)", table, rows.len() - max_items),
+                        None => compact(),
+                    }
+                }
+                _ => result.to_table().unwrap_or_else(compact),
+            },
+        }
+    }
+
+    fn show_type(&self) {
+        match &self.context.last_result {
+            Some(result) => print!("{}", describe_type(result)),
+            None => println!("No result available."),
+        }
+    }
+
+    fn show_timings(&self) {
+        match &self.context.last_timings {
+            Some(timings) => {
+                let cached = if timings.cached { " (cached)" } else { "" };
+                println!("Compile: {}{}", format_duration(timings.compile), cached);
+                println!("Execute: {}", format_duration(timings.execute));
+                println!("Total:   {}", format_duration(timings.compile + timings.execute));
+            }
+            None => println!("No compiled command has run yet."),
+        }
+    }
+
+    fn show_table(&self) {
+        match &self.context.last_result {
+            Some(result) => match result.to_table() {
+                Some(table) => println!("{}", table),
+                None => println!("{} can't be shown as a table (expected an array of maps with the same keys).", result.type_name()),
+            },
+            None => println!("No result available."),
+        }
+    }
+
     fn show_history(&self) {
         if self.context.code_history.is_empty() {
             println!("No history available.");
@@ -529,19 +1458,289 @@ Press Ctrl+D or type exit() to quit.
         }
     }
 
-    fn is_incomplete_input(&self, line: &str) -> bool {
-        let trimmed = line.trim();
-        
-        // Simple heuristics for incomplete input
-        trimmed.ends_with('{') ||
-        trimmed.ends_with('(') ||
-        trimmed.ends_with('[') ||
-        (trimmed.starts_with("let ") && !trimmed.contains('=')) ||
-        (trimmed.starts_with("fn ") && !trimmed.contains('{')) ||
-        trimmed.ends_with('\\')
+    /// Whether the input so far needs more lines: a bracket or string
+    /// literal is still open, or the last line ends in a backslash
+    fn is_incomplete_input(&self, code: &str) -> bool {
+        let scan = scan_input(code);
+        code.trim_end().ends_with('\\') || scan.depth > 0 || scan.open_literal
     }
 }
 
+/// What's left open at the end of some REPL input
+struct InputScan {
+    /// Net count of brackets left open
+    depth: usize,
+    /// Whether the input ends inside a string or char literal
+    open_literal: bool,
+}
+
+/// Scan input for open brackets and literals, ignoring brackets inside
+/// string and char literals or line comments
+fn scan_input(code: &str) -> InputScan {
+    let mut depth: usize = 0;
+    let mut open_literal = false;
+    let mut chars = code.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '{' | '(' | '[' => depth += 1,
+            '}' | ')' | ']' => depth = depth.saturating_sub(1),
+            '/' if chars.peek() == Some(&'/') => {
+                chars.by_ref().take_while(|c| *c != '\n').for_each(drop);
+            }
+            // Strings may span lines
+            '"' => {
+                open_literal = true;
+                while let Some(c) = chars.next() {
+                    match c {
+                        '\\' => { chars.next(); }
+                        '"' => {
+                            open_literal = false;
+                            break;
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            // Char literals, but not lifetimes like 'a
+            '\'' => {
+                let rest: String = chars.clone().take(3).collect();
+                if rest.starts_with('\\') {
+                    chars.next();
+                    chars.next();
+                    open_literal = !chars.by_ref().any(|c| c == '\'');
+                } else if rest.chars().nth(1) == Some('\'') {
+                    chars.nth(1);
+                } else if rest.is_empty() {
+                    open_literal = true;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    InputScan { depth, open_literal }
+}
+
+/// `rustc -V` output, asked once per process. Empty if rustc can't be run,
+/// in which case compiling fails anyway.
+fn rustc_version() -> &'static str {
+    static VERSION: OnceLock<String> = OnceLock::new();
+    VERSION.get_or_init(|| {
+        Command::new("rustc")
+            .arg("-V")
+            .output()
+            .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+            .unwrap_or_default()
+    })
+}
+
+/// Limit a compiled snippet's CPU time to the execution timeout and its
+/// memory to `sandbox_memory_mb`, and on Linux cut it off from the network.
+/// The snippet fails to start if any of this can't be set up.
+#[cfg(unix)]
+fn sandbox_command(command: &mut Command, config: &ReplConfig) {
+    use std::os::unix::process::CommandExt;
+
+    let cpu_secs = config.execution_timeout_ms.div_ceil(1000);
+    let memory_bytes = config.sandbox_memory_mb.saturating_mul(1024 * 1024);
+
+    // SAFETY: only async-signal-safe libc calls run between fork and exec
+    unsafe {
+        command.pre_exec(move || {
+            let limit = |resource, value: u64| {
+                let rlim = libc::rlimit { rlim_cur: value as libc::rlim_t, rlim_max: value as libc::rlim_t };
+                match libc::setrlimit(resource, &rlim) {
+                    0 => Ok(()),
+                    _ => Err(std::io::Error::last_os_error()),
+                }
+            };
+            limit(libc::RLIMIT_CPU, cpu_secs)?;
+            limit(libc::RLIMIT_AS, memory_bytes)?;
+
+            // A new network namespace has no interfaces but loopback.
+            // Without unprivileged user namespaces this fails, and the
+            // snippet isn't run rather than run with network access.
+            #[cfg(target_os = "linux")]
+            if libc::unshare(libc::CLONE_NEWUSER | libc::CLONE_NEWNET) != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+
+            Ok(())
+        });
+    }
+}
+
+#[cfg(not(unix))]
+fn sandbox_command(_command: &mut Command, _config: &ReplConfig) {}
+
+/// Rewrite compiler output for a generated program so positions in the
+/// user's code read `<input>:line:col` counted from their first line, and
+/// other positions in the program read `<generated>` instead of a temp path.
+/// Snippet gutters are renumbered to match. Color codes are left intact.
+fn map_diagnostics(stderr: &str, source: &str, program: &str) -> String {
+    let lines: Vec<&str> = program.lines().collect();
+    let start = lines.iter().position(|l| *l == USER_CODE_START).map_or(0, |i| i + 2);
+    let end = lines.iter().position(|l| *l == USER_CODE_END).unwrap_or(0);
+    let map_line = |line: usize| (start..=end).contains(&line).then(|| line + 1 - start);
+
+    let location = Regex::new(&format!(r"{}:(\d+):(\d+)", regex::escape(source))).unwrap();
+    let gutter = Regex::new(r"^((?:\x1b\[[0-9;]*m)*)(\d+)( *(?:\x1b\[[0-9;]*m)* *(?:\x1b\[[0-9;]*m)*\|)").unwrap();
+
+    // Gutter numbers belong to whichever file the last location named
+    let mut in_program = false;
+    let mut mapped = Vec::new();
+    for line in stderr.lines() {
+        if line.contains("--> ") || line.contains("::: ") {
+            in_program = line.contains(source);
+            mapped.push(location.replace_all(line, |caps: &regex::Captures| {
+                let number: usize = caps[1].parse().unwrap_or(0);
+                match map_line(number) {
+                    Some(user_line) => format!("<input>:{}:{}", user_line, &caps[2]),
+                    None => format!("<generated>:{}:{}", number, &caps[2]),
+                }
+            }).into_owned());
+        } else if in_program {
+            mapped.push(gutter.replace(line, |caps: &regex::Captures| {
+                let digits = &caps[2];
+                let number = digits.parse().ok().and_then(map_line);
+                let number = number.map_or(digits.to_string(), |n| n.to_string());
+                format!("{}{:<width$}{}", &caps[1], number, &caps[3], width = digits.len())
+            }).into_owned());
+        } else {
+            mapped.push(line.to_string());
+        }
+    }
+    mapped.join("\n")
+}
+
+/// Whether code uses the `unsafe` keyword, ignoring strings and comments
+fn contains_unsafe(code: &str) -> bool {
+    fn scan(tokens: proc_macro2::TokenStream) -> bool {
+        tokens.into_iter().any(|token| match token {
+            proc_macro2::TokenTree::Ident(ident) => ident == "unsafe",
+            proc_macro2::TokenTree::Group(group) => scan(group.stream()),
+            _ => false,
+        })
+    }
+
+    match code.parse::<proc_macro2::TokenStream>() {
+        Ok(tokens) => scan(tokens),
+        // Code that doesn't tokenize won't compile either; err on the side
+        // of refusing it if the keyword appears at all
+        Err(_) => code.contains("unsafe"),
+    }
+}
+
+/// The type of a value and its field names, as shown by `type?()`
+fn describe_type(object: &ShellObject) -> String {
+    let mut description = format!("Type: {}\n", object.type_name());
+    let fields = object.field_names();
+    if !fields.is_empty() {
+        description.push_str("Fields:\n");
+        for field in fields {
+            description.push_str(&format!("  .{}\n", field));
+        }
+    }
+    description
+}
+
+/// Split `:bench` arguments into the expression and run count, taking
+/// `-n RUNS` from either end
+fn parse_bench_args(args: &str) -> AnvilResult<(&str, usize)> {
+    let args = args.trim();
+    let (expr, runs) = if let Some(rest) = args.strip_prefix("-n ") {
+        let rest = rest.trim_start();
+        let (runs, expr) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+        (expr.trim(), Some(runs))
+    } else if let Some((expr, runs)) = args.rsplit_once(" -n ") {
+        (expr.trim(), Some(runs.trim()))
+    } else {
+        (args, None)
+    };
+
+    let runs = match runs {
+        Some(runs) => runs.parse::<usize>().ok().filter(|n| *n > 0)
+            .ok_or_else(|| AnvilError::parse(format!("Expected a positive run count, got {:?}", runs)))?,
+        None => DEFAULT_BENCH_RUNS,
+    };
+    if expr.is_empty() {
+        return Err(AnvilError::parse("Usage: :bench EXPR [-n RUNS]"));
+    }
+    Ok((expr, runs))
+}
+
+/// Min, median, mean and max of benchmark samples as a map of durations
+fn bench_stats(mut samples: Vec<Duration>) -> ShellObject {
+    samples.sort();
+    let runs = samples.len();
+    let total: Duration = samples.iter().sum();
+    let median = if runs.is_multiple_of(2) {
+        (samples[runs / 2 - 1] + samples[runs / 2]) / 2
+    } else {
+        samples[runs / 2]
+    };
+
+    let mut stats = BTreeMap::new();
+    stats.insert("runs".to_string(), ShellObject::Integer(runs as i64));
+    stats.insert("min".to_string(), ShellObject::Duration(samples[0]));
+    stats.insert("median".to_string(), ShellObject::Duration(median));
+    stats.insert("mean".to_string(), ShellObject::Duration(total.div_f64(runs as f64)));
+    stats.insert("max".to_string(), ShellObject::Duration(samples[runs - 1]));
+    ShellObject::Map(stats)
+}
+
+/// Parse a `name = "version"` dependency declaration
+fn parse_dependency(spec: &str) -> AnvilResult<(String, String)> {
+    let (name, version) = spec.split_once('=')
+        .ok_or_else(|| AnvilError::parse("Expected :dep name = \"version\""))?;
+
+    let name = name.trim();
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+        return Err(AnvilError::parse(format!("Invalid crate name: '{}'", name)));
+    }
+
+    let version = version.trim();
+    let version = version.strip_prefix('"')
+        .and_then(|v| v.strip_suffix('"'))
+        .ok_or_else(|| AnvilError::parse("Version must be a quoted string, e.g. \"1.0\""))?;
+
+    if !is_valid_version_req(version) {
+        return Err(AnvilError::parse(format!("Invalid version requirement: '{}'", version)));
+    }
+
+    Ok((name.to_string(), version.to_string()))
+}
+
+/// Check a cargo version requirement such as `1`, `^0.4.2`, `>=1, <2` or `*`
+fn is_valid_version_req(req: &str) -> bool {
+    req.split(',').all(|part| {
+        let part = part.trim()
+            .trim_start_matches(['=', '^', '~', '>', '<'])
+            .trim();
+        if part == "*" {
+            return true;
+        }
+
+        let (core, pre) = match part.split_once('-') {
+            Some((core, pre)) => (core, Some(pre)),
+            None => (part, None),
+        };
+        let pre_ok = pre.is_none_or(|pre| {
+            !pre.is_empty() && pre.chars().all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-')
+        });
+
+        let numbers: Vec<&str> = core.split('.').collect();
+        let numbers_ok = (1..=3).contains(&numbers.len())
+            && numbers.iter().enumerate().all(|(i, n)| {
+                (!n.is_empty() && n.chars().all(|c| c.is_ascii_digit()))
+                    || (i > 0 && (*n == "*" || *n == "x"))
+            });
+
+        pre_ok && numbers_ok
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -564,4 +1763,637 @@ mod tests {
             _ => panic!("Expected integer"),
         }
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn test_assignment_updates_context() {
+        let config = Config::default();
+        let mut repl = ReplEngine::new(config).unwrap();
+
+        repl.execute_line("let scores = [1, 2, 3];").await.unwrap();
+        repl.execute_line("scores[0] = 10").await.unwrap();
+        let scores = repl.context.variables.get("scores").unwrap();
+        assert_eq!(scores.to_display_string(), "[10, 2, 3]");
+
+        let err = repl.execute_line("scores[5] = 1").await.unwrap_err();
+        assert!(matches!(err, AnvilError::Runtime { .. }));
+    }
+
+    #[tokio::test]
+    async fn test_compile_error_does_not_end_session() {
+        let config = Config::default();
+        let repl = ReplEngine::new(config).unwrap();
+
+        let err = AnvilError::compilation("expected `;`, found `}`");
+        assert!(repl.report_error(err).is_ok());
+        assert!(repl.report_error(AnvilError::runtime("panicked")).is_ok());
+        assert!(repl.report_error(AnvilError::timeout("execution", 30000)).is_ok());
+        assert!(repl.report_error(AnvilError::Interrupted).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_parse_output_result_marker() {
+        let config = Config::default();
+        let repl = ReplEngine::new(config).unwrap();
+
+        let output = "hello from user code\n__ANVIL_RESULT__{\"a\":[1,2.5,\"x\"],\"b\":null}\n";
+        let result = repl.parse_output(output).unwrap();
+        let ShellObject::Map(map) = result else { panic!("Expected map") };
+        match map.get("a") {
+            Some(ShellObject::Array(items)) => {
+                assert!(matches!(items[0], ShellObject::Integer(1)));
+                assert!(matches!(items[1], ShellObject::Float(f) if f == 2.5));
+                assert!(matches!(&items[2], ShellObject::String(s) if s == "x"));
+            }
+            other => panic!("Expected array, got {:?}", other),
+        }
+        assert!(matches!(map.get("b"), Some(ShellObject::Null)));
+
+        let program = repl.generate_rust_program("vec![1, 2]").unwrap();
+        assert!(program.contains(RESULT_MARKER));
+    }
+
+    #[test]
+    fn test_right_prompt_status_and_branch() {
+        let mut config = Config::default();
+        config.shell.show_git_branch = false;
+        let mut prompt = AnvilPrompt::new(&config);
+
+        assert!(!prompt.render_prompt_right().contains('✗'));
+        prompt.set_last_status(127);
+        assert!(prompt.render_prompt_right().starts_with("✗ 127"));
+
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join(".git")).unwrap();
+        std::fs::write(dir.path().join(".git/HEAD"), "ref: refs/heads/main\n").unwrap();
+        assert_eq!(prompt.git_branch(dir.path()).as_deref(), Some("main"));
+
+        // Cached until the next command runs
+        std::fs::write(dir.path().join(".git/HEAD"), "ref: refs/heads/dev\n").unwrap();
+        assert_eq!(prompt.git_branch(dir.path()).as_deref(), Some("main"));
+        prompt.set_last_status(0);
+        assert_eq!(prompt.git_branch(dir.path()).as_deref(), Some("dev"));
+    }
+
+    #[test]
+    fn test_prompt_template() {
+        let expanded = expand_prompt_template("{user}@{host}$ ");
+        assert_eq!(expanded, format!("{}@{}$ ", SystemUtils::username(), SystemUtils::hostname()));
+
+        assert_eq!(expand_prompt_template("{nope} {"), "{nope} {");
+        assert!(!expand_prompt_template("{cwd} {time}").contains('{'));
+
+        let home = dirs::home_dir().unwrap();
+        assert_eq!(collapse_home(&home), "~");
+        assert_eq!(collapse_home(&home.join("src")), format!("~{}src", std::path::MAIN_SEPARATOR));
+        assert_eq!(collapse_home(Path::new("/definitely/elsewhere")), "/definitely/elsewhere");
+    }
+
+    #[tokio::test]
+    async fn test_compile_cache_key() {
+        let config = Config::default();
+        let mut repl = ReplEngine::new(config).unwrap();
+
+        let program = repl.generate_rust_program("1 + 1").unwrap();
+        assert_eq!(program, repl.generate_rust_program("1 + 1").unwrap());
+        let cached = repl.cached_binary_path(&program);
+        assert_eq!(cached, repl.cached_binary_path(&program));
+
+        repl.context.imports.push("use std::time::Duration;".to_string());
+        let program = repl.generate_rust_program("1 + 1").unwrap();
+        assert_ne!(cached, repl.cached_binary_path(&program));
+    }
+
+    #[test]
+    fn test_map_diagnostics() {
+        let config = Config::default();
+        let repl = ReplEngine::new(config).unwrap();
+
+        let program = repl.generate_rust_program("foo\n    + 1").unwrap();
+        let line = program.lines().position(|l| l == "foo").unwrap() + 1;
+        let stderr = format!(
+            "error[E0425]: cannot find value `foo` in this scope\n \
+             --> /tmp/.tmpX1:{line}:1\n  |\n{line} | foo\n  | ^^^ not found in this scope\n\n\
+             warning: unused import: `std::fs`\n --> /tmp/.tmpX1:1:5\n",
+        );
+        let mapped = map_diagnostics(&stderr, "/tmp/.tmpX1", &program);
+        assert!(mapped.contains("--> <input>:1:1"), "{}", mapped);
+        // Renumbered gutters keep their width so the snippet stays aligned
+        let gutter = format!("\n{:<width$} | foo\n", 1, width = line.to_string().len());
+        assert!(mapped.contains(&gutter), "{}", mapped);
+        assert!(mapped.contains("--> <generated>:1:5"), "{}", mapped);
+        assert!(!mapped.contains("/tmp/.tmpX1"));
+
+        // Second line of the input, with rustc's color codes around the gutter
+        let colored = format!(
+            " \x1b[1m\x1b[94m--> \x1b[0m/tmp/.tmpX1:{next}:5\n\
+             \x1b[1m\x1b[94m{next}\x1b[0m \x1b[1m\x1b[94m|\x1b[0m     + 1",
+            next = line + 1,
+        );
+        let mapped = map_diagnostics(&colored, "/tmp/.tmpX1", &program);
+        assert!(mapped.contains("\x1b[0m<input>:2:5"), "{:?}", mapped);
+        let gutter = format!("\x1b[1m\x1b[94m{:<width$}\x1b[0m \x1b[1m\x1b[94m|", 2, width = (line + 1).to_string().len());
+        assert!(mapped.contains(&gutter), "{:?}", mapped);
+
+        // Notes pointing into other files keep their own numbers
+        let note = format!(" ::: /rustc/library/core/src/ops.rs:{line}:1\n{line} | fn add()");
+        assert_eq!(map_diagnostics(&note, "/tmp/.tmpX1", &program), note);
+    }
+
+    #[test]
+    fn test_unsafe_code_needs_enable_unsafe() {
+        let mut config = Config::default();
+        let mut repl = ReplEngine::new(config.clone()).unwrap();
+
+        let err = repl.generate_rust_program("unsafe { std::ptr::null::<u8>().read() }").unwrap_err();
+        assert!(matches!(err, AnvilError::Compilation { .. }));
+        assert!(err.is_recoverable());
+
+        // Only the keyword counts, not the word in strings or comments
+        assert!(repl.generate_rust_program("\"unsafe\".len() // unsafe").is_ok());
+
+        repl.context.functions.insert("f".to_string(), "unsafe fn f() {}".to_string());
+        assert!(repl.generate_rust_program("1 + 1").is_err());
+
+        config.repl.enable_unsafe = true;
+        repl.set_config(config);
+        assert!(repl.generate_rust_program("unsafe { 1 }").is_ok());
+    }
+
+    /// A REPL whose compiled program for `code` is the given shell script,
+    /// so no rustc is needed
+    #[cfg(unix)]
+    fn repl_with_script(config: &mut Config, dir: &Path, code: &str, script: &str) -> ReplEngine {
+        use std::os::unix::fs::PermissionsExt;
+
+        config.paths.cache_dir = dir.to_path_buf();
+        let repl = ReplEngine::new(config.clone()).unwrap();
+        let exe = repl.cached_binary_path(&repl.generate_rust_program(code).unwrap());
+        std::fs::write(&exe, format!("#!/bin/sh\n{}\n", script)).unwrap();
+        std::fs::set_permissions(&exe, std::fs::Permissions::from_mode(0o755)).unwrap();
+        repl
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_bench() {
+        fn stat(stats: &ShellObject, key: &str) -> ShellObject {
+            match stats {
+                ShellObject::Map(map) => map[key].clone(),
+                other => panic!("expected a map, got {:?}", other),
+            }
+        }
+
+        // The interactive prompt runs it as a special command, leaving the
+        // stats as the last result
+        let mut repl = ReplEngine::new(Config::default()).unwrap();
+        assert_eq!(repl.handle_special_command(":bench 1 + 1 -n 5").await.unwrap(), Some(false));
+        let stats = repl.context.last_result.clone().unwrap();
+        assert_eq!(stat(&stats, "runs"), ShellObject::Integer(5));
+        let [min, median, max] = ["min", "median", "max"].map(|key| match stat(&stats, key) {
+            ShellObject::Duration(d) => d,
+            other => panic!("expected a duration, got {:?}", other),
+        });
+        assert!(min <= median && median <= max);
+
+        assert_eq!(repl.execute_line(":bench -n 3 [1, 2].len()").await.unwrap(), ShellObject::Unit);
+        assert_eq!(stat(repl.context.last_result.as_ref().unwrap(), "runs"), ShellObject::Integer(3));
+
+        // Compiled code is built once and the binary run each time
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = Config::default();
+        let log = dir.path().join("runs.log");
+        let script = format!("echo run >> {}\necho __ANVIL_RESULT__42", log.display());
+        let mut repl = repl_with_script(&mut config, dir.path(), "answer()", &script);
+        repl.execute_line(":bench answer() -n 3").await.unwrap();
+        assert_eq!(stat(repl.context.last_result.as_ref().unwrap(), "runs"), ShellObject::Integer(3));
+        assert_eq!(std::fs::read_to_string(&log).unwrap().lines().count(), 3);
+
+        assert!(repl.execute_line(":bench 1 + 1 -n 0").await.is_err());
+        assert!(repl.execute_line(":bench -n 5").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_type_introspection() {
+        let mut repl = ReplEngine::new(Config::default()).unwrap();
+        assert_eq!(
+            repl.execute_line("typeof([1, 2])").await.unwrap(),
+            ShellObject::String("Array".to_string())
+        );
+        assert_eq!(repl.execute_line("type?()").await.unwrap(), ShellObject::Unit);
+
+        let mut map = BTreeMap::new();
+        map.insert("name".to_string(), ShellObject::String("anvil".to_string()));
+        assert_eq!(describe_type(&ShellObject::Map(map)), "Type: Map\nFields:\n  .name\n");
+        assert_eq!(describe_type(&ShellObject::Integer(1)), "Type: Integer\n");
+    }
+
+    #[test]
+    fn test_member_completions() {
+        let mut map = BTreeMap::new();
+        map.insert("name".to_string(), ShellObject::String("anvil".to_string()));
+        map.insert("not-an-ident".to_string(), ShellObject::Integer(1));
+        let mut variables = HashMap::new();
+        variables.insert("info".to_string(), ShellObject::Map(map));
+        variables.insert("items".to_string(), ShellObject::Array(vec![ShellObject::Integer(1)]));
+
+        let (start, candidates) = member_completions(&variables, "info.").unwrap();
+        assert_eq!(start, 5);
+        assert!(candidates.contains(&"name".to_string()));
+        assert!(candidates.contains(&"keys(".to_string()));
+        assert!(!candidates.iter().any(|c| c.contains('-')));
+
+        let (start, candidates) = member_completions(&variables, "let n = info.na").unwrap();
+        assert_eq!((start, candidates), (13, vec!["name".to_string()]));
+        let (_, candidates) = member_completions(&variables, "info.name.to_up").unwrap();
+        assert_eq!(candidates, vec!["to_uppercase(".to_string()]);
+        let (_, candidates) = member_completions(&variables, "items.fi").unwrap();
+        assert_eq!(candidates, vec!["filter(".to_string(), "first".to_string()]);
+
+        assert!(member_completions(&variables, "unknown.").is_none());
+        assert!(member_completions(&variables, "info").is_none());
+        assert!(member_completions(&variables, "items.len().").is_none());
+
+        // The completer reads the variables the session shares with it
+        let shared = SharedVariables::new(RwLock::new(variables));
+        let suggestions = AnvilCompleter { variables: shared }.complete("info.na", 7);
+        assert_eq!(suggestions.len(), 1);
+        assert_eq!(suggestions[0].value, "name");
+        assert_eq!(suggestions[0].span, Span::new(5, 7));
+    }
+
+    #[test]
+    fn test_bench_stats() {
+        let ms = Duration::from_millis;
+        let stats = bench_stats(vec![ms(4), ms(1), ms(3), ms(2)]);
+        let ShellObject::Map(stats) = stats else { panic!("expected a map") };
+        assert_eq!(stats["min"], ShellObject::Duration(ms(1)));
+        assert_eq!(stats["median"], ShellObject::Duration(Duration::from_micros(2500)));
+        assert_eq!(stats["mean"], ShellObject::Duration(Duration::from_micros(2500)));
+        assert_eq!(stats["max"], ShellObject::Duration(ms(4)));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_execution_timeout_kills_program() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = Config::default();
+        config.repl.execution_timeout_ms = 200;
+        let mut repl = repl_with_script(&mut config, dir.path(), "slow()", "sleep 10");
+
+        let start = Instant::now();
+        let err = repl.compile_and_execute("slow()").await.unwrap_err();
+        assert!(matches!(err, AnvilError::Timeout { .. }), "{:?}", err);
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    /// Whether this host lets an unprivileged process create the user and
+    /// network namespaces the Linux sandbox needs. Docker's default seccomp
+    /// profile and AppArmor's userns restriction both forbid it.
+    #[cfg(unix)]
+    fn sandbox_available() -> bool {
+        #[cfg(target_os = "linux")]
+        {
+            use std::os::unix::process::CommandExt;
+
+            let mut probe = Command::new("true");
+            // SAFETY: unshare is async-signal-safe
+            unsafe {
+                probe.pre_exec(|| match libc::unshare(libc::CLONE_NEWUSER | libc::CLONE_NEWNET) {
+                    0 => Ok(()),
+                    _ => Err(std::io::Error::last_os_error()),
+                });
+            }
+            probe.status().is_ok_and(|status| status.success())
+        }
+        #[cfg(not(target_os = "linux"))]
+        true
+    }
+
+    /// Run `script` as the sandboxed binary for `code`, or check that the
+    /// snippet is refused and return None where the sandbox can't be set up
+    #[cfg(unix)]
+    async fn run_sandboxed(code: &str, script: &str) -> Option<ShellObject> {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = Config::default();
+        config.repl.sandbox = true;
+        config.repl.sandbox_memory_mb = 256;
+        let mut repl = repl_with_script(&mut config, dir.path(), code, script);
+
+        let result = repl.compile_and_execute(code).await;
+        if !sandbox_available() {
+            let err = result.unwrap_err();
+            assert!(err.to_string().contains("Failed to set up the sandbox"), "{}", err);
+            return None;
+        }
+        Some(result.unwrap())
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_sandbox_limits() {
+        let script = format!("echo {}$(ulimit -v)", RESULT_MARKER);
+        let Some(result) = run_sandboxed("limits()", &script).await else { return };
+        assert!(matches!(result, ShellObject::Integer(kb) if kb == 256 * 1024), "{:?}", result);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_sandbox_network() {
+        // Only loopback is left; /proc/net/dev has two header lines
+        let script = format!("echo {}$(tail -n +3 /proc/net/dev | wc -l)", RESULT_MARKER);
+        let Some(result) = run_sandboxed("interfaces()", &script).await else { return };
+        assert_eq!(result, ShellObject::Integer(1));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_compile_timings() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = Config::default();
+        config.paths.cache_dir = dir.path().to_path_buf();
+        let mut repl = ReplEngine::new(config).unwrap();
+        assert!(repl.context.last_timings.is_none());
+
+        // Stand in a script for the cached binary so no rustc is needed
+        let program = repl.generate_rust_program("answer()").unwrap();
+        let exe = repl.cached_binary_path(&program);
+        std::fs::write(&exe, format!("#!/bin/sh\necho {}42\n", RESULT_MARKER)).unwrap();
+        std::fs::set_permissions(&exe, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let result = repl.compile_and_execute("answer()").await.unwrap();
+        assert!(matches!(result, ShellObject::Integer(42)));
+
+        let timings = repl.context.last_timings.unwrap();
+        assert!(timings.cached);
+        assert!(timings.execute > Duration::ZERO);
+    }
+
+    #[tokio::test]
+    async fn test_failed_compile_leaves_no_binary() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = Config::default();
+        config.paths.cache_dir = dir.path().to_path_buf();
+        let repl = ReplEngine::new(config).unwrap();
+
+        let program = "fn main() { let x: i64 = \"not a number\"; }";
+        assert!(repl.compile_cached(program).await.is_err());
+        assert!(!repl.cached_binary_path(program).exists());
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_parse_dependency() {
+        assert_eq!(
+            parse_dependency("serde = \"1\"").unwrap(),
+            ("serde".to_string(), "1".to_string())
+        );
+        assert_eq!(parse_dependency("rand=\"^0.8.5\"").unwrap().1, "^0.8.5");
+        assert!(parse_dependency("tokio = \">=1.2, <2\"").is_ok());
+        assert!(parse_dependency("foo = \"1.0.0-beta.1\"").is_ok());
+
+        assert!(parse_dependency("serde").is_err());
+        assert!(parse_dependency("serde = 1").is_err());
+        assert!(parse_dependency("serde = \"latest\"").is_err());
+        assert!(parse_dependency("bad name = \"1\"").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_cargo_project_layout() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = Config::default();
+        config.paths.cache_dir = dir.path().to_path_buf();
+        config.repl.backend = ReplBackend::Cargo;
+        let repl = ReplEngine::new(config).unwrap();
+
+        let program = repl.generate_rust_program("1 + 1").unwrap();
+        let project_dir = repl.write_cargo_project(&program).unwrap();
+
+        let manifest = std::fs::read_to_string(project_dir.join("Cargo.toml")).unwrap();
+        assert!(manifest.contains("name = \"anvil_repl\""));
+        assert!(manifest.ends_with("[dependencies]\n"));
+        let main = std::fs::read_to_string(project_dir.join("src/main.rs")).unwrap();
+        assert_eq!(main, program);
+    }
+
+    #[tokio::test]
+    async fn test_dependencies_select_cargo() {
+        let config = Config::default();
+        let mut repl = ReplEngine::new(config).unwrap();
+        assert_eq!(repl.backend(), ReplBackend::Rustc);
+
+        repl.context.dependencies.insert("serde".to_string(), "1".to_string());
+        assert_eq!(repl.backend(), ReplBackend::Cargo);
+        assert!(repl.cargo_manifest().ends_with("[dependencies]\nserde = \"1\"\n"));
+
+        let err = repl.execute_line(":dep serde = latest").await.unwrap_err();
+        assert!(err.is_recoverable());
+    }
+
+    #[test]
+    fn test_nesting_depth() {
+        assert_eq!(scan_input("fn f() {\n").depth, 1);
+        assert_eq!(scan_input("fn f() {\nif x {\n").depth, 2);
+        assert_eq!(scan_input("fn f() {\nif x {\n}\n").depth, 1);
+        assert_eq!(scan_input("let v = vec![\n(1, 2),\n").depth, 1);
+        assert_eq!(scan_input("}}").depth, 0);
+
+        // Brackets in literals and comments don't count
+        assert_eq!(scan_input("let s = \"{ \\\" (\"; // {\n").depth, 0);
+        assert_eq!(scan_input("let c = '{';\n").depth, 0);
+        assert_eq!(scan_input("let c = '\\'';\n").depth, 0);
+        assert_eq!(scan_input("fn f<'a>(x: &'a str) {\n").depth, 1);
+        assert_eq!(scan_input("let s = \"line one {\nline two\";\n").depth, 0);
+    }
+
+    #[test]
+    fn test_closing_bracket_dedents() {
+        let insert = |c| ReedlineEvent::Edit(vec![EditCommand::InsertChar(c)]);
+
+        let mut expected = vec![EditCommand::Backspace; 4];
+        expected.push(EditCommand::InsertChar('}'));
+        assert_eq!(dedent_closing_bracket(insert('}'), 4), ReedlineEvent::Edit(expected));
+        assert!(matches!(dedent_closing_bracket(insert(']'), 2), ReedlineEvent::Edit(c) if c.len() == 3));
+
+        // Other keys, and brackets on unindented or already edited lines, are left alone
+        assert_eq!(dedent_closing_bracket(insert('x'), 4), insert('x'));
+        assert_eq!(dedent_closing_bracket(insert('}'), 0), insert('}'));
+        assert_eq!(dedent_closing_bracket(ReedlineEvent::Enter, 4), ReedlineEvent::Enter);
+    }
+
+    #[test]
+    fn test_unterminated_literals() {
+        let repl = ReplEngine::new(Config::default()).unwrap();
+
+        assert!(repl.is_incomplete_input("let s = \"hello"));
+        assert!(repl.is_incomplete_input("let s = \"say \\\"hi\\\""));
+        assert!(repl.is_incomplete_input("let c = '\\"));
+        assert!(repl.is_incomplete_input("let c = '"));
+        assert!(!repl.is_incomplete_input("let s = \"hello\nworld\";"));
+        assert!(!repl.is_incomplete_input("let s = \"say \\\"hi\\\"\";"));
+        assert!(!repl.is_incomplete_input("let c = '\\n';"));
+        assert!(!repl.is_incomplete_input("fn f<'a>(x: &'a str) -> &'a str { x }"));
+    }
+
+    #[test]
+    fn test_incomplete_input() {
+        let repl = ReplEngine::new(Config::default()).unwrap();
+
+        assert!(repl.is_incomplete_input("fn f() {"));
+        assert!(repl.is_incomplete_input("let x = foo(1,\n2,"));
+        assert!(repl.is_incomplete_input("let v = [\n1, 2"));
+        assert!(repl.is_incomplete_input("let total = a +\\"));
+        assert!(!repl.is_incomplete_input("{ let x = 1; x }"));
+        assert!(!repl.is_incomplete_input("let x = foo(1,\n2)"));
+        assert!(!repl.is_incomplete_input("let s = \"(\"; // {"));
+    }
+
+    #[tokio::test]
+    async fn test_save_and_load_session() {
+        let dir = tempfile::tempdir().unwrap();
+        let session = dir.path().join("session.json");
+
+        let mut repl = ReplEngine::new(Config::default()).unwrap();
+        repl.execute_line("let xs = [1, 2, 3]").await.unwrap();
+        repl.execute_line("let double = |x| x * 2").await.unwrap();
+        repl.execute_line("fn greet(name) { \"hi \" + name }").await.unwrap();
+        repl.context.variables.insert("bad".to_string(), ShellObject::Float(f64::NAN));
+        repl.context.code_history.push("println!(\"x\")".to_string());
+        repl.execute_line(&format!(":save {}", session.display())).await.unwrap();
+
+        let mut restored = ReplEngine::new(Config::default()).unwrap();
+        restored.execute_line(&format!(":load {}", session.display())).await.unwrap();
+
+        let result = restored.execute_line("xs.len()").await.unwrap();
+        assert!(matches!(result, ShellObject::Integer(3)));
+        let result = restored.execute_line("double(4)").await.unwrap();
+        assert!(matches!(result, ShellObject::Integer(8)));
+        let result = restored.execute_line("greet(\"bob\")").await.unwrap();
+        assert!(matches!(result, ShellObject::String(s) if s == "hi bob"));
+        assert_eq!(restored.context.code_history, repl.context.code_history);
+
+        // A value that doesn't round-trip is skipped rather than failing the load
+        assert!(!restored.context.variables.contains_key("bad"));
+
+        let err = restored.execute_line(":load /no/such/anvil/session.json").await.unwrap_err();
+        assert!(err.is_recoverable());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_edit_in_editor() {
+        let repl = ReplEngine::new(Config::default()).unwrap();
+
+        let code = repl.edit_in_editor("printf 'let x = 1;\\nx + 1\\n' >").unwrap();
+        assert_eq!(code.as_deref(), Some("let x = 1;\nx + 1"));
+
+        // Quitting without writing anything runs nothing
+        assert_eq!(repl.edit_in_editor("true").unwrap(), None);
+
+        let err = repl.edit_in_editor("false").unwrap_err();
+        assert!(matches!(err, AnvilError::ExternalCommand { .. }));
+    }
+
+    #[test]
+    fn test_banner() {
+        let mut config = Config::default();
+        let repl = ReplEngine::new(config.clone()).unwrap();
+        assert!(repl.banner().unwrap().starts_with("🔨 Anvil Rust Shell v"));
+
+        config.shell.banner = Some("my shell {version}".to_string());
+        let repl = ReplEngine::new(config.clone()).unwrap();
+        assert_eq!(repl.banner().unwrap(), format!("my shell {}", env!("CARGO_PKG_VERSION")));
+
+        config.shell.show_banner = false;
+        let repl = ReplEngine::new(config).unwrap();
+        assert!(repl.banner().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_display_modes() {
+        let config = Config::default();
+        let mut repl = ReplEngine::new(config).unwrap();
+
+        let mut map = BTreeMap::new();
+        map.insert("items".to_string(), ShellObject::Array(vec![ShellObject::Integer(1), ShellObject::Integer(2)]));
+        map.insert("name".to_string(), ShellObject::String("x".to_string()));
+        let value = ShellObject::Map(map);
+
+        repl.execute_line(":mode pretty").await.unwrap();
+        assert_eq!(repl.config.repl.display_mode, DisplayMode::Pretty);
+        assert_eq!(
+            repl.render_result(&value),
+            "{\n    items: [\n        1,\n        2\n    ],\n    name: x\n}"
+        );
+
+        repl.execute_line(":mode json").await.unwrap();
+        let json: serde_json::Value = serde_json::from_str(&repl.render_result(&value)).unwrap();
+        assert_eq!(json["items"][1], 2);
+
+        // An unknown mode leaves the current one in place
+        repl.execute_line(":mode fancy").await.unwrap();
+        assert_eq!(repl.config.repl.display_mode, DisplayMode::Json);
+
+        // The command name has to stand on its own
+        assert_eq!(repl.handle_special_command(":modecompact").await.unwrap(), None);
+        assert_eq!(repl.config.repl.display_mode, DisplayMode::Json);
+    }
+
+    #[test]
+    fn test_spinner_gating() {
+        let mut config = Config::default();
+        config.repl.spinner = false;
+        let repl = ReplEngine::new(config).unwrap();
+        assert!(repl.spinner("Compiling").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_bounded_display() {
+        let mut config = Config::default();
+        config.repl.max_display_items = 3;
+        config.repl.max_display_width = 8;
+        let mut repl = ReplEngine::new(config).unwrap();
+
+        let big = ShellObject::Array((1..=1000).map(ShellObject::Integer).collect());
+        assert_eq!(repl.render_result(&big), "[1, 2, 3, ... (997 more)]");
+
+        let mut map = BTreeMap::new();
+        for key in ["a", "b", "c", "d"] {
+            map.insert(key.to_string(), ShellObject::String("a long string value".to_string()));
+        }
+        let map = ShellObject::Map(map);
+        assert_eq!(
+            repl.render_result(&map),
+            "{a: a lon..., b: a lon..., c: a lon..., ... (1 more)}"
+        );
+
+        repl.execute_line(":mode pretty").await.unwrap();
+        let small = ShellObject::Array((1..=4).map(ShellObject::Integer).collect());
+        assert_eq!(
+            repl.render_result(&small),
+            "[\n    1,\n    2,\n    3,\n    ... (1 more)\n]"
+        );
+
+        repl.execute_line(":mode table").await.unwrap();
+        let row = |n: i64| ShellObject::Map(BTreeMap::from([("n".to_string(), ShellObject::Integer(n))]));
+        let rows = ShellObject::Array((1..=5).map(row).collect());
+        assert_eq!(repl.render_result(&rows), "n\n-\n1\n2\n3\n... (2 more rows)");
+
+        repl.execute_line(":mode json").await.unwrap();
+        assert_eq!(
+            repl.render_result(&small),
+            "[\n  1,\n  2,\n  3,\n  \"... (1 more)\"\n]"
+        );
+        let json: serde_json::Value = serde_json::from_str(&repl.render_result(&map)).unwrap();
+        assert_eq!(json["a"], "a lon...");
+        assert_eq!(json["... (1 more)"], serde_json::Value::Null);
+        assert!(json.get("d").is_none());
+
+        // Zero lifts the limits
+        repl.config.repl.max_display_items = 0;
+        repl.config.repl.max_display_width = 0;
+        repl.execute_line(":mode compact").await.unwrap();
+        assert_eq!(repl.render_result(&big), big.to_display_string());
+    }
+}
@@ -2,20 +2,80 @@ use crate::config::Config;
 use crate::error::{AnvilError, AnvilResult};
 use crate::objects::ShellObject;
 use crate::repl::ReplEngine;
-use crate::commands::CommandRegistry;
-use std::collections::HashMap;
+use crate::commands::{CommandInfo, CommandRegistry};
+use crate::utils::{closest_match, parse_command_line_with, parse_command_words_with, print_error, PathUtils};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
 use tokio::fs;
 use regex::Regex;
 
+/// How the line editor encodes newlines inside a history entry
+const HISTORY_NEWLINE_ESCAPE: &str = "<\\n>";
+
+/// Read the entries saved in a history file, oldest first
+async fn read_history_file(path: &Path) -> AnvilResult<Vec<String>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let content = fs::read_to_string(path).await
+        .map_err(|e| AnvilError::io_at(path.display().to_string(), e))?;
+    Ok(content.lines()
+        .filter(|line| !line.is_empty())
+        .map(|line| line.replace(HISTORY_NEWLINE_ESCAPE, "\n"))
+        .collect())
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::metadata(path)
+        .map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+/// Commands handled directly by `try_builtin_command`
+const SHELL_BUILTINS: &[&str] = &[
+    "cd", "pwd", "ls", "echo", "env", "export", "unset", "alias", "which", "type",
+    "history", "jobs", "wait", "sleep", "retry", "watch", "read", "readline", "config", "exit", "quit",
+];
+
+/// Longest alias chain followed before giving up
+const MAX_ALIAS_DEPTH: usize = 32;
+
 pub struct Shell {
     config: Config,
     repl: ReplEngine,
     commands: CommandRegistry,
     env: HashMap<String, String>,
+    /// Variables passed on to child processes
+    exported: HashSet<String>,
     current_dir: PathBuf,
     aliases: HashMap<String, String>,
+    /// Background jobs started with a trailing `&`
+    jobs: Vec<Job>,
+    next_job_id: usize,
+    /// Directories allowed with `--allow-dirs`, kept apart from the config
+    /// so reloading it doesn't drop them and saving it doesn't persist them
+    cli_allowed_dirs: Vec<PathBuf>,
+    /// Buffered stdin for `read`, kept between calls so lines it read
+    /// ahead from piped input aren't lost
+    stdin: Option<tokio::io::BufReader<tokio::io::Stdin>>,
+    /// Set while `execute_script` runs, where `auto_cd` doesn't apply
+    running_script: bool,
+}
+
+/// An external command running in the background
+struct Job {
+    id: usize,
+    command: String,
+    child: tokio::process::Child,
 }
 
 impl Shell {
@@ -36,19 +96,69 @@ impl Shell {
             env.insert(key.clone(), value.clone());
         }
 
+        // Everything the shell starts with is visible to children
+        let exported = env.keys().cloned().collect();
+
         let current_dir = std::env::current_dir()
             .unwrap_or_else(|_| PathBuf::from("."));
 
         let aliases = config.aliases.clone();
 
-        Ok(Self {
+        let shell = Self {
             config,
             repl,
             commands,
             env,
+            exported,
             current_dir,
             aliases,
-        })
+            jobs: Vec::new(),
+            next_job_id: 1,
+            cli_allowed_dirs: Vec::new(),
+            stdin: None,
+            running_script: false,
+        };
+        shell.apply_command_config();
+        Ok(shell)
+    }
+
+    /// Confine file commands to `dirs` in addition to the config's
+    /// `paths.allowed_dirs`, for the rest of the session
+    pub fn allow_dirs(&mut self, dirs: Vec<PathBuf>) {
+        self.cli_allowed_dirs.extend(dirs);
+        self.apply_command_config();
+    }
+
+    /// The directories file commands are confined to; empty means anywhere
+    fn allowed_dirs(&self) -> Vec<PathBuf> {
+        let mut dirs = self.config.paths.allowed_dirs.clone();
+        dirs.extend(self.cli_allowed_dirs.iter().cloned());
+        dirs
+    }
+
+    /// Pass the settings registry commands read on to the registry
+    fn apply_command_config(&self) {
+        self.commands.set_allowed_dirs(self.allowed_dirs());
+        #[cfg(feature = "http")]
+        self.commands.set_http_timeout(std::time::Duration::from_millis(self.config.shell.http_timeout_ms));
+    }
+
+    /// Add a command to the shell's registry. Registered commands run ahead
+    /// of Rust evaluation and external programs, and replace any existing
+    /// command with the same name.
+    pub fn register_command(&mut self, info: CommandInfo) {
+        self.commands.register_command(info);
+    }
+
+    /// Load the config file again and apply it. Aliases from the file are
+    /// merged over the ones defined in this session.
+    pub async fn reload_config(&mut self) -> AnvilResult<()> {
+        let config = Config::load(self.config.loaded_from.as_deref()).await?;
+        self.aliases.extend(config.aliases.clone());
+        self.repl.set_config(config.clone());
+        self.config = config;
+        self.apply_command_config();
+        Ok(())
     }
 
     pub async fn run_repl(&mut self) -> AnvilResult<()> {
@@ -62,14 +172,58 @@ impl Shell {
             return Ok(ShellObject::Unit);
         }
 
-        // Check for shell built-ins first
+        // A trailing `&` runs an external command in the background
+        if let Some(background) = command.strip_suffix('&').filter(|c| !c.ends_with('&')) {
+            let background = background.trim_end();
+            let resolved = self.resolve_alias(background)
+                .unwrap_or_else(|| background.to_string());
+            return self.spawn_background_job(&resolved);
+        }
+
+        // Built-ins shadow aliases; any other alias expansion is final and
+        // isn't resolved again
+        let first_word = command.split_whitespace().next().unwrap_or_default();
+        match self.resolve_alias(command).filter(|_| !SHELL_BUILTINS.contains(&first_word)) {
+            Some(alias_command) => self.execute_resolved(&alias_command).await,
+            None => self.execute_resolved(command).await,
+        }
+    }
+
+    /// Run a command whose aliases have already been expanded
+    async fn execute_resolved(&mut self, command: &str) -> AnvilResult<ShellObject> {
         if let Some(result) = self.try_builtin_command(command).await? {
             return Ok(result);
         }
 
-        // Check for aliases
-        if let Some(alias_command) = self.resolve_alias(command) {
-            return Box::pin(self.execute_command(&alias_command)).await;
+        // User-defined functions take their words as string arguments
+        if let Ok(parts) = self.words(command) {
+            if let Some((name, args)) = parts.split_first() {
+                let args = args.iter().cloned().map(ShellObject::String).collect();
+                if let Some(result) = self.repl.call_function(name, args) {
+                    return result;
+                }
+            }
+        }
+
+        // Then commands in the registry, which take the same expanded
+        // arguments as external programs and run ahead of anything on PATH.
+        // One given a flag it doesn't implement defers to the real program.
+        let first_word = command.split_whitespace().next().unwrap_or_default();
+        if self.commands.has_command(first_word) {
+            let words = self.expanded_words(command)?;
+            if let Some((name, args)) = words.split_first() {
+                match self.commands.execute_command_async(name, args).await {
+                    Err(AnvilError::Unsupported { .. }) if which::which(name).is_ok() => {
+                        return self.execute_external_command(command).await;
+                    }
+                    result => return result,
+                }
+            }
+        }
+
+        if let Some(dir) = self.auto_cd_target(command) {
+            self.change_directory(&dir).await?;
+            return Ok(ShellObject::String(format!("Changed to {}", dir.display())));
         }
 
         // Try to execute as Rust code in the REPL
@@ -83,10 +237,20 @@ impl Shell {
     }
 
     pub async fn execute_script(&mut self, script_path: &Path) -> AnvilResult<()> {
-        let content = fs::read_to_string(script_path).await?;
-        let lines = content.lines();
+        let script_path = crate::utils::expand_user_path(script_path)?;
+        let content = fs::read_to_string(&script_path).await
+            .map_err(|e| AnvilError::io_at(script_path.display().to_string(), e))?;
+
+        self.running_script = true;
+        let result = self.run_script_lines(&content).await;
+        self.running_script = false;
+        result
+    }
 
-        for (line_num, line) in lines.enumerate() {
+    /// Run a script's lines in order, stopping at the first error that
+    /// isn't recoverable
+    async fn run_script_lines(&mut self, content: &str) -> AnvilResult<()> {
+        for (line_num, line) in content.lines().enumerate() {
             let line = line.trim();
             
             // Skip empty lines and comments
@@ -101,7 +265,7 @@ impl Shell {
                     }
                 }
                 Err(e) => {
-                    eprintln!("Error on line {}: {}", line_num + 1, e);
+                    print_error(format!("line {}: {}", line_num + 1, e));
                     if !e.is_recoverable() {
                         return Err(e);
                     }
@@ -129,7 +293,15 @@ impl Shell {
                     self.expand_path(args[0])
                 };
                 
-                self.change_directory(&target).await?;
+                if let Err(e) = self.change_directory(&target).await {
+                    return Err(match args.first().and_then(|arg| self.suggest_directory(arg)) {
+                        Some(suggestion) => AnvilError::command(format!(
+                            "cd: no such directory: {} (did you mean {}?)",
+                            args[0], suggestion
+                        )),
+                        None => e,
+                    });
+                }
                 Ok(Some(ShellObject::String(format!("Changed to {}", target.display()))))
             }
             "pwd" => {
@@ -142,17 +314,18 @@ impl Shell {
                     &self.expand_path(args[0])
                 };
                 
+                PathUtils::check_allowed(path, &self.allowed_dirs())?;
                 let entries = self.list_directory(path).await?;
                 Ok(Some(ShellObject::Array(entries)))
             }
             "echo" => {
-                let output = args.join(" ");
+                let output = self.words(command)?[1..].join(" ");
                 println!("{}", output);
                 Ok(Some(ShellObject::String(output)))
             }
             "env" => {
                 if args.is_empty() {
-                    let env_vars: HashMap<String, ShellObject> = self.env.iter()
+                    let env_vars: BTreeMap<String, ShellObject> = self.env.iter()
                         .map(|(k, v)| (k.clone(), ShellObject::String(v.clone())))
                         .collect();
                     Ok(Some(ShellObject::Map(env_vars)))
@@ -162,6 +335,7 @@ impl Shell {
                         let key = &args[0][..eq_pos];
                         let value = &args[0][eq_pos + 1..];
                         self.env.insert(key.to_string(), value.to_string());
+                        self.exported.insert(key.to_string());
                         std::env::set_var(key, value);
                         Ok(Some(ShellObject::String(format!("Set {}={}", key, value))))
                     } else {
@@ -173,10 +347,44 @@ impl Shell {
                     }
                 }
             }
+            "export" => {
+                if args.is_empty() {
+                    let exported: BTreeMap<String, ShellObject> = self.exported.iter()
+                        .filter_map(|k| self.env.get(k).map(|v| (k.clone(), ShellObject::String(v.clone()))))
+                        .collect();
+                    return Ok(Some(ShellObject::Map(exported)));
+                }
+
+                for arg in args {
+                    match arg.split_once('=') {
+                        Some((key, value)) => {
+                            self.env.insert(key.to_string(), value.to_string());
+                            std::env::set_var(key, value);
+                            self.exported.insert(key.to_string());
+                        }
+                        None => {
+                            // Mark an existing shell variable for inheritance
+                            if let Some(value) = self.env.get(*arg) {
+                                std::env::set_var(arg, value);
+                            }
+                            self.exported.insert(arg.to_string());
+                        }
+                    }
+                }
+                Ok(Some(ShellObject::Unit))
+            }
+            "unset" => {
+                for name in args {
+                    self.env.remove(*name);
+                    self.exported.remove(*name);
+                    std::env::remove_var(name);
+                }
+                Ok(Some(ShellObject::Unit))
+            }
             "alias" => {
                 if args.is_empty() {
                     // List all aliases
-                    let aliases: HashMap<String, ShellObject> = self.aliases.iter()
+                    let aliases: BTreeMap<String, ShellObject> = self.aliases.iter()
                         .map(|(k, v)| (k.clone(), ShellObject::String(v.clone())))
                         .collect();
                     Ok(Some(ShellObject::Map(aliases)))
@@ -196,11 +404,18 @@ impl Shell {
                 }
             }
             "which" => {
-                if args.is_empty() {
+                let all = args.contains(&"-a");
+                let Some(program) = args.iter().copied().find(|a| *a != "-a") else {
                     return Err(AnvilError::command("which: missing argument"));
+                };
+
+                if all {
+                    let matches = self.find_all_in_path(program).iter()
+                        .map(|path| ShellObject::String(path.to_string_lossy().to_string()))
+                        .collect();
+                    return Ok(Some(ShellObject::Array(matches)));
                 }
-                
-                let program = args[0];
+
                 match which::which(program) {
                     Ok(path) => Ok(Some(ShellObject::String(path.to_string_lossy().to_string()))),
                     Err(_) => Ok(Some(ShellObject::String(format!("{}: not found", program)))),
@@ -212,10 +427,15 @@ impl Shell {
                 }
                 
                 let name = args[0];
-                if self.aliases.contains_key(name) {
-                    Ok(Some(ShellObject::String(format!("{} is an alias", name))))
-                } else if self.commands.has_command(name) {
-                    Ok(Some(ShellObject::String(format!("{} is a builtin command", name))))
+                let registered = self.commands.get_command_info(name);
+
+                // Same precedence as execute_command: builtins shadow aliases
+                if SHELL_BUILTINS.contains(&name) {
+                    Ok(Some(ShellObject::String(format!("{} is a shell builtin", name))))
+                } else if let Some(expansion) = self.aliases.get(name) {
+                    Ok(Some(ShellObject::String(format!("{} is aliased to '{}'", name, expansion))))
+                } else if let Some(info) = registered {
+                    Ok(Some(ShellObject::String(format!("{} is a builtin command: {}", name, info.usage))))
                 } else {
                     match which::which(name) {
                         Ok(path) => Ok(Some(ShellObject::String(format!("{} is {}", name, path.display())))),
@@ -223,6 +443,116 @@ impl Shell {
                     }
                 }
             }
+            "history" => {
+                if args.first() == Some(&"-c") {
+                    self.config.clear_history().await?;
+                    return Ok(Some(ShellObject::Unit));
+                }
+
+                let entries = read_history_file(&self.config.shell.history_file).await?;
+                let count = match args.first() {
+                    Some(n) => n.parse::<usize>()
+                        .map_err(|_| AnvilError::command(format!("history: invalid count: {}", n)))?,
+                    None => entries.len(),
+                };
+
+                let start = entries.len().saturating_sub(count);
+                for (i, entry) in entries.iter().enumerate().skip(start) {
+                    println!("{:5}  {}", i + 1, entry);
+                }
+
+                Ok(Some(ShellObject::Array(
+                    entries[start..].iter().cloned().map(ShellObject::String).collect()
+                )))
+            }
+            "config" => {
+                let words = self.words(command)?;
+                let value = self.config.run_command(&words[1..]).await?;
+                self.apply_command_config();
+                self.repl.set_config(self.config.clone());
+                Ok(Some(ShellObject::from_toml(&value)))
+            }
+            ":reload" => {
+                self.reload_config().await?;
+                Ok(Some(ShellObject::Unit))
+            }
+            "jobs" => self.list_jobs().map(Some),
+            "wait" => {
+                if args.is_empty() {
+                    while let Some(job) = self.jobs.first() {
+                        self.wait_for_job(job.id).await?;
+                    }
+                    return Ok(Some(ShellObject::Unit));
+                }
+
+                let id = args[0].trim_start_matches('%').parse::<usize>()
+                    .map_err(|_| AnvilError::command(format!("wait: invalid job id: {}", args[0])))?;
+                self.wait_for_job(id).await.map(Some)
+            }
+            "sleep" => {
+                if args.is_empty() {
+                    return Err(AnvilError::command("sleep: missing duration"));
+                }
+
+                let duration = crate::utils::parse_duration(args[0])?;
+                tokio::time::sleep(duration).await;
+                Ok(Some(ShellObject::Unit))
+            }
+            "retry" => {
+                let usage = || AnvilError::command("usage: retry <attempts> [-d delay] -- <command>");
+                let (options, inner) = command.split_once(" -- ").ok_or_else(usage)?;
+                let options: Vec<&str> = options.split_whitespace().skip(1).collect();
+
+                let (attempts, delay) = match options.as_slice() {
+                    [n] => (*n, "1s"),
+                    [n, "-d", delay] => (*n, *delay),
+                    _ => return Err(usage()),
+                };
+                let attempts = attempts.parse::<u32>()
+                    .ok()
+                    .filter(|n| *n > 0)
+                    .ok_or_else(|| AnvilError::command(format!("retry: invalid attempt count: {}", attempts)))?;
+                let delay = crate::utils::parse_duration(delay)?;
+
+                self.execute_with_retry(inner.trim(), attempts, delay).await.map(Some)
+            }
+            "read" | "readline" => {
+                let parts = self.words(command)?;
+                let (prompt, name) = match &parts[1..] {
+                    [name] => (None, name.clone()),
+                    [flag, prompt, name] if flag == "-p" => (Some(prompt.clone()), name.clone()),
+                    _ => return Err(AnvilError::command(format!("usage: {} [-p prompt] <name>", cmd))),
+                };
+
+                if let Some(prompt) = prompt {
+                    print!("{}", prompt);
+                    std::io::Write::flush(&mut std::io::stdout())
+                        .map_err(|e| AnvilError::command(format!("{}: {}", cmd, e)))?;
+                }
+
+                let mut stdin = self.stdin.take()
+                    .unwrap_or_else(|| tokio::io::BufReader::new(tokio::io::stdin()));
+                let result = self.read_variable(&mut stdin, &name).await;
+                self.stdin = Some(stdin);
+                result.map(Some)
+            }
+            "watch" => {
+                let usage = || AnvilError::command("usage: watch [-n seconds] <command>");
+                let rest = command.trim_start().strip_prefix("watch").unwrap_or_default().trim_start();
+
+                let (interval, inner) = match rest.strip_prefix("-n") {
+                    Some(rest) => {
+                        let (secs, inner) = rest.trim_start().split_once(char::is_whitespace).ok_or_else(usage)?;
+                        (crate::utils::parse_duration(secs)?, inner.trim())
+                    }
+                    None => (std::time::Duration::from_secs(2), rest),
+                };
+                if inner.is_empty() {
+                    return Err(usage());
+                }
+
+                self.watch(inner, interval).await.map(Some)
+            }
             "exit" | "quit" => {
                 std::process::exit(0);
             }
@@ -231,13 +561,97 @@ impl Shell {
     }
 
     async fn execute_external_command(&mut self, command: &str) -> AnvilResult<ShellObject> {
-        let parts = self.parse_command_line(command)?;
-        if parts.is_empty() {
+        let Some((program, cmd)) = self.build_external_command(command)? else {
             return Ok(ShellObject::Unit);
+        };
+
+        // Ctrl+C kills the command rather than the shell
+        let output = crate::utils::output_interruptible(cmd).await
+            .map_err(|e| AnvilError::command(format!("Failed to execute {}: {}", program, e)))?
+            .ok_or_else(|| AnvilError::external_command(program.clone(), 130))?;
+
+        if !output.status.success() {
+            let code = output.status.code().unwrap_or(-1);
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            
+            if !stderr.is_empty() {
+                eprintln!("{}", stderr);
+            }
+            
+            return Err(AnvilError::external_command(program.to_string(), code));
         }
 
-        let program = &parts[0];
-        let args = &parts[1..];
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        Ok(ShellObject::String(stdout.to_string()))
+    }
+
+    /// Run an external command, retrying up to `attempts` times in total
+    /// when it fails to start or times out. The delay doubles after each
+    /// failed attempt. Commands that run and exit non-zero aren't retried.
+    pub async fn execute_with_retry(&mut self, command: &str, attempts: u32, delay: std::time::Duration) -> AnvilResult<ShellObject> {
+        let mut delay = delay;
+        let mut attempt = 1;
+
+        loop {
+            match self.execute_external_command(command).await {
+                Err(e @ (AnvilError::Command { .. } | AnvilError::Timeout { .. })) if attempt < attempts => {
+                    eprintln!("retry: attempt {}/{} failed: {}", attempt, attempts, e);
+                    tokio::time::sleep(delay).await;
+                    delay *= 2;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Read one line into the shell variable `name`. At end of input the
+    /// variable is set to an empty string.
+    async fn read_variable<R>(&mut self, mut reader: R, name: &str) -> AnvilResult<ShellObject>
+    where
+        R: tokio::io::AsyncBufRead + Unpin,
+    {
+        use tokio::io::AsyncBufReadExt;
+
+        let mut line = String::new();
+        reader.read_line(&mut line).await
+            .map_err(|e| AnvilError::command(format!("read: {}", e)))?;
+        let value = line.trim_end_matches(['\n', '\r']).to_string();
+
+        self.env.insert(name.to_string(), value.clone());
+        Ok(ShellObject::String(value))
+    }
+
+    /// Clear the screen and run a command every `interval` until Ctrl+C
+    async fn watch(&mut self, command: &str, interval: std::time::Duration) -> AnvilResult<ShellObject> {
+        loop {
+            print!("\x1B[2J\x1B[1;1H"); // ANSI clear screen
+            println!("Every {:.1}s: {}\n", interval.as_secs_f64(), command);
+
+            match Box::pin(self.execute_command(command)).await {
+                Ok(ShellObject::Unit) => {}
+                Ok(result) => print!("{}", result.to_display_string()),
+                // The command itself was interrupted
+                Err(AnvilError::ExternalCommand { code: 130, .. }) => return Ok(ShellObject::Unit),
+                Err(e) if e.is_recoverable() => print_error(&e),
+                Err(e) => return Err(e),
+            }
+            std::io::Write::flush(&mut std::io::stdout())?;
+
+            tokio::select! {
+                _ = tokio::time::sleep(interval) => {}
+                _ = tokio::signal::ctrl_c() => return Ok(ShellObject::Unit),
+            }
+        }
+    }
+
+    /// Resolve the program for a command line and set up its arguments,
+    /// working directory and exported environment
+    fn build_external_command(&self, command: &str) -> AnvilResult<Option<(String, Command)>> {
+        let words = self.expanded_words(command)?;
+        let Some((program, args)) = words.split_first() else {
+            return Ok(None);
+        };
 
         // Check if it's an executable in PATH or relative/absolute path
         let program_path = if program.contains('/') || program.contains('\\') {
@@ -246,7 +660,10 @@ impl Shell {
             match which::which(program) {
                 Ok(path) => path,
                 Err(_) => {
-                    return Err(AnvilError::command(format!("Command not found: {}", program)));
+                    let suggestion = self.suggest_command(program)
+                        .map(|name| format!(" (did you mean {}?)", name))
+                        .unwrap_or_default();
+                    return Err(AnvilError::command(format!("Command not found: {}{}", program, suggestion)));
                 }
             }
         };
@@ -256,27 +673,185 @@ impl Shell {
             .current_dir(&self.current_dir)
             .env_clear();
 
-        // Set environment variables
-        for (key, value) in &self.env {
+        // Pass on exported environment variables
+        for (key, value) in self.env.iter().filter(|(k, _)| self.exported.contains(*k)) {
             cmd.env(key, value);
         }
 
-        let output = cmd.output()
+        Ok(Some((program.clone(), cmd)))
+    }
+
+    /// Split a command line into words, expanding the shell's variables
+    fn words(&self, command: &str) -> AnvilResult<Vec<String>> {
+        parse_command_line_with(command, &|name| self.env.get(name).cloned())
+    }
+
+    /// Split a command line into the command name and its arguments, with
+    /// unquoted arguments expanded as by [`Shell::expand_argument`]
+    fn expanded_words(&self, command: &str) -> AnvilResult<Vec<String>> {
+        let words = parse_command_words_with(command, &|name| self.env.get(name).cloned())?;
+        let Some((program, rest)) = words.split_first() else {
+            return Ok(Vec::new());
+        };
+
+        let mut expanded = vec![program.text.clone()];
+        for word in rest {
+            if word.quoted {
+                expanded.push(word.text.clone());
+            } else {
+                expanded.extend(self.expand_argument(&word.text)?);
+            }
+        }
+        Ok(expanded)
+    }
+
+    /// Expand tilde, brace and glob patterns in an unquoted argument. Globs
+    /// are matched relative to the shell's directory; one that matches
+    /// nothing is kept literally unless `failglob` is set.
+    fn expand_argument(&self, word: &str) -> AnvilResult<Vec<String>> {
+        let mut expanded = Vec::new();
+
+        for pattern in crate::utils::expand_braces(&crate::utils::expand_tilde(word)) {
+            if !crate::utils::is_glob_pattern(&pattern) {
+                expanded.push(pattern);
+                continue;
+            }
+
+            let absolute = self.current_dir.join(&pattern);
+            let matches = crate::utils::glob_matches(&absolute.to_string_lossy());
+            if matches.is_empty() {
+                if self.config.shell.failglob {
+                    return Err(AnvilError::command(format!("No match: {}", pattern)));
+                }
+                expanded.push(pattern);
+                continue;
+            }
+
+            // Relative patterns expand to relative paths, as in bash
+            expanded.extend(matches.iter().map(|path| {
+                path.strip_prefix(&self.current_dir).ok()
+                    .filter(|_| Path::new(&pattern).is_relative())
+                    .unwrap_or(path)
+                    .to_string_lossy()
+                    .into_owned()
+            }));
+        }
+
+        Ok(expanded)
+    }
+
+    /// The builtin, alias, registered command or program on PATH whose name
+    /// is closest to a command that wasn't found
+    fn suggest_command(&self, typed: &str) -> Option<String> {
+        let mut names: Vec<String> = SHELL_BUILTINS.iter().map(|name| name.to_string()).collect();
+        names.extend(self.aliases.keys().cloned());
+        names.extend(self.commands.list_commands().iter().map(|info| info.name.clone()));
+        names.extend(self.path_executables());
+        closest_match(typed, names.iter().map(String::as_str)).map(str::to_string)
+    }
+
+    /// Names of the executables in the PATH directories
+    fn path_executables(&self) -> Vec<String> {
+        let Some(path_var) = self.env.get("PATH") else {
+            return Vec::new();
+        };
+
+        path_var.split(self.config.environment.path_separator.as_str())
+            .filter(|dir| !dir.is_empty())
+            .filter_map(|dir| std::fs::read_dir(dir).ok())
+            .flat_map(|entries| entries.filter_map(|entry| entry.ok()))
+            .filter(|entry| is_executable(&entry.path()))
+            .filter_map(|entry| entry.file_name().into_string().ok())
+            .collect()
+    }
+
+    /// Every executable called `name` on PATH, in search order
+    fn find_all_in_path(&self, name: &str) -> Vec<PathBuf> {
+        let Some(path_var) = self.env.get("PATH") else {
+            return Vec::new();
+        };
+
+        let mut seen = HashSet::new();
+        path_var.split(self.config.environment.path_separator.as_str())
+            .filter(|dir| !dir.is_empty())
+            .map(|dir| Path::new(dir).join(name))
+            .filter(|candidate| is_executable(candidate))
+            .filter(|candidate| seen.insert(candidate.clone()))
+            .collect()
+    }
+
+    /// Start an external command without waiting for it, returning its job id
+    fn spawn_background_job(&mut self, command: &str) -> AnvilResult<ShellObject> {
+        let Some((program, mut cmd)) = self.build_external_command(command)? else {
+            return Err(AnvilError::command("Expected a command before '&'"));
+        };
+        cmd.stdin(Stdio::null());
+
+        let child = tokio::process::Command::from(cmd)
+            .spawn()
             .map_err(|e| AnvilError::command(format!("Failed to execute {}: {}", program, e)))?;
 
-        if !output.status.success() {
-            let code = output.status.code().unwrap_or(-1);
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            
-            if !stderr.is_empty() {
-                eprintln!("{}", stderr);
+        let id = self.next_job_id;
+        self.next_job_id += 1;
+        println!("[{}] {}", id, child.id().unwrap_or_default());
+
+        self.jobs.push(Job {
+            id,
+            command: command.to_string(),
+            child,
+        });
+        Ok(ShellObject::Integer(id as i64))
+    }
+
+    /// List background jobs, reporting and forgetting any that have finished
+    fn list_jobs(&mut self) -> AnvilResult<ShellObject> {
+        let mut running = Vec::new();
+
+        let mut jobs = std::mem::take(&mut self.jobs).into_iter();
+        while let Some(mut job) = jobs.next() {
+            let status = match job.child.try_wait() {
+                Ok(status) => status,
+                Err(e) => {
+                    // Keep this job and the ones not checked yet
+                    let error = AnvilError::command(format!("jobs: [{}] {}: {}", job.id, job.command, e));
+                    self.jobs.push(job);
+                    self.jobs.extend(jobs);
+                    return Err(error);
+                }
+            };
+            match status {
+                Some(status) => {
+                    println!("[{}] Done ({})  {}", job.id, status.code().unwrap_or(-1), job.command);
+                }
+                None => {
+                    println!("[{}] Running  {}", job.id, job.command);
+                    let mut info = BTreeMap::new();
+                    info.insert("id".to_string(), ShellObject::Integer(job.id as i64));
+                    info.insert("pid".to_string(), ShellObject::Integer(job.child.id().unwrap_or_default() as i64));
+                    info.insert("command".to_string(), ShellObject::String(job.command.clone()));
+                    running.push(ShellObject::Map(info));
+                    self.jobs.push(job);
+                }
             }
-            
-            return Err(AnvilError::external_command(program.to_string(), code));
         }
 
-        let stdout = String::from_utf8_lossy(&output.stdout);
-        Ok(ShellObject::String(stdout.to_string()))
+        Ok(ShellObject::Array(running))
+    }
+
+    /// Wait for a background job to finish, returning its exit code
+    async fn wait_for_job(&mut self, id: usize) -> AnvilResult<ShellObject> {
+        let position = self.jobs.iter().position(|job| job.id == id)
+            .ok_or_else(|| AnvilError::command(format!("wait: no such job: {}", id)))?;
+        let mut job = self.jobs.remove(position);
+
+        match job.child.wait().await {
+            Ok(status) => Ok(ShellObject::Integer(status.code().unwrap_or(-1) as i64)),
+            Err(e) => {
+                let error = AnvilError::command(format!("wait: [{}] {}: {}", job.id, job.command, e));
+                self.jobs.insert(position, job);
+                Err(error)
+            }
+        }
     }
 
     async fn change_directory(&mut self, path: &Path) -> AnvilResult<()> {
@@ -302,6 +877,44 @@ impl Shell {
         Ok(())
     }
 
+    /// With `auto_cd`, the directory a command line made of one bare path
+    /// refers to. Words the interpreter can evaluate or that name a program
+    /// keep their usual meaning, and script lines are never taken as
+    /// directories to enter.
+    fn auto_cd_target(&self, command: &str) -> Option<PathBuf> {
+        if !self.config.shell.auto_cd || self.running_script {
+            return None;
+        }
+        let words = self.words(command).ok()?;
+        let [word] = words.as_slice() else { return None };
+
+        let target = self.expand_path(word);
+        if !self.current_dir.join(&target).is_dir()
+            || self.repl.evaluate_expression(word).is_ok()
+            || which::which(word).is_ok()
+        {
+            return None;
+        }
+        Some(target)
+    }
+
+    /// A directory next to a mistyped `cd` target whose name is close to
+    /// it, written the way the target was
+    fn suggest_directory(&self, typed: &str) -> Option<String> {
+        let target = self.current_dir.join(self.expand_path(typed));
+        if target.exists() {
+            return None;
+        }
+        let name = target.file_name()?.to_str()?;
+        let siblings: Vec<String> = std::fs::read_dir(target.parent()?).ok()?
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().is_dir())
+            .filter_map(|entry| entry.file_name().into_string().ok())
+            .collect();
+        let suggestion = closest_match(name, siblings.iter().map(String::as_str))?;
+        Some(Path::new(typed).with_file_name(suggestion).display().to_string())
+    }
+
     async fn list_directory(&self, path: &Path) -> AnvilResult<Vec<ShellObject>> {
         let mut entries = Vec::new();
         let mut dir = fs::read_dir(path).await
@@ -319,7 +932,7 @@ impl Shell {
                 "other"
             };
 
-            let mut entry_map = HashMap::new();
+            let mut entry_map = BTreeMap::new();
             entry_map.insert("name".to_string(), ShellObject::String(file_name));
             entry_map.insert("type".to_string(), ShellObject::String(entry_type.to_string()));
             entry_map.insert("size".to_string(), ShellObject::Integer(metadata.len() as i64));
@@ -330,35 +943,40 @@ impl Shell {
         Ok(entries)
     }
 
+    /// Expand aliases on the first word of a command, following chains
+    /// like `la` -> `ll -a` -> `ls -l -a`. Expansion stops when a word comes
+    /// round again, so a cyclic alias leaves the command where it started.
     fn resolve_alias(&self, command: &str) -> Option<String> {
-        let parts: Vec<&str> = command.split_whitespace().collect();
-        if parts.is_empty() {
-            return None;
-        }
+        let mut current = command.trim().to_string();
+        let mut visited = HashSet::new();
+        let mut expanded = false;
 
-        let cmd = parts[0];
-        if let Some(alias) = self.aliases.get(cmd) {
-            if parts.len() > 1 {
-                let args = parts[1..].join(" ");
-                Some(format!("{} {}", alias, args))
-            } else {
-                Some(alias.clone())
+        for _ in 0..MAX_ALIAS_DEPTH {
+            let (cmd, args) = match current.split_once(char::is_whitespace) {
+                Some((cmd, args)) => (cmd, args.trim_start()),
+                None => (current.as_str(), ""),
+            };
+
+            if !visited.insert(cmd.to_string()) {
+                break;
             }
-        } else {
-            None
+            let Some(alias) = self.aliases.get(cmd) else {
+                break;
+            };
+
+            current = if args.is_empty() {
+                alias.clone()
+            } else {
+                format!("{} {}", alias, args)
+            };
+            expanded = true;
         }
+
+        expanded.then_some(current)
     }
 
     fn expand_path(&self, path: &str) -> PathBuf {
-        if path.starts_with('~') {
-            if let Some(home) = dirs::home_dir() {
-                if path == "~" {
-                    return home;
-                } else if path.starts_with("~/") {
-                    return home.join(&path[2..]);
-                }
-            }
-        }
+        let path = crate::utils::expand_tilde(path);
 
         if path.starts_with('.') {
             self.current_dir.join(path)
@@ -367,41 +985,6 @@ impl Shell {
         }
     }
 
-    fn parse_command_line(&self, command: &str) -> AnvilResult<Vec<String>> {
-        let mut parts = Vec::new();
-        let mut current = String::new();
-        let mut in_quotes = false;
-        let mut escape_next = false;
-
-        for ch in command.chars() {
-            if escape_next {
-                current.push(ch);
-                escape_next = false;
-            } else if ch == '\\' {
-                escape_next = true;
-            } else if ch == '"' || ch == '\'' {
-                in_quotes = !in_quotes;
-            } else if ch.is_whitespace() && !in_quotes {
-                if !current.is_empty() {
-                    parts.push(current);
-                    current = String::new();
-                }
-            } else {
-                current.push(ch);
-            }
-        }
-
-        if !current.is_empty() {
-            parts.push(current);
-        }
-
-        if in_quotes {
-            return Err(AnvilError::parse("Unterminated quote"));
-        }
-
-        Ok(parts)
-    }
-
     /// Get current working directory
     pub fn current_dir(&self) -> &Path {
         &self.current_dir
@@ -427,6 +1010,7 @@ impl Shell {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::commands::CommandHandler;
     use tempfile::tempdir;
 
     #[tokio::test]
@@ -464,12 +1048,484 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn test_sleep_command() {
+        let config = Config::default();
+        let mut shell = Shell::new(config).await.unwrap();
+
+        let start = std::time::Instant::now();
+        let result = shell.execute_command("sleep 20ms").await.unwrap();
+        assert!(matches!(result, ShellObject::Unit));
+        assert!(start.elapsed() >= std::time::Duration::from_millis(20));
+
+        assert!(shell.execute_command("sleep soon").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_export_unset() {
+        let config = Config::default();
+        let mut shell = Shell::new(config).await.unwrap();
+
+        shell.execute_command("export ANVIL_TEST_EXPORT=one").await.unwrap();
+        assert_eq!(shell.env.get("ANVIL_TEST_EXPORT").map(String::as_str), Some("one"));
+        assert!(shell.exported.contains("ANVIL_TEST_EXPORT"));
+        assert_eq!(std::env::var("ANVIL_TEST_EXPORT").unwrap(), "one");
+
+        shell.execute_command("unset ANVIL_TEST_EXPORT").await.unwrap();
+        assert!(!shell.env.contains_key("ANVIL_TEST_EXPORT"));
+        assert!(!shell.exported.contains("ANVIL_TEST_EXPORT"));
+        assert!(std::env::var("ANVIL_TEST_EXPORT").is_err());
+
+        // Unsetting something that isn't set is fine
+        shell.execute_command("unset ANVIL_TEST_EXPORT").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_alias_chains_and_cycles() {
+        let config = Config::default();
+        let mut shell = Shell::new(config).await.unwrap();
+        shell.aliases.clear();
+
+        shell.aliases.insert("ll".to_string(), "ls -l".to_string());
+        shell.aliases.insert("la".to_string(), "ll -a".to_string());
+        assert_eq!(shell.resolve_alias("la src").as_deref(), Some("ls -l -a src"));
+        assert_eq!(shell.resolve_alias("unaliased x"), None);
+
+        shell.aliases.insert("ping".to_string(), "pong".to_string());
+        shell.aliases.insert("pong".to_string(), "ping".to_string());
+        assert_eq!(shell.resolve_alias("ping host").as_deref(), Some("ping host"));
+
+        shell.aliases.insert("greet".to_string(), "echo hi".to_string());
+        let result = shell.execute_command("greet there").await.unwrap();
+        assert!(matches!(result, ShellObject::String(s) if s == "hi there"));
+    }
+
+    #[tokio::test]
+    async fn test_history_command() {
+        let dir = tempdir().unwrap();
+        let mut config = Config::default();
+        config.shell.history_file = dir.path().join("history.txt");
+        std::fs::write(&config.shell.history_file, "ls\nlet x = 1;\nfn f() {<\\n>}\n").unwrap();
+        let mut shell = Shell::new(config).await.unwrap();
+
+        let result = shell.execute_command("history 2").await.unwrap();
+        match result {
+            ShellObject::Array(entries) => {
+                let entries: Vec<String> = entries.iter().map(|e| e.to_display_string()).collect();
+                assert_eq!(entries, vec!["let x = 1;", "fn f() {\n}"]);
+            }
+            other => panic!("Expected array, got {:?}", other),
+        }
+
+        shell.execute_command("history -c").await.unwrap();
+        let result = shell.execute_command("history").await.unwrap();
+        assert!(matches!(result, ShellObject::Array(entries) if entries.is_empty()));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_background_jobs() {
+        let config = Config::default();
+        let mut shell = Shell::new(config).await.unwrap();
+
+        let id = shell.execute_command("sleep 0.2 &").await.unwrap();
+        assert!(matches!(id, ShellObject::Integer(1)));
+
+        let jobs = shell.execute_command("jobs").await.unwrap();
+        assert!(matches!(jobs, ShellObject::Array(jobs) if jobs.len() == 1));
+
+        let status = shell.execute_command("wait 1").await.unwrap();
+        assert!(matches!(status, ShellObject::Integer(0)));
+        assert!(shell.execute_command("wait 1").await.is_err());
+
+        shell.execute_command("true &").await.unwrap();
+        shell.execute_command("wait").await.unwrap();
+        let jobs = shell.execute_command("jobs").await.unwrap();
+        assert!(matches!(jobs, ShellObject::Array(jobs) if jobs.is_empty()));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_which_all() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempdir().unwrap();
+        let mut dirs = Vec::new();
+        for name in ["first", "second", "third"] {
+            let bin = dir.path().join(name);
+            std::fs::create_dir(&bin).unwrap();
+            let tool = bin.join("anvil-tool");
+            std::fs::write(&tool, "").unwrap();
+            let mode = if name == "third" { 0o644 } else { 0o755 };
+            std::fs::set_permissions(&tool, std::fs::Permissions::from_mode(mode)).unwrap();
+            dirs.push(bin.to_string_lossy().to_string());
+        }
+
+        let config = Config::default();
+        let mut shell = Shell::new(config).await.unwrap();
+        shell.env.insert("PATH".to_string(), dirs.join(":"));
+
+        let result = shell.execute_command("which -a anvil-tool").await.unwrap();
+        let ShellObject::Array(paths) = result else { panic!("Expected array") };
+        let paths: Vec<String> = paths.iter().map(|p| p.to_display_string()).collect();
+        assert_eq!(paths, vec![
+            format!("{}/anvil-tool", dirs[0]),
+            format!("{}/anvil-tool", dirs[1]),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn test_type_command() {
+        let config = Config::default();
+        let mut shell = Shell::new(config).await.unwrap();
+        shell.aliases.insert("ll".to_string(), "ls -l".to_string());
+
+        let describe = |result: ShellObject| result.to_display_string();
+        assert_eq!(describe(shell.execute_command("type ll").await.unwrap()), "ll is aliased to 'ls -l'");
+        assert_eq!(describe(shell.execute_command("type cd").await.unwrap()), "cd is a shell builtin");
+        assert_eq!(
+            describe(shell.execute_command("type head").await.unwrap()),
+            "head is a builtin command: head [-n lines] <file>"
+        );
+        assert_eq!(
+            describe(shell.execute_command("type no-such-anvil-cmd").await.unwrap()),
+            "no-such-anvil-cmd: not found"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_shell_functions() {
+        let config = Config::default();
+        let mut shell = Shell::new(config).await.unwrap();
+
+        shell.execute_command("fn greet(name) { \"hello \" + name }").await.unwrap();
+        let result = shell.execute_command("greet \"big world\"").await.unwrap();
+        assert!(matches!(result, ShellObject::String(s) if s == "hello big world"));
+
+        let err = shell.execute_command("greet").await.unwrap_err();
+        assert!(err.to_string().contains("expects 1 argument"));
+    }
+
+    #[tokio::test]
+    async fn test_retry() {
+        let config = Config::default();
+        let mut shell = Shell::new(config).await.unwrap();
+
+        // Commands that can't start are retried with a growing delay
+        let start = std::time::Instant::now();
+        let err = shell.execute_command("retry 3 -d 20ms -- anvil-no-such-command").await.unwrap_err();
+        assert!(matches!(err, AnvilError::Command { .. }));
+        assert!(start.elapsed() >= std::time::Duration::from_millis(60));
+
+        // A clean non-zero exit is returned straight away
+        let start = std::time::Instant::now();
+        let err = shell.execute_command("retry 3 -d 1s -- false").await.unwrap_err();
+        assert!(matches!(err, AnvilError::ExternalCommand { .. }));
+        assert!(start.elapsed() < std::time::Duration::from_secs(1));
+
+        let result = shell.execute_command("retry 2 -- echo 'hi there'").await.unwrap();
+        assert!(matches!(result, ShellObject::String(s) if s == "hi there\n"));
+
+        assert!(shell.execute_command("retry 0 -- true").await.is_err());
+        assert!(shell.execute_command("retry 3 true").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_watch() {
+        let temp_dir = tempdir().unwrap();
+        let config = Config::default();
+        let mut shell = Shell::new(config).await.unwrap();
+        shell.current_dir = temp_dir.path().to_path_buf();
+
+        let watching = shell.execute_command("watch -n 50ms sh -c 'echo tick >> ticks'");
+        assert!(tokio::time::timeout(std::time::Duration::from_millis(300), watching).await.is_err());
+
+        let ticks = std::fs::read_to_string(temp_dir.path().join("ticks")).unwrap();
+        assert!(ticks.lines().count() >= 2);
+
+        assert!(shell.execute_command("watch").await.is_err());
+        assert!(shell.execute_command("watch -n 1").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_exit_codes() {
+        let config = Config::default();
+        let mut shell = Shell::new(config).await.unwrap();
+
+        let err = shell.execute_command("sh -c 'exit 3'").await.unwrap_err();
+        assert_eq!(err.exit_code(), 3);
+
+        let err = shell.execute_command("anvil-no-such-command").await.unwrap_err();
+        assert_eq!(err.exit_code(), crate::error::INTERNAL_ERROR_EXIT_CODE);
+    }
+
+    #[tokio::test]
+    async fn test_read_variable() {
+        let config = Config::default();
+        let mut shell = Shell::new(config).await.unwrap();
+
+        let result = shell.read_variable(&b"Ada Lovelace\nnext line\n"[..], "NAME").await.unwrap();
+        assert!(matches!(result, ShellObject::String(s) if s == "Ada Lovelace"));
+        assert_eq!(shell.env().get("NAME").map(String::as_str), Some("Ada Lovelace"));
+
+        // End of input reads as empty
+        let result = shell.read_variable(&b""[..], "NAME").await.unwrap();
+        assert!(matches!(result, ShellObject::String(s) if s.is_empty()));
+        assert_eq!(shell.env().get("NAME").map(String::as_str), Some(""));
+
+        assert!(shell.execute_command("read").await.is_err());
+        assert!(shell.execute_command("read -x prompt NAME").await.is_err());
+    }
+
     #[tokio::test]
     async fn test_command_parsing() {
         let config = Config::default();
         let shell = Shell::new(config).await.unwrap();
         
-        let parts = shell.parse_command_line("echo \"hello world\"").unwrap();
+        let parts = shell.words("echo \"hello world\"").unwrap();
         assert_eq!(parts, vec!["echo", "hello world"]);
     }
+
+    #[tokio::test]
+    async fn test_registered_commands() {
+        let config = Config::default();
+        let mut shell = Shell::new(config).await.unwrap();
+
+        shell.register_command(CommandInfo {
+            name: "shout".to_string(),
+            description: "Upper-case the arguments".to_string(),
+            usage: "shout <word>...".to_string(),
+            handler: CommandHandler::Sync(Box::new(|args| Ok(ShellObject::String(args.join(" ").to_uppercase())))),
+        });
+
+        let result = shell.execute_command("shout \"hello there\" you").await.unwrap();
+        assert!(matches!(result, ShellObject::String(s) if s == "HELLO THERE YOU"));
+
+        let result = shell.execute_command("type shout").await.unwrap();
+        assert!(matches!(result, ShellObject::String(s) if s.contains("builtin command")));
+    }
+
+    #[tokio::test]
+    async fn test_registry_commands_run_before_path() {
+        let dir = tempdir().unwrap();
+        std::fs::write(dir.path().join("a.txt"), "apple\nbanana\n").unwrap();
+        std::fs::write(dir.path().join("b.txt"), "grape\npineapple\n").unwrap();
+
+        let config = Config::default();
+        let mut shell = Shell::new(config).await.unwrap();
+
+        // /usr/bin/grep would return its output as a single string
+        let file = dir.path().join("a.txt");
+        let result = shell.execute_command(&format!("grep apple {}", file.display())).await.unwrap();
+        assert!(matches!(&result, ShellObject::Array(lines) if lines.len() == 1), "{:?}", result);
+
+        // Globs are expanded before the handler sees its arguments
+        let result = shell.execute_command(&format!("wc -l {}/*.txt", dir.path().display())).await.unwrap();
+        assert!(matches!(&result, ShellObject::Array(rows) if rows.len() == 3), "{:?}", result);
+
+        // A flag the registry command doesn't implement goes to the real program
+        if which::which("grep").is_ok() {
+            let result = shell.execute_command(&format!("grep -c apple {}", file.display())).await.unwrap();
+            assert!(matches!(&result, ShellObject::String(s) if s.trim() == "1"), "{:?}", result);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_config_builtin() {
+        let dir = tempdir().unwrap();
+        let mut config = Config::default();
+        config.paths.config_dir = dir.path().to_path_buf();
+        let mut shell = Shell::new(config).await.unwrap();
+
+        let result = shell.execute_command("config get repl.auto_print").await.unwrap();
+        assert_eq!(result, ShellObject::Boolean(true));
+
+        let result = shell.execute_command("config set shell.prompt \"my shell> \"").await.unwrap();
+        assert_eq!(result, ShellObject::String("my shell> ".to_string()));
+        assert_eq!(shell.config().shell.prompt, "my shell> ");
+        assert!(dir.path().join("config.toml").exists());
+
+        assert!(shell.execute_command("config set repl.auto_print maybe").await.is_err());
+        assert!(shell.execute_command("config get shell.nothing").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_reload_config() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        let mut config = Config::default();
+        config.paths.config_dir = dir.path().to_path_buf();
+        config.save(Some(&path)).await.unwrap();
+
+        let mut shell = Shell::new(Config::load(Some(&path)).await.unwrap()).await.unwrap();
+        shell.execute_command("alias mine=pwd").await.unwrap();
+
+        let mut edited = shell.config().clone();
+        edited.repl.compile_timeout_ms = 1234;
+        edited.aliases.insert("hi".to_string(), "echo hi".to_string());
+        edited.save(Some(&path)).await.unwrap();
+
+        shell.execute_command(":reload").await.unwrap();
+        assert_eq!(shell.config().repl.compile_timeout_ms, 1234);
+        assert!(shell.aliases.contains_key("hi"));
+        assert!(shell.aliases.contains_key("mine"));
+    }
+
+    #[tokio::test]
+    async fn test_cli_allowed_dirs() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        let allowed = dir.path().join("allowed");
+        std::fs::create_dir(&allowed).unwrap();
+        let mut config = Config::default();
+        config.paths.config_dir = dir.path().to_path_buf();
+        config.save(Some(&path)).await.unwrap();
+
+        let mut shell = Shell::new(Config::load(Some(&path)).await.unwrap()).await.unwrap();
+        shell.allow_dirs(vec![allowed.clone()]);
+        let outside = dir.path().display().to_string();
+        let denied = |result: AnvilResult<ShellObject>| matches!(result, Err(AnvilError::PermissionDenied { .. }));
+
+        assert!(denied(shell.execute_command(&format!("ls {}", outside)).await));
+        assert!(denied(shell.execute_command(&format!("find {}", outside)).await));
+        assert!(shell.execute_command(&format!("ls {}", allowed.display())).await.is_ok());
+
+        // Reloading or changing the config keeps the command-line directories
+        // but never writes them to the file
+        shell.execute_command(":reload").await.unwrap();
+        assert!(denied(shell.execute_command(&format!("ls {}", outside)).await));
+        shell.execute_command("config set repl.auto_print false").await.unwrap();
+        assert!(denied(shell.execute_command(&format!("find {}", outside)).await));
+        assert!(Config::load(Some(&path)).await.unwrap().paths.allowed_dirs.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_external_command_parsing() {
+        let config = Config::default();
+        let mut shell = Shell::new(config).await.unwrap();
+
+        // External commands go through the same tokenizer as builtins
+        let result = shell.execute_command("printf '%s|' \"hello world\" 'a  b' c").await.unwrap();
+        assert!(matches!(result, ShellObject::String(s) if s == "hello world|a  b|c|"));
+
+        let result = shell.execute_command("printf '%s' 'unterminated").await;
+        assert!(matches!(result, Err(AnvilError::Parse { .. })));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_command_suggestions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempdir().unwrap();
+        let tool = dir.path().join("anvil-frobnicate");
+        std::fs::write(&tool, "").unwrap();
+        std::fs::set_permissions(&tool, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let mut config = Config::default();
+        config.aliases.insert("deploy".to_string(), "echo deploy".to_string());
+        let mut shell = Shell::new(config).await.unwrap();
+        shell.env.insert("PATH".to_string(), dir.path().display().to_string());
+
+        assert_eq!(shell.suggest_command("anvil-frobnicat").as_deref(), Some("anvil-frobnicate"));
+        assert_eq!(shell.suggest_command("histroy").as_deref(), Some("history"));
+        assert_eq!(shell.suggest_command("delpoy").as_deref(), Some("deploy"));
+        assert_eq!(shell.suggest_command("gerp").as_deref(), Some("grep"));
+        assert_eq!(shell.suggest_command("ehco").as_deref(), Some("echo"));
+        assert_eq!(shell.suggest_command("qqqqqqqq"), None);
+
+        let Err(err) = shell.build_external_command("anvil-frobnicat --now") else { panic!("Expected an error") };
+        assert_eq!(err.to_string(), "Command error: Command not found: anvil-frobnicat (did you mean anvil-frobnicate?)");
+        let Err(err) = shell.build_external_command("qqqqqqqq") else { panic!("Expected an error") };
+        assert_eq!(err.to_string(), "Command error: Command not found: qqqqqqqq");
+    }
+
+    #[tokio::test]
+    async fn test_auto_cd() {
+        let dir = tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("project/src")).unwrap();
+        std::fs::write(dir.path().join("notes.txt"), "").unwrap();
+
+        let mut config = Config::default();
+        let mut shell = Shell::new(config.clone()).await.unwrap();
+        shell.current_dir = dir.path().join("project");
+
+        assert_eq!(shell.auto_cd_target("src"), Some(PathBuf::from("src")));
+        assert_eq!(shell.auto_cd_target("../project"), Some(dir.path().join("project/../project")));
+        let absolute = dir.path().display().to_string();
+        assert_eq!(shell.auto_cd_target(&absolute), Some(dir.path().to_path_buf()));
+
+        // Files, missing paths, arguments and programs on PATH aren't directories to enter
+        assert_eq!(shell.auto_cd_target("../notes.txt"), None);
+        assert_eq!(shell.auto_cd_target("missing"), None);
+        assert_eq!(shell.auto_cd_target("src src"), None);
+        std::fs::create_dir(dir.path().join("project/sh")).unwrap();
+        assert_eq!(shell.auto_cd_target("sh"), None);
+
+        // Script lines never change directory this way
+        shell.running_script = true;
+        assert_eq!(shell.auto_cd_target("src"), None);
+
+        config.shell.auto_cd = false;
+        let mut shell = Shell::new(config).await.unwrap();
+        shell.current_dir = dir.path().join("project");
+        assert_eq!(shell.auto_cd_target("src"), None);
+
+        // Mistyped cd targets suggest a close sibling
+        let err = shell.execute_command("cd scr").await.unwrap_err();
+        assert!(err.to_string().contains("did you mean src?"), "{}", err);
+        let err = shell.execute_command("cd ../porject").await.unwrap_err();
+        assert!(err.to_string().contains("did you mean ../project?"), "{}", err);
+        let err = shell.execute_command("cd zzzzzz").await.unwrap_err();
+        assert!(!err.to_string().contains("did you mean"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn test_glob_expansion_in_arguments() {
+        let dir = tempdir().unwrap();
+        for name in ["b.txt", "a.txt", "c.rs", ".hidden.txt"] {
+            std::fs::write(dir.path().join(name), "").unwrap();
+        }
+
+        let mut config = Config::default();
+        let mut shell = Shell::new(config.clone()).await.unwrap();
+        shell.current_dir = dir.path().to_path_buf();
+
+        let result = shell.execute_command("printf '%s|' *.txt {c,d}.rs").await.unwrap();
+        assert!(matches!(result, ShellObject::String(s) if s == "a.txt|b.txt|c.rs|d.rs|"));
+
+        // Quoted and escaped wildcards stay literal
+        let result = shell.execute_command("printf '%s|' '*.txt' \\*.rs").await.unwrap();
+        assert!(matches!(result, ShellObject::String(s) if s == "*.txt|*.rs|"));
+
+        // Unmatched globs pass through unless failglob is set
+        let result = shell.execute_command("printf '%s|' *.none").await.unwrap();
+        assert!(matches!(result, ShellObject::String(s) if s == "*.none|"));
+
+        config.shell.failglob = true;
+        let mut shell = Shell::new(config).await.unwrap();
+        shell.current_dir = dir.path().to_path_buf();
+        let result = shell.execute_command("printf '%s|' *.none").await;
+        assert!(matches!(result, Err(AnvilError::Command { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_variable_expansion_in_arguments() {
+        let config = Config::default();
+        let mut shell = Shell::new(config).await.unwrap();
+        shell.env.insert("GREETING".to_string(), "hi there".to_string());
+        let lookup = |name: &str| shell.env.get(name).cloned();
+
+        let parts = parse_command_line_with("echo \"say $GREETING\" '$GREETING' ${GREETING}!", &lookup).unwrap();
+        assert_eq!(parts, vec!["echo", "say hi there", "$GREETING", "hi there!"]);
+
+        // Escaped dollars and apostrophes inside double quotes stay literal
+        let parts = parse_command_line_with("echo \\$GREETING \"it's $NO_SUCH_ANVIL_VAR\"", &lookup).unwrap();
+        assert_eq!(parts, vec!["echo", "$GREETING", "it's "]);
+
+        let result = shell.execute_command("echo \"greeting: $GREETING\"").await.unwrap();
+        assert!(matches!(result, ShellObject::String(s) if s == "greeting: hi there"));
+        let result = shell.execute_command("sh -c \"echo $GREETING\"").await.unwrap();
+        assert!(matches!(result, ShellObject::String(s) if s == "hi there\n"));
+    }
 }
\ No newline at end of file
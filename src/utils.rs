@@ -1,93 +1,179 @@
 use crate::error::{AnvilError, AnvilResult};
 use crate::objects::ShellObject;
 use std::path::{Path, PathBuf};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use regex::Regex;
 
 /// Utility functions for the Anvil shell
 
-/// Expand shell patterns like glob, tilde, and environment variables
+/// Expand shell patterns like braces, glob, tilde, and environment variables
 pub fn expand_shell_pattern(pattern: &str) -> AnvilResult<Vec<PathBuf>> {
     let mut results = Vec::new();
     
-    // Handle tilde expansion
-    let expanded = expand_tilde(pattern);
-    
-    // Handle environment variable expansion
-    let expanded = expand_env_vars(&expanded)?;
-    
-    // Handle glob patterns
-    if expanded.contains('*') || expanded.contains('?') || expanded.contains('[') {
-        results.extend(expand_glob(&expanded)?);
-    } else {
-        results.push(PathBuf::from(expanded));
+    for pattern in expand_braces(pattern) {
+        // Handle tilde expansion
+        let expanded = expand_tilde(&pattern);
+        
+        // Handle environment variable expansion
+        let expanded = expand_env_vars(&expanded)?;
+        
+        // Handle glob patterns
+        if is_glob_pattern(&expanded) {
+            results.extend(expand_glob(&expanded)?);
+        } else {
+            results.push(PathBuf::from(expanded));
+        }
     }
     
     Ok(results)
 }
 
-/// Expand tilde (~) to home directory
-pub fn expand_tilde(path: &str) -> String {
-    if path.starts_with('~') {
-        if let Some(home) = dirs::home_dir() {
-            if path == "~" {
-                return home.to_string_lossy().to_string();
-            } else if path.starts_with("~/") {
-                return home.join(&path[2..]).to_string_lossy().to_string();
+/// Expand bash-style brace alternatives, e.g. `file.{rs,toml}`
+///
+/// Nested groups are supported (`a{1,2{x,y}}` yields `a1 a2x a2y`). Braces
+/// without a top-level comma are left as literal text.
+pub fn expand_braces(pattern: &str) -> Vec<String> {
+    let chars: Vec<char> = pattern.chars().collect();
+
+    for open in 0..chars.len() {
+        if chars[open] != '{' {
+            continue;
+        }
+
+        // Find the matching close brace and top-level commas
+        let mut depth = 0;
+        let mut commas = Vec::new();
+        let mut close = None;
+        for (i, &ch) in chars.iter().enumerate().skip(open) {
+            match ch {
+                '{' => depth += 1,
+                '}' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        close = Some(i);
+                        break;
+                    }
+                }
+                ',' if depth == 1 => commas.push(i),
+                _ => {}
             }
         }
+
+        let Some(close) = close else {
+            break;
+        };
+        if commas.is_empty() {
+            continue;
+        }
+
+        let prefix: String = chars[..open].iter().collect();
+        let suffix: String = chars[close + 1..].iter().collect();
+        let mut bounds = vec![open];
+        bounds.extend(commas);
+        bounds.push(close);
+
+        return bounds.windows(2)
+            .flat_map(|w| {
+                let alternative: String = chars[w[0] + 1..w[1]].iter().collect();
+                expand_braces(&format!("{}{}{}", prefix, alternative, suffix))
+            })
+            .collect();
     }
-    path.to_string()
+
+    vec![pattern.to_string()]
 }
 
-/// Expand environment variables in the form $VAR or ${VAR}
-pub fn expand_env_vars(text: &str) -> AnvilResult<String> {
-    let mut result = text.to_string();
-    
-    // Handle ${VAR} format
-    let brace_re = Regex::new(r"\$\{([^}]+)\}")
-        .map_err(|e| AnvilError::runtime(format!("Regex error: {}", e)))?;
-    
-    for captures in brace_re.captures_iter(text) {
-        if let Some(var_name) = captures.get(1) {
-            let var_value = std::env::var(var_name.as_str()).unwrap_or_default();
-            result = result.replace(&captures[0], &var_value);
-        }
+/// Expand tilde (~) to home directory, including `~user` forms
+pub fn expand_tilde(path: &str) -> String {
+    let Some(rest) = path.strip_prefix('~') else {
+        return path.to_string();
+    };
+
+    let (user, tail) = match rest.find('/') {
+        Some(idx) => (&rest[..idx], &rest[idx..]),
+        None => (rest, ""),
+    };
+
+    let home = if user.is_empty() {
+        dirs::home_dir()
+    } else {
+        user_home_dir(user)
+    };
+
+    match home {
+        Some(home) => format!("{}{}", home.to_string_lossy(), tail),
+        None => path.to_string(),
     }
-    
-    // Handle $VAR format (word boundaries)
-    let simple_re = Regex::new(r"\$([A-Za-z_][A-Za-z0-9_]*)")
-        .map_err(|e| AnvilError::runtime(format!("Regex error: {}", e)))?;
-    
-    for captures in simple_re.captures_iter(&result.clone()) {
-        if let Some(var_name) = captures.get(1) {
-            let var_value = std::env::var(var_name.as_str()).unwrap_or_default();
-            result = result.replace(&captures[0], &var_value);
+}
+
+/// Look up a user's home directory in the passwd database, through the
+/// system's name service so NSS sources like LDAP are included
+#[cfg(unix)]
+fn user_home_dir(user: &str) -> Option<PathBuf> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let name = std::ffi::CString::new(user).ok()?;
+    let mut buf: Vec<libc::c_char> = vec![0; 1024];
+    loop {
+        // SAFETY: passwd is plain data that getpwnam_r fills in
+        let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+        let mut result = std::ptr::null_mut();
+        // SAFETY: every pointer refers to a live local and `buf.len()` is
+        // the buffer's real size
+        let code = unsafe {
+            libc::getpwnam_r(name.as_ptr(), &mut passwd, buf.as_mut_ptr(), buf.len(), &mut result)
+        };
+        // The entry didn't fit, so retry with more room
+        if code == libc::ERANGE && buf.len() < 1 << 20 {
+            buf.resize(buf.len() * 2, 0);
+            continue;
         }
+        if code != 0 || result.is_null() || passwd.pw_dir.is_null() {
+            return None;
+        }
+        // SAFETY: on success pw_dir points to a NUL-terminated string in buf
+        let dir = unsafe { std::ffi::CStr::from_ptr(passwd.pw_dir) };
+        return Some(PathBuf::from(std::ffi::OsStr::from_bytes(dir.to_bytes())));
     }
-    
-    Ok(result)
+}
+
+#[cfg(not(unix))]
+fn user_home_dir(_user: &str) -> Option<PathBuf> {
+    None
+}
+
+/// Expand environment variables in the form $VAR or ${VAR}
+pub fn expand_env_vars(text: &str) -> AnvilResult<String> {
+    expand_vars_with(text, |name| std::env::var(name).ok())
+}
+
+/// Expand `$VAR` and `${VAR}` references using `lookup`; unknown
+/// variables expand to nothing. Substituted values aren't expanded again.
+pub fn expand_vars_with<F>(text: &str, lookup: F) -> AnvilResult<String>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let var_re = Regex::new(r"\$\{([^}]+)\}|\$([A-Za-z_][A-Za-z0-9_]*)")
+        .map_err(|e| AnvilError::runtime(format!("Regex error: {}", e)))?;
+
+    let expanded = var_re.replace_all(text, |captures: &regex::Captures| {
+        let name = captures.get(1).or_else(|| captures.get(2)).map_or("", |m| m.as_str());
+        lookup(name).unwrap_or_default()
+    });
+
+    Ok(expanded.into_owned())
+}
+
+/// Expand `$VAR`/`${VAR}` references and a leading tilde in a path
+/// given on the command line or in a config file
+pub fn expand_user_path(path: &Path) -> AnvilResult<PathBuf> {
+    let expanded = expand_env_vars(&path.to_string_lossy())?;
+    Ok(PathBuf::from(expand_tilde(&expanded)))
 }
 
 /// Simple glob pattern expansion
 pub fn expand_glob(pattern: &str) -> AnvilResult<Vec<PathBuf>> {
-    let mut results = Vec::new();
-    let path = Path::new(pattern);
-    
-    if let Some(parent) = path.parent() {
-        if let Some(filename) = path.file_name() {
-            let filename_str = filename.to_string_lossy();
-            
-            if let Ok(entries) = std::fs::read_dir(parent) {
-                for entry in entries.flatten() {
-                    let entry_name = entry.file_name().to_string_lossy().to_string();
-                    if glob_match(&filename_str, &entry_name) {
-                        results.push(entry.path());
-                    }
-                }
-            }
-        }
-    }
+    let mut results = glob_matches(pattern);
     
     if results.is_empty() {
         results.push(PathBuf::from(pattern));
@@ -96,6 +182,58 @@ pub fn expand_glob(pattern: &str) -> AnvilResult<Vec<PathBuf>> {
     Ok(results)
 }
 
+/// Whether text contains glob wildcards
+pub fn is_glob_pattern(text: &str) -> bool {
+    text.contains('*') || text.contains('?') || text.contains('[')
+}
+
+/// Paths matching a glob, sorted by name. Any component may have wildcards,
+/// as in `src/*/mod.rs`. A relative pattern is matched against the process
+/// working directory.
+pub fn glob_matches(pattern: &str) -> Vec<PathBuf> {
+    let components: Vec<_> = Path::new(pattern).components().collect();
+    let mut paths = vec![PathBuf::new()];
+
+    for (i, component) in components.iter().enumerate() {
+        let part = component.as_os_str().to_string_lossy();
+        if !matches!(component, std::path::Component::Normal(_)) || !is_glob_pattern(&part) {
+            for path in &mut paths {
+                path.push(component);
+            }
+            continue;
+        }
+
+        // Only directories can have later components matched inside them
+        let last = i + 1 == components.len();
+        paths = paths.iter()
+            .flat_map(|base| glob_dir(base, &part))
+            .filter(|path| last || path.is_dir())
+            .collect();
+    }
+
+    paths.retain(|path| path.exists());
+    paths.sort();
+    paths
+}
+
+/// Entries of `base` whose names match the glob `part`
+fn glob_dir(base: &Path, part: &str) -> Vec<PathBuf> {
+    let dir = if base.as_os_str().is_empty() { Path::new(".") } else { base };
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+
+    entries.flatten()
+        .map(|entry| entry.file_name())
+        .filter(|name| {
+            let name = name.to_string_lossy();
+            // Like bash, wildcards don't match hidden files
+            (!name.starts_with('.') || part.starts_with('.')) && glob_match(part, &name)
+        })
+        .map(|name| base.join(name))
+        .collect()
+}
+
 /// Simple glob pattern matching
 pub fn glob_match(pattern: &str, text: &str) -> bool {
     let pattern_chars: Vec<char> = pattern.chars().collect();
@@ -138,6 +276,48 @@ fn glob_match_recursive(pattern: &[char], text: &[char], p_idx: usize, t_idx: us
     }
 }
 
+/// Edit distance between two strings in characters, where swapping two
+/// neighbouring characters counts as one edit like an insertion, deletion
+/// or substitution does
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    // d[i][j] is the distance between the first i chars of a and j of b
+    let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in d[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            d[i][j] = (d[i - 1][j] + 1).min(d[i][j - 1] + 1).min(d[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+    d[a.len()][b.len()]
+}
+
+/// The candidate closest to `target` by edit distance, if any is close
+/// enough to be a plausible typo: about one edit per three characters, and
+/// always fewer edits than `target` has characters
+pub fn closest_match<'a, I>(target: &str, candidates: I) -> Option<&'a str>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let len = target.chars().count();
+    let limit = (len / 3).max(1).min(len.saturating_sub(1));
+    candidates.into_iter()
+        .map(|candidate| (edit_distance(target, candidate), candidate))
+        .filter(|(distance, _)| *distance <= limit)
+        .min()
+        .map(|(_, candidate)| candidate)
+}
+
 /// Format file size in human-readable format
 pub fn format_file_size(size: u64) -> String {
     const UNITS: &[&str] = &["B", "KB", "MB", "GB", "TB"];
@@ -176,61 +356,149 @@ pub fn format_duration(duration: std::time::Duration) -> String {
         format!("{}m {}s", minutes, seconds)
     } else if secs > 0 {
         format!("{}.{}s", secs, millis / 100)
-    } else {
+    } else if millis > 0 {
         format!("{}ms", millis)
+    } else if duration.as_micros() > 0 {
+        format!("{}µs", duration.as_micros())
+    } else {
+        format!("{}ns", duration.as_nanos())
+    }
+}
+
+/// Whether to color messages on stderr: it has to be a terminal, and
+/// `NO_COLOR` must be unset or empty
+pub fn color_enabled() -> bool {
+    let no_color = std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+    !no_color && std::io::IsTerminal::is_terminal(&std::io::stderr())
+}
+
+/// An error message behind an `error:` prefix, red and bold with `color`
+pub fn format_error(error: impl std::fmt::Display, color: bool) -> String {
+    if color {
+        format!("{} {}", nu_ansi_term::Color::Red.bold().paint("error:"), error)
+    } else {
+        format!("error: {}", error)
     }
 }
 
+/// Print an error to stderr, colored when the terminal allows it
+pub fn print_error(error: impl std::fmt::Display) {
+    eprintln!("{}", format_error(error, color_enabled()));
+}
+
+/// Parse a duration like `1.5`, `500ms`, `2s`, `3m` or `1h`; bare numbers are seconds
+pub fn parse_duration(text: &str) -> AnvilResult<std::time::Duration> {
+    let text = text.trim();
+    let split = text.find(|c: char| c.is_ascii_alphabetic()).unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+
+    let value: f64 = number.parse()
+        .map_err(|_| AnvilError::parse(format!("Invalid duration: '{}'", text)))?;
+
+    let seconds = match unit {
+        "" | "s" => value,
+        "ms" => value / 1000.0,
+        "m" => value * 60.0,
+        "h" => value * 3600.0,
+        _ => return Err(AnvilError::parse(format!("Invalid duration unit: '{}'", unit))),
+    };
+
+    std::time::Duration::try_from_secs_f64(seconds)
+        .map_err(|_| AnvilError::parse(format!("Invalid duration: '{}'", text)))
+}
+
+/// Looks up the value of a variable by name
+pub type VarLookup<'a> = &'a dyn Fn(&str) -> Option<String>;
+
+/// A word from a command line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandWord {
+    pub text: String,
+    /// Whether any part of the word was quoted or escaped, which suppresses
+    /// glob expansion
+    pub quoted: bool,
+}
+
 /// Parse command line arguments with basic quoting support
 pub fn parse_command_line(line: &str) -> AnvilResult<Vec<String>> {
+    Ok(tokenize_command_line(line, None)?.into_iter().map(|word| word.text).collect())
+}
+
+/// Parse command line arguments, expanding `$VAR` and `${VAR}` through
+/// `lookup` outside quotes and inside double quotes. Single-quoted text is
+/// taken literally, and expanded values are never split into more words.
+pub fn parse_command_line_with(line: &str, lookup: VarLookup) -> AnvilResult<Vec<String>> {
+    Ok(parse_command_words_with(line, lookup)?.into_iter().map(|word| word.text).collect())
+}
+
+/// Like [`parse_command_line_with`], but records which words were quoted
+pub fn parse_command_words_with(line: &str, lookup: VarLookup) -> AnvilResult<Vec<CommandWord>> {
+    tokenize_command_line(line, Some(lookup))
+}
+
+fn tokenize_command_line(line: &str, lookup: Option<VarLookup>) -> AnvilResult<Vec<CommandWord>> {
+    let expand = |text: &str| match lookup {
+        Some(lookup) => expand_vars_with(text, lookup),
+        None => Ok(text.to_string()),
+    };
+
     let mut args = Vec::new();
     let mut current_arg = String::new();
-    let mut chars = line.chars().peekable();
+    let mut quoted = false;
+    // Text since the last quote change, still to be expanded
+    let mut pending = String::new();
     let mut in_single_quote = false;
     let mut in_double_quote = false;
     let mut escape_next = false;
-    
-    while let Some(ch) = chars.next() {
+
+    for ch in line.chars() {
         if escape_next {
+            current_arg.push_str(&expand(&std::mem::take(&mut pending))?);
             current_arg.push(ch);
             escape_next = false;
+            quoted = true;
             continue;
         }
-        
+
         match ch {
             '\\' if !in_single_quote => {
                 escape_next = true;
             }
             '\'' if !in_double_quote => {
+                current_arg.push_str(&expand(&std::mem::take(&mut pending))?);
                 in_single_quote = !in_single_quote;
+                quoted = true;
             }
             '"' if !in_single_quote => {
+                current_arg.push_str(&expand(&std::mem::take(&mut pending))?);
                 in_double_quote = !in_double_quote;
+                quoted = true;
             }
-            ' ' | '\t' if !in_single_quote && !in_double_quote => {
+            c if c.is_whitespace() && !in_single_quote && !in_double_quote => {
+                current_arg.push_str(&expand(&std::mem::take(&mut pending))?);
                 if !current_arg.is_empty() {
-                    args.push(current_arg);
-                    current_arg = String::new();
-                }
-                // Skip multiple whitespace
-                while chars.peek() == Some(&' ') || chars.peek() == Some(&'\t') {
-                    chars.next();
+                    args.push(CommandWord { text: std::mem::take(&mut current_arg), quoted });
                 }
+                quoted = false;
             }
-            _ => {
+            _ if in_single_quote => {
                 current_arg.push(ch);
             }
+            _ => {
+                pending.push(ch);
+            }
         }
     }
-    
+
     if in_single_quote || in_double_quote {
         return Err(AnvilError::parse("Unterminated quote"));
     }
-    
+
+    current_arg.push_str(&expand(&pending)?);
     if !current_arg.is_empty() {
-        args.push(current_arg);
+        args.push(CommandWord { text: current_arg, quoted });
     }
-    
+
     Ok(args)
 }
 
@@ -290,7 +558,7 @@ impl<T: ToShellObject> ToShellObject for Vec<T> {
 
 impl<T: ToShellObject> ToShellObject for HashMap<String, T> {
     fn to_shell_object(self) -> ShellObject {
-        let objects: HashMap<String, ShellObject> = self.into_iter()
+        let objects: BTreeMap<String, ShellObject> = self.into_iter()
             .map(|(k, v)| (k, v.to_shell_object()))
             .collect();
         ShellObject::Map(objects)
@@ -364,7 +632,7 @@ impl FromShellObject for Vec<ShellObject> {
 impl FromShellObject for HashMap<String, ShellObject> {
     fn from_shell_object(obj: ShellObject) -> AnvilResult<Self> {
         match obj {
-            ShellObject::Map(map) => Ok(map),
+            ShellObject::Map(map) => Ok(map.into_iter().collect()),
             other => Err(AnvilError::type_error("map", other.type_name())),
         }
     }
@@ -392,6 +660,47 @@ impl PathUtils {
         true
     }
     
+    /// Check that `path` resolves to somewhere inside one of `allowed`,
+    /// following symlinks. An empty list allows every path.
+    pub fn check_allowed(path: &Path, allowed: &[PathBuf]) -> AnvilResult<()> {
+        if allowed.is_empty() {
+            return Ok(());
+        }
+
+        let resolved = Self::resolve_path(path);
+        if allowed.iter().any(|dir| resolved.starts_with(Self::resolve_path(dir))) {
+            Ok(())
+        } else {
+            Err(AnvilError::permission_denied(path.display().to_string()))
+        }
+    }
+
+    /// Make a path absolute, resolving symlinks in the part that exists
+    fn resolve_path(path: &Path) -> PathBuf {
+        let absolute = if path.is_absolute() {
+            path.to_path_buf()
+        } else {
+            std::env::current_dir().unwrap_or_default().join(path)
+        };
+
+        let mut existing = absolute.as_path();
+        let mut missing = Vec::new();
+        let canonical = loop {
+            if let Ok(canonical) = existing.canonicalize() {
+                break canonical;
+            }
+            match (existing.parent(), existing.file_name()) {
+                (Some(parent), Some(name)) => {
+                    missing.push(name);
+                    existing = parent;
+                }
+                _ => return Self::normalize_path(&absolute),
+            }
+        };
+
+        Self::normalize_path(&missing.iter().rev().fold(canonical, |path, name| path.join(name)))
+    }
+
     /// Normalize a path (resolve . and .. components)
     pub fn normalize_path(path: &Path) -> PathBuf {
         let mut components = Vec::new();
@@ -477,17 +786,27 @@ impl TextUtils {
         result
     }
     
-    /// Truncate text to specified length with ellipsis
+    /// Truncate text to specified length with ellipsis, counting characters
     pub fn truncate(text: &str, max_len: usize) -> String {
-        if text.len() <= max_len {
+        if text.chars().count() <= max_len {
             text.to_string()
         } else if max_len <= 3 {
             "...".to_string()
         } else {
-            format!("{}...", &text[..max_len - 3])
+            format!("{}...", text.chars().take(max_len - 3).collect::<String>())
         }
     }
     
+    /// Left-align text within specified width, counting characters
+    pub fn pad_right(text: &str, width: usize) -> String {
+        let len = text.chars().count();
+        if len >= width {
+            return text.to_string();
+        }
+
+        format!("{}{}", text, " ".repeat(width - len))
+    }
+
     /// Center text within specified width
     pub fn center(text: &str, width: usize) -> String {
         if text.len() >= width {
@@ -502,6 +821,135 @@ impl TextUtils {
     }
 }
 
+/// Find the git branch checked out for `dir` by reading `.git/HEAD` in it
+/// or the nearest parent. Detached heads are reported as a short hash.
+pub fn find_git_branch(dir: &Path) -> Option<String> {
+    let dot_git = dir.ancestors()
+        .map(|d| d.join(".git"))
+        .find(|p| p.exists())?;
+
+    // Worktrees and submodules use a `.git` file pointing at the real directory
+    let git_dir = if dot_git.is_file() {
+        let content = std::fs::read_to_string(&dot_git).ok()?;
+        let target = PathBuf::from(content.trim().strip_prefix("gitdir:")?.trim());
+        match dot_git.parent() {
+            Some(parent) if target.is_relative() => parent.join(target),
+            _ => target,
+        }
+    } else {
+        dot_git
+    };
+
+    let head = std::fs::read_to_string(git_dir.join("HEAD")).ok()?;
+    let head = head.trim();
+    match head.strip_prefix("ref:") {
+        Some(reference) => {
+            let reference = reference.trim();
+            Some(reference.strip_prefix("refs/heads/").unwrap_or(reference).to_string())
+        }
+        None => Some(head.chars().take(7).collect()),
+    }
+}
+
+/// Run a command to completion with its output captured, killing it if
+/// Ctrl+C is pressed first. Returns None when the command was interrupted.
+pub async fn output_interruptible(cmd: std::process::Command) -> std::io::Result<Option<std::process::Output>> {
+    output_cancellable(cmd, tokio::signal::ctrl_c()).await
+}
+
+/// Run a command to completion with its output captured, killing it if
+/// `cancel` completes first. Returns None when the command was cancelled.
+/// If `cancel` fails the command is simply waited for.
+pub async fn output_cancellable<F>(cmd: std::process::Command, cancel: F) -> std::io::Result<Option<std::process::Output>>
+where
+    F: std::future::Future<Output = std::io::Result<()>>,
+{
+    let mut cmd = tokio::process::Command::from(cmd);
+    cmd.stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .kill_on_drop(true);
+
+    let output = cmd.spawn()?.wait_with_output();
+    tokio::pin!(output);
+
+    tokio::select! {
+        result = &mut output => result.map(Some),
+        cancelled = cancel => match cancelled {
+            // Dropping the pending output future kills the child
+            Ok(()) => Ok(None),
+            // Without signal support, just wait for the command
+            Err(_) => output.await.map(Some),
+        },
+    }
+}
+
+/// A rotating indicator with the elapsed time, drawn on stderr while
+/// something slow runs and cleared when dropped
+pub struct Spinner {
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl Spinner {
+    const FRAMES: [char; 10] = ['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
+
+    /// Start drawing `label` on the current runtime. Nothing is drawn for
+    /// the first moment so quick work doesn't flicker.
+    pub fn start(label: &str) -> Self {
+        let label = label.to_string();
+        let started = std::time::Instant::now();
+        let task = tokio::spawn(async move {
+            tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+            for frame in Self::FRAMES.iter().cycle() {
+                eprint!("\r{} {} {:.1}s", frame, label, started.elapsed().as_secs_f64());
+                let _ = std::io::Write::flush(&mut std::io::stderr());
+                tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+            }
+        });
+        Self { task }
+    }
+}
+
+impl Drop for Spinner {
+    fn drop(&mut self) {
+        self.task.abort();
+        // Erase whatever frame was last drawn
+        eprint!("\r\x1b[2K");
+    }
+}
+
+/// System information lookups with fallbacks for unsupported platforms
+pub struct SystemUtils;
+
+impl SystemUtils {
+    /// Name of the user running the shell
+    pub fn username() -> String {
+        ["USER", "LOGNAME", "USERNAME"].iter()
+            .filter_map(|var| std::env::var(var).ok())
+            .find(|name| !name.is_empty())
+            .unwrap_or_else(|| "unknown".to_string())
+    }
+
+    /// Network name of this machine
+    pub fn hostname() -> String {
+        ["HOSTNAME", "COMPUTERNAME"].iter()
+            .filter_map(|var| std::env::var(var).ok())
+            .chain(
+                ["/proc/sys/kernel/hostname", "/etc/hostname"].iter()
+                    .filter_map(|path| std::fs::read_to_string(path).ok())
+            )
+            .map(|name| name.trim().to_string())
+            .find(|name| !name.is_empty())
+            .unwrap_or_else(|| "localhost".to_string())
+    }
+
+    /// Time since the system booted, if the platform exposes it
+    pub fn uptime() -> Option<std::time::Duration> {
+        let content = std::fs::read_to_string("/proc/uptime").ok()?;
+        let seconds: f64 = content.split_whitespace().next()?.parse().ok()?;
+        Some(std::time::Duration::from_secs_f64(seconds))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -515,6 +963,43 @@ mod tests {
         assert_eq!(expanded, "/absolute/path");
     }
 
+    #[test]
+    fn test_tilde_user_expansion() {
+        let home = dirs::home_dir().unwrap().to_string_lossy().to_string();
+        assert_eq!(expand_tilde("~"), home);
+        assert_eq!(expand_tilde("~/sub"), format!("{}/sub", home));
+
+        // Unknown users are left untouched
+        assert_eq!(expand_tilde("~no_such_anvil_user/docs"), "~no_such_anvil_user/docs");
+
+        #[cfg(target_os = "linux")]
+        assert_eq!(expand_tilde("~root/docs"), "/root/docs");
+    }
+
+    #[test]
+    fn test_expand_vars_with() {
+        let lookup = |name: &str| match name {
+            "A" => Some("1".to_string()),
+            "AB" => Some("$A".to_string()),
+            _ => None,
+        };
+
+        assert_eq!(expand_vars_with("$A-$AB-${A}x-$MISSING.", lookup).unwrap(), "1-$A-1x-.");
+    }
+
+    #[test]
+    fn test_user_path_expansion() {
+        let home = dirs::home_dir().unwrap();
+        assert_eq!(expand_user_path(Path::new("~/scripts/build.anvil")).unwrap(), home.join("scripts/build.anvil"));
+
+        let user_home = std::env::var("HOME").unwrap();
+        assert_eq!(
+            expand_user_path(Path::new("$HOME/build.anvil")).unwrap(),
+            PathBuf::from(format!("{}/build.anvil", user_home))
+        );
+        assert_eq!(expand_user_path(Path::new("/plain/path")).unwrap(), PathBuf::from("/plain/path"));
+    }
+
     #[test]
     fn test_glob_matching() {
         assert!(glob_match("*.txt", "file.txt"));
@@ -523,6 +1008,92 @@ mod tests {
         assert!(!glob_match("*.txt", "file.rs"));
     }
 
+    #[test]
+    fn test_glob_in_directories() {
+        let dir = tempfile::tempdir().unwrap();
+        for path in ["src/a/mod.rs", "src/b/mod.rs", "src/c/lib.rs", "src/.d/mod.rs", "docs/mod.rs"] {
+            std::fs::create_dir_all(dir.path().join(path).parent().unwrap()).unwrap();
+            std::fs::write(dir.path().join(path), "").unwrap();
+        }
+        let glob = |pattern: &str| glob_matches(&dir.path().join(pattern).to_string_lossy());
+
+        assert_eq!(glob("src/*/mod.rs"), vec![dir.path().join("src/a/mod.rs"), dir.path().join("src/b/mod.rs")]);
+        assert_eq!(glob("*/?/lib.rs"), vec![dir.path().join("src/c/lib.rs")]);
+        assert_eq!(glob("*/mod.rs"), vec![dir.path().join("docs/mod.rs")]);
+        assert!(glob("src/*/missing.rs").is_empty());
+        // A file can't stand in for a directory
+        assert!(glob("docs/*/x").is_empty());
+    }
+
+    #[test]
+    fn test_find_git_branch() {
+        let dir = tempfile::tempdir().unwrap();
+        let nested = dir.path().join("src/deep");
+        std::fs::create_dir_all(&nested).unwrap();
+        assert_eq!(find_git_branch(&nested), None);
+
+        std::fs::create_dir(dir.path().join(".git")).unwrap();
+        std::fs::write(dir.path().join(".git/HEAD"), "ref: refs/heads/feature/x\n").unwrap();
+        assert_eq!(find_git_branch(&nested).as_deref(), Some("feature/x"));
+
+        std::fs::write(dir.path().join(".git/HEAD"), "0123456789abcdef\n").unwrap();
+        assert_eq!(find_git_branch(dir.path()).as_deref(), Some("0123456"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_interrupt_kills_child() {
+        let start = std::time::Instant::now();
+        let mut cmd = std::process::Command::new("sleep");
+        cmd.arg("5");
+        let (interrupt, interrupted) = tokio::sync::oneshot::channel();
+        let run = tokio::spawn(output_cancellable(cmd, async {
+            interrupted.await.map_err(std::io::Error::other)
+        }));
+
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+        interrupt.send(()).unwrap();
+
+        assert!(run.await.unwrap().unwrap().is_none());
+        assert!(start.elapsed() < std::time::Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        use std::time::Duration;
+
+        assert_eq!(parse_duration("2").unwrap(), Duration::from_secs(2));
+        assert_eq!(parse_duration("0.25").unwrap(), Duration::from_millis(250));
+        assert_eq!(parse_duration("500ms").unwrap(), Duration::from_millis(500));
+        assert_eq!(parse_duration("1.5m").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_duration("1h").unwrap(), Duration::from_secs(3600));
+
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("-1").is_err());
+        assert!(parse_duration("5 days").is_err());
+    }
+
+    #[test]
+    fn test_format_duration() {
+        use std::time::Duration;
+
+        assert_eq!(format_duration(Duration::from_secs(90)), "1m 30s");
+        assert_eq!(format_duration(Duration::from_millis(1500)), "1.5s");
+        assert_eq!(format_duration(Duration::from_millis(12)), "12ms");
+        assert_eq!(format_duration(Duration::from_micros(340)), "340µs");
+        assert_eq!(format_duration(Duration::from_nanos(80)), "80ns");
+    }
+
+    #[test]
+    fn test_brace_expansion() {
+        assert_eq!(expand_braces("file.{rs,toml}"), vec!["file.rs", "file.toml"]);
+        assert_eq!(expand_braces("a{1,2{x,y}}"), vec!["a1", "a2x", "a2y"]);
+        assert_eq!(expand_braces("{a,b}{c,d}"), vec!["ac", "ad", "bc", "bd"]);
+        assert_eq!(expand_braces("{single}"), vec!["{single}"]);
+        assert_eq!(expand_braces("no braces"), vec!["no braces"]);
+        assert_eq!(expand_braces("{unclosed,"), vec!["{unclosed,"]);
+    }
+
     #[test]
     fn test_command_line_parsing() {
         let args = parse_command_line("echo \"hello world\" test").unwrap();
@@ -532,6 +1103,32 @@ mod tests {
         assert_eq!(args, vec!["echo", "single quotes", "test"]);
     }
 
+    #[test]
+    fn test_command_line_quote_context() {
+        let lookup = |name: &str| (name == "V").then(|| "a  b".to_string());
+
+        // Single quotes are literal, double quotes expand without splitting
+        let args = parse_command_line_with("x '$V \\n' \"$V\" $V", &lookup).unwrap();
+        assert_eq!(args, vec!["x", "$V \\n", "a  b", "a  b"]);
+
+        // Each quote type nests the other literally
+        let args = parse_command_line_with("x \"it's\" 'say \"$V\"'", &lookup).unwrap();
+        assert_eq!(args, vec!["x", "it's", "say \"$V\""]);
+
+        // Without a lookup nothing is expanded
+        assert_eq!(parse_command_line("x $V").unwrap(), vec!["x", "$V"]);
+        assert!(parse_command_line("x 'open").is_err());
+    }
+
+    #[test]
+    fn test_command_words_record_quoting() {
+        let lookup = |_: &str| Some("*".to_string());
+        let words = parse_command_words_with("ls *.rs \"*.rs\" \\*.rs '*'.rs $STAR", &lookup).unwrap();
+        let quoted: Vec<bool> = words.iter().map(|word| word.quoted).collect();
+        assert_eq!(quoted, vec![false, false, true, true, true, false]);
+        assert_eq!(words[5].text, "*");
+    }
+
     #[test]
     fn test_file_size_formatting() {
         assert_eq!(format_file_size(512), "512 B");
@@ -558,6 +1155,44 @@ mod tests {
         let text = "This is a very long string";
         let truncated = TextUtils::truncate(text, 10);
         assert_eq!(truncated, "This is...");
+        assert_eq!(TextUtils::truncate("héllo wörld", 8), "héllo...");
+    }
+
+    #[test]
+    fn test_map_display_order() {
+        let mut map = HashMap::new();
+        for (key, value) in [("zeta", 3), ("alpha", 1), ("mid", 2)] {
+            map.insert(key.to_string(), value);
+        }
+        let obj = map.to_shell_object();
+
+        assert_eq!(obj.to_display_string(), "{alpha: 1, mid: 2, zeta: 3}");
+        assert_eq!(obj.field_names(), vec!["alpha", "mid", "zeta"]);
+    }
+
+    #[test]
+    fn test_table_rendering() {
+        let row = |name: &str, size: i64| {
+            let mut map = BTreeMap::new();
+            map.insert("name".to_string(), ShellObject::String(name.to_string()));
+            map.insert("size".to_string(), ShellObject::Integer(size));
+            ShellObject::Map(map)
+        };
+
+        let listing = ShellObject::Array(vec![row("a.txt", 10), row("longer.rs", 2048)]);
+        let table = listing.to_table().unwrap();
+        assert_eq!(table, "\
+name      | size
+----------+-----
+a.txt     | 10
+longer.rs | 2048");
+
+        // Mismatched keys, non-maps and empty arrays aren't tables
+        let mut odd = BTreeMap::new();
+        odd.insert("other".to_string(), ShellObject::Unit);
+        assert!(ShellObject::Array(vec![row("a", 1), ShellObject::Map(odd)]).to_table().is_none());
+        assert!(ShellObject::Array(vec![ShellObject::Integer(1)]).to_table().is_none());
+        assert!(ShellObject::Array(vec![]).to_table().is_none());
     }
 
     #[test]
@@ -582,4 +1217,46 @@ mod tests {
         let value: bool = FromShellObject::from_shell_object(obj).unwrap();
         assert!(value);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_format_error() {
+        let error = AnvilError::runtime("boom");
+        assert_eq!(format_error(&error, false), "error: Runtime error: boom");
+
+        let colored = format_error(&error, true);
+        assert!(colored.starts_with("\x1b["));
+        assert!(colored.ends_with("error:\x1b[0m Runtime error: boom"));
+
+        // Color codes already in the message, e.g. from rustc, pass through
+        let compiler = AnvilError::compilation("\x1b[31merror[E0425]\x1b[0m");
+        assert!(format_error(&compiler, false).contains("\x1b[31merror[E0425]\x1b[0m"));
+    }
+
+    #[test]
+    fn test_closest_match() {
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("héllo", "hello"), 1);
+        assert_eq!(edit_distance("gti", "git"), 1);
+
+        let dirs = ["src", "target", "docs"];
+        assert_eq!(closest_match("scr", dirs), Some("src"));
+        assert_eq!(closest_match("targte", dirs), Some("target"));
+        assert_eq!(closest_match("benches", dirs), None);
+
+        // Short names only match short distances
+        assert_eq!(closest_match("zz", ["cd"]), None);
+        assert_eq!(closest_match("x", ["y"]), None);
+        assert_eq!(closest_match("cx", ["cd"]), Some("cd"));
+    }
+
+    #[tokio::test]
+    async fn test_spinner_stops_when_dropped() {
+        // Stopping before the first frame draws nothing and ends the task
+        let spinner = Spinner::start("Compiling");
+        let task = spinner.task.abort_handle();
+        drop(spinner);
+        tokio::task::yield_now().await;
+        assert!(task.is_finished());
+    }
+}